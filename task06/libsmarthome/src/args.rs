@@ -0,0 +1,294 @@
+//! Typed arguments of device commands.
+//!
+//! Arguments are declared by `ArgSpec`s of the command and validated by
+//! `DeviceCommand::validate` before they reach the physical device, so devices
+//! get arguments of declared types, within ranges and with defaults filled in.
+//! Arguments received as text are given as `name=value`.
+
+use std::fmt::Display;
+
+use crate::error::DeviceError;
+use crate::logical::*;
+
+/// ArgValue describes value of command argument
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl ArgValue {
+    pub fn arg_type(&self) -> ArgType {
+        match self {
+            ArgValue::Bool(_) => ArgType::Bool,
+            ArgValue::Int(_) => ArgType::Int,
+            ArgValue::Float(_) => ArgType::Float,
+            ArgValue::Str(_) => ArgType::Str,
+        }
+    }
+
+    /// Parses value of the given type from the text
+    pub fn parse(s: &str, arg_type: ArgType) -> Option<Self> {
+        match arg_type {
+            ArgType::Bool => s.parse().ok().map(ArgValue::Bool),
+            ArgType::Int => s.parse().ok().map(ArgValue::Int),
+            ArgType::Float => s.parse().ok().map(ArgValue::Float),
+            ArgType::Str => Some(ArgValue::Str(String::from(s))),
+        }
+    }
+}
+
+impl Display for ArgValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgValue::Bool(v) => write!(f, "{}", v),
+            ArgValue::Int(v) => write!(f, "{}", v),
+            ArgValue::Float(v) => write!(f, "{}", v),
+            ArgValue::Str(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl From<ArgDefault> for ArgValue {
+    fn from(value: ArgDefault) -> Self {
+        match value {
+            ArgDefault::Bool(v) => ArgValue::Bool(v),
+            ArgDefault::Int(v) => ArgValue::Int(v),
+            ArgDefault::Float(v) => ArgValue::Float(v),
+            ArgDefault::Str(v) => ArgValue::Str(String::from(v)),
+        }
+    }
+}
+
+/// CommandArgs describes named arguments of the command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandArgs {
+    values: Vec<(String, ArgValue)>,
+}
+
+impl<const N: usize> From<[(&str, ArgValue); N]> for CommandArgs {
+    fn from(values: [(&str, ArgValue); N]) -> Self {
+        let mut args = CommandArgs::new();
+        for (name, value) in values {
+            args.set(name, value);
+        }
+        args
+    }
+}
+
+impl CommandArgs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets value of the argument, replaces previous one
+    pub fn set(&mut self, name: &str, value: ArgValue) -> &mut Self {
+        match self.values.iter_mut().find(|(n, _)| n == name) {
+            Some(v) => v.1 = value,
+            None => self.values.push((String::from(name), value)),
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ArgValue> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Result<bool, DeviceError> {
+        match self.get(name) {
+            Some(ArgValue::Bool(v)) => Ok(*v),
+            _ => Err(missing(name)),
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Result<i64, DeviceError> {
+        match self.get(name) {
+            Some(ArgValue::Int(v)) => Ok(*v),
+            _ => Err(missing(name)),
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Result<f64, DeviceError> {
+        match self.get(name) {
+            Some(ArgValue::Float(v)) => Ok(*v),
+            _ => Err(missing(name)),
+        }
+    }
+
+    pub fn get_str(&self, name: &str) -> Result<String, DeviceError> {
+        match self.get(name) {
+            Some(ArgValue::Str(v)) => Ok(v.clone()),
+            _ => Err(missing(name)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Arguments as `name=value` texts
+    pub fn to_strings(&self) -> Vec<String> {
+        self.values
+            .iter()
+            .map(|(n, v)| format!("{}={}", n, v))
+            .collect()
+    }
+}
+
+fn missing(name: &str) -> DeviceError {
+    DeviceError::InvalidArgument(format!("argument {} of expected type is missing", name))
+}
+
+fn invalid(msg: String) -> DeviceError {
+    DeviceError::InvalidArgument(msg)
+}
+
+impl DeviceCommand {
+    /// Validates arguments against the declared ones: unknown arguments are
+    /// rejected, types and ranges are checked and defaults are filled in.
+    /// Integer is accepted as float argument.
+    pub fn validate(&self, args: &CommandArgs) -> Result<CommandArgs, DeviceError> {
+        if let Some((name, _)) = args
+            .values
+            .iter()
+            .find(|(n, _)| !self.args.iter().any(|a| a.name == n))
+        {
+            return Err(invalid(format!(
+                "unknown argument {} of command {}",
+                name, self.name
+            )));
+        }
+        let mut valid = CommandArgs::new();
+        for spec in self.args {
+            let value = match (args.get(spec.name), spec.default) {
+                (Some(v), _) => v.clone(),
+                (None, Some(v)) => ArgValue::from(v),
+                (None, None) => {
+                    return Err(invalid(format!("argument {} is required", spec.name)));
+                }
+            };
+            let value = match (value, spec.arg_type) {
+                (ArgValue::Int(v), ArgType::Float) => ArgValue::Float(v as f64),
+                (v, t) if v.arg_type() == t => v,
+                _ => {
+                    return Err(invalid(format!(
+                        "argument {} must be {:?}",
+                        spec.name, spec.arg_type
+                    )))
+                }
+            };
+            let number = match value {
+                ArgValue::Int(v) => Some(v as f64),
+                ArgValue::Float(v) => Some(v),
+                _ => None,
+            };
+            if let Some(v) = number {
+                if spec.min.is_some_and(|min| v < min)
+                    || spec.max.is_some_and(|max| v > max)
+                    || v.is_nan()
+                {
+                    return Err(invalid(format!(
+                        "argument {} must be in range [{}, {}]",
+                        spec.name,
+                        spec.min.unwrap_or(f64::NEG_INFINITY),
+                        spec.max.unwrap_or(f64::INFINITY)
+                    )));
+                }
+            }
+            valid.set(spec.name, value);
+        }
+        Ok(valid)
+    }
+
+    /// Parses `name=value` texts and validates them
+    pub fn parse_args(&self, args: &[String]) -> Result<CommandArgs, DeviceError> {
+        let mut parsed = CommandArgs::new();
+        for arg in args {
+            let Some((name, value)) = arg.split_once('=') else {
+                return Err(invalid(format!("argument {:?} must be name=value", arg)));
+            };
+            let Some(spec) = self.args.iter().find(|a| a.name == name) else {
+                return Err(invalid(format!(
+                    "unknown argument {} of command {}",
+                    name, self.name
+                )));
+            };
+            let Some(value) = ArgValue::parse(value, spec.arg_type) else {
+                return Err(invalid(format!(
+                    "argument {} must be {:?}, got {:?}",
+                    name, spec.arg_type, value
+                )));
+            };
+            parsed.set(name, value);
+        }
+        self.validate(&parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CMD_SET_BRIGHTNESS: DeviceCommand = DeviceCommand {
+        id: 100,
+        name: "set_brightness",
+        description: "sets brightness of the lamp",
+        args: &[
+            ArgSpec::new("percent", ArgType::Int).range(0.0, 100.0),
+            ArgSpec::new("fade", ArgType::Float)
+                .range(0.0, 10.0)
+                .with_default(ArgDefault::Float(0.5)),
+            ArgSpec::new("label", ArgType::Str).with_default(ArgDefault::Str("")),
+        ],
+        result: ResultType::None,
+    };
+
+    #[test]
+    fn test_validate() {
+        let args = CMD_SET_BRIGHTNESS
+            .validate(&CommandArgs::from([
+                ("fade", ArgValue::Int(2)),
+                ("percent", ArgValue::Int(40)),
+            ]))
+            .unwrap();
+        assert_eq!(args.get_int("percent").unwrap(), 40);
+        assert_eq!(args.get_float("fade").unwrap(), 2.0);
+        assert_eq!(args.get_str("label").unwrap(), "");
+        assert_eq!(args.to_strings(), vec!["percent=40", "fade=2", "label="]);
+        assert!(args.get_bool("percent").is_err());
+
+        for invalid in [
+            CommandArgs::new(),
+            CommandArgs::from([("percent", ArgValue::Int(101))]),
+            CommandArgs::from([("percent", ArgValue::Float(50.0))]),
+            CommandArgs::from([("percent", ArgValue::Int(1)), ("speed", ArgValue::Int(1))]),
+        ] {
+            assert!(matches!(
+                CMD_SET_BRIGHTNESS.validate(&invalid),
+                Err(DeviceError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = CMD_SET_BRIGHTNESS
+            .parse_args(&[String::from("percent=75"), String::from("label=a=b")])
+            .unwrap();
+        assert_eq!(args.get_int("percent").unwrap(), 75);
+        assert_eq!(args.get_float("fade").unwrap(), 0.5);
+        assert_eq!(args.get_str("label").unwrap(), "a=b");
+        assert_eq!(
+            CMD_SET_BRIGHTNESS.parse_args(&args.to_strings()).unwrap(),
+            args
+        );
+
+        for invalid in ["percent", "percent=high", "percent=-1", "speed=1"] {
+            assert!(CMD_SET_BRIGHTNESS
+                .parse_args(&[String::from(invalid)])
+                .is_err());
+        }
+    }
+}
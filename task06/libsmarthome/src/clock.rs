@@ -0,0 +1,153 @@
+//! Clock of the automations. Time of day is UTC.
+//!
+//! Automations take the clock as a parameter, tests use `SimulatedClock` to
+//! move time forward instead of waiting.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds in a day
+pub const DAY: u64 = 24 * 60 * 60;
+
+/// Clock defines source of the current time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// Clock of the system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock which is moved by hand, clones share the time
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self::new(UNIX_EPOCH)
+    }
+}
+
+impl SimulatedClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+
+    pub fn advance(&self, d: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(PoisonError::into_inner);
+        *now += d;
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Seconds since the unix epoch, zero for earlier times
+pub fn unix_seconds(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Seconds since midnight
+pub fn time_of_day(t: SystemTime) -> u64 {
+    unix_seconds(t) % DAY
+}
+
+/// Parses time of day `HH:MM` or `HH:MM:SS` as seconds since midnight
+pub fn parse_time_of_day(s: &str) -> Option<u64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+    let limits = [24, 60, 60];
+    let mut seconds = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let v: u64 = part.parse().ok().filter(|v| *v < limits[i])?;
+        seconds += v * [3600, 60, 1][i];
+    }
+    Some(seconds)
+}
+
+/// The latest time at or before `now` with the given time of day
+pub fn latest_time_of_day(now: SystemTime, seconds: u64) -> SystemTime {
+    let midnight = now - Duration::from_secs(time_of_day(now));
+    let t = midnight + Duration::from_secs(seconds);
+    match t > now {
+        true => t - Duration::from_secs(DAY),
+        false => t,
+    }
+}
+
+/// Days since the unix epoch
+pub fn unix_days(t: SystemTime) -> u64 {
+    unix_seconds(t) / DAY
+}
+
+/// Date `(year, month, day)` of the day since the unix epoch, months and days start from 1
+pub fn civil_date(days: u64) -> (u64, u32, u32) {
+    // days to the civil date in the proleptic gregorian calendar, eras start on March 1
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+/// Day of week of the day since the unix epoch, 0 is Sunday
+pub fn weekday(days: u64) -> u32 {
+    ((days + 4) % 7) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_of_day() {
+        assert_eq!(parse_time_of_day("07:30"), Some(27000));
+        assert_eq!(parse_time_of_day("23:59:59"), Some(DAY - 1));
+        for invalid in ["7", "24:00", "12:60", "1:2:3:4", "aa:bb"] {
+            assert_eq!(parse_time_of_day(invalid), None);
+        }
+
+        let clock = SimulatedClock::default();
+        clock.advance(Duration::from_secs(DAY + 3600));
+        assert_eq!(time_of_day(clock.now()), 3600);
+        assert_eq!(
+            latest_time_of_day(clock.now(), 1800),
+            UNIX_EPOCH + Duration::from_secs(DAY + 1800)
+        );
+        assert_eq!(
+            latest_time_of_day(clock.now(), 7200),
+            UNIX_EPOCH + Duration::from_secs(7200)
+        );
+
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(weekday(0), 4);
+        // 2024-02-29, Thursday
+        assert_eq!(civil_date(19782), (2024, 2, 29));
+        assert_eq!(weekday(19782), 4);
+        assert_eq!(civil_date(19783), (2024, 3, 1));
+    }
+}
@@ -1,13 +1,209 @@
+use std::collections::HashMap;
+
+use crate::error::RegistryError;
 use crate::logical::*;
 
 /// List of common device commands
-pub const CMD_STATUS: DeviceCommand = DeviceCommand(1, "status", "returns device status");
-pub const CMD_SELF_TEST: DeviceCommand =
-    DeviceCommand(2, "selftest", "performs self test of the device");
-pub const CMD_GET_POWER_CONSUMPTION: DeviceCommand = DeviceCommand(
-    3,
-    "get_power_consumption",
-    "returns instance consumption of the device",
-);
-pub const CMD_GET_TEMPERATURE: DeviceCommand =
-    DeviceCommand(3, "get_temperature", "returns temperature");
+pub const CMD_STATUS: DeviceCommand = DeviceCommand {
+    id: 1,
+    name: "status",
+    description: "returns device status",
+    args: &[],
+    result: ResultType::Str,
+};
+pub const CMD_SELF_TEST: DeviceCommand = DeviceCommand {
+    id: 2,
+    name: "selftest",
+    description: "performs self test of the device",
+    args: &[],
+    result: ResultType::Str,
+};
+pub const CMD_GET_POWER_CONSUMPTION: DeviceCommand = DeviceCommand {
+    id: 3,
+    name: "get_power_consumption",
+    description: "returns instance consumption of the device",
+    args: &[],
+    result: ResultType::Float32,
+};
+pub const CMD_GET_TEMPERATURE: DeviceCommand = DeviceCommand {
+    id: 4,
+    name: "get_temperature",
+    description: "returns temperature",
+    args: &[],
+    result: ResultType::Float32,
+};
+pub const CMD_SET_THRESHOLD: DeviceCommand = DeviceCommand {
+    id: 5,
+    name: "set_threshold",
+    description: "sets temperature threshold of the sensor, C",
+    args: &[ArgSpec::new("celsius", ArgType::Float).range(-40.0, 125.0)],
+    result: ResultType::None,
+};
+
+/// Commands known to every registry
+pub const BUILTIN_COMMANDS: &[DeviceCommand] = &[
+    CMD_STATUS,
+    CMD_SELF_TEST,
+    CMD_GET_POWER_CONSUMPTION,
+    CMD_GET_TEMPERATURE,
+    CMD_SET_THRESHOLD,
+];
+
+// collision of the builtin commands fails compilation
+const _: () = assert_unique(BUILTIN_COMMANDS);
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Panics if ids or names of the commands are not unique.
+/// Use it in constant context to check command lists at compile time:
+/// `const _: () = assert_unique(MY_COMMANDS);`
+pub const fn assert_unique(cmds: &[DeviceCommand]) {
+    let mut i = 0;
+    while i < cmds.len() {
+        let mut j = i + 1;
+        while j < cmds.len() {
+            if cmds[i].id == cmds[j].id {
+                panic!("duplicate command id");
+            }
+            if str_eq(cmds[i].name, cmds[j].name) {
+                panic!("duplicate command name");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Registry of commands with unique ids and names
+#[derive(Debug, Clone)]
+pub struct CommandRegistry {
+    by_id: HashMap<u64, DeviceCommand>,
+    by_name: HashMap<&'static str, u64>,
+}
+
+impl Default for CommandRegistry {
+    /// Registry of the builtin commands
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        for cmd in BUILTIN_COMMANDS {
+            // builtin commands are checked at compile time
+            registry.register(*cmd).unwrap();
+        }
+        registry
+    }
+}
+
+impl CommandRegistry {
+    /// Registry of the builtin commands
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry without commands
+    pub fn empty() -> Self {
+        Self {
+            by_id: HashMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    /// Registers command, fails if its id or name is already used by another command.
+    /// Registration of the same command again is allowed.
+    pub fn register(&mut self, cmd: DeviceCommand) -> Result<(), RegistryError> {
+        if let Some(existing) = self.by_id.get(&cmd.id) {
+            return match *existing == cmd {
+                true => Ok(()),
+                false => Err(RegistryError::DuplicateId(cmd.id, existing.name)),
+            };
+        }
+        if self.by_name.contains_key(cmd.name) {
+            return Err(RegistryError::DuplicateName(cmd.name));
+        }
+        self.by_id.insert(cmd.id, cmd);
+        self.by_name.insert(cmd.name, cmd.id);
+        Ok(())
+    }
+
+    /// Get command by id
+    pub fn get(&self, id: u64) -> Option<DeviceCommand> {
+        self.by_id.get(&id).copied()
+    }
+
+    /// Get command by name, e.g. received as text
+    pub fn find(&self, name: &str) -> Option<DeviceCommand> {
+        self.by_name.get(name).and_then(|id| self.get(*id))
+    }
+
+    /// Registered commands sorted by id
+    pub fn commands(&self) -> Vec<DeviceCommand> {
+        let mut cmds: Vec<DeviceCommand> = self.by_id.values().copied().collect();
+        cmds.sort_by_key(|c| c.id);
+        cmds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CMD_SET_BRIGHTNESS: DeviceCommand = DeviceCommand {
+        id: 100,
+        name: "set_brightness",
+        description: "sets brightness of the lamp",
+        args: &[ArgSpec::new("percent", ArgType::Int).range(0.0, 100.0)],
+        result: ResultType::None,
+    };
+
+    #[test]
+    fn test_registry() {
+        let mut registry = CommandRegistry::new();
+        assert_eq!(registry.commands(), BUILTIN_COMMANDS);
+        assert_eq!(registry.find("get_temperature"), Some(CMD_GET_TEMPERATURE));
+        assert_eq!(registry.get(3), Some(CMD_GET_POWER_CONSUMPTION));
+        assert_eq!(registry.find("unknown"), None);
+
+        registry.register(CMD_SET_BRIGHTNESS).unwrap();
+        registry.register(CMD_SET_BRIGHTNESS).unwrap();
+        assert_eq!(registry.find("set_brightness"), Some(CMD_SET_BRIGHTNESS));
+        assert!(matches!(
+            registry.register(DeviceCommand {
+                name: "other",
+                ..CMD_SET_BRIGHTNESS
+            }),
+            Err(RegistryError::DuplicateId(100, "set_brightness"))
+        ));
+        assert!(matches!(
+            registry.register(DeviceCommand {
+                id: 101,
+                ..CMD_STATUS
+            }),
+            Err(RegistryError::DuplicateName("status"))
+        ));
+        assert_eq!(CommandRegistry::empty().commands(), vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate command id")]
+    fn test_assert_unique() {
+        assert_unique(&[
+            CMD_STATUS,
+            DeviceCommand {
+                name: "other",
+                ..CMD_STATUS
+            },
+        ]);
+    }
+}
@@ -19,6 +19,10 @@ pub enum DeviceError {
     CommandFailed(String),
     /// Device is not found in expected place
     DeviceIsMissing(xid::Id),
+    /// Room is not found in the home
+    RoomIsMissing(xid::Id),
+    /// Command argument is missing or invalid, value includes details
+    InvalidArgument(String),
 }
 
 impl Display for DeviceError {
@@ -34,6 +38,8 @@ impl Display for DeviceError {
             Self::UnexpectedResultFormat => write!(f, "unexpected result from the command"),
             Self::CommandFailed(v) => write!(f, "command failed with error {}", v),
             Self::DeviceIsMissing(id) => write!(f, "device {} is missing", id),
+            Self::RoomIsMissing(id) => write!(f, "room {} is missing", id),
+            Self::InvalidArgument(v) => write!(f, "invalid argument: {}", v),
         }
     }
 }
@@ -53,6 +59,8 @@ impl Error for DeviceError {
             Self::UnexpectedResultFormat => "unexpected result from the command",
             Self::CommandFailed(_) => "command failed",
             Self::DeviceIsMissing(_) => "device is missing",
+            Self::RoomIsMissing(_) => "room is missing",
+            Self::InvalidArgument(_) => "invalid argument",
         }
     }
 
@@ -60,3 +68,151 @@ impl Error for DeviceError {
         self.source()
     }
 }
+
+/// Errors of saving and loading the home topology
+#[derive(Debug)]
+pub enum TopologyError {
+    /// File can't be read or written
+    Io(std::io::Error),
+    /// Topology can't be parsed or serialized, value includes details
+    Format(String),
+    /// Room or device id is not a valid xid
+    InvalidId(String),
+    /// Room or device id is used more than once
+    DuplicateId(xid::Id),
+    /// Loaded devices can't be bound to physical ones
+    Bind(DeviceError),
+}
+
+impl Display for TopologyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Self::Io(v) => write!(f, "topology file error {}", v),
+            Self::Format(v) => write!(f, "invalid topology format: {}", v),
+            Self::InvalidId(v) => write!(f, "invalid id {:?}", v),
+            Self::DuplicateId(id) => write!(f, "id {} is used more than once", id),
+            Self::Bind(v) => write!(f, "binding of devices failed: {}", v),
+        }
+    }
+}
+
+impl Error for TopologyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            Self::Io(v) => Some(v),
+            Self::Bind(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TopologyError {
+    fn from(v: std::io::Error) -> Self {
+        Self::Io(v)
+    }
+}
+
+/// Errors of the client of the home server
+#[derive(Debug, Clone)]
+pub enum RemoteError {
+    /// Server can't be reached or connection is broken
+    Connection(String),
+    /// Server executed request with error
+    Server(libprotocol::commands::Status, String),
+    /// Response doesn't match the request
+    UnexpectedResponse,
+}
+
+impl Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Self::Connection(v) => write!(f, "connection error {}", v),
+            Self::Server(status, v) => write!(f, "server error {:?}: {}", status, v),
+            Self::UnexpectedResponse => write!(f, "unexpected response"),
+        }
+    }
+}
+
+impl Error for RemoteError {}
+
+/// Errors of the command registration
+#[derive(Debug, Clone)]
+pub enum RegistryError {
+    /// Id is used by the command with given name
+    DuplicateId(u64, &'static str),
+    /// Name is used by another command
+    DuplicateName(&'static str),
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Self::DuplicateId(id, name) => write!(f, "command id {} is used by {}", id, name),
+            Self::DuplicateName(name) => write!(f, "command name {} is already used", name),
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+/// Errors of the automation rules
+#[derive(Debug)]
+pub enum RuleError {
+    /// File can't be read
+    Io(std::io::Error),
+    /// Rules can't be parsed, value includes details
+    Format(String),
+    /// Rule with the given name is invalid, value includes details
+    Invalid(String, String),
+}
+
+impl Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Self::Io(v) => write!(f, "rules file error {}", v),
+            Self::Format(v) => write!(f, "invalid rules format: {}", v),
+            Self::Invalid(name, v) => write!(f, "invalid rule {:?}: {}", name, v),
+        }
+    }
+}
+
+impl Error for RuleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self {
+            Self::Io(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RuleError {
+    fn from(v: std::io::Error) -> Self {
+        Self::Io(v)
+    }
+}
+
+/// Errors of the scheduler
+#[derive(Debug, Clone)]
+pub enum ScheduleError {
+    /// Cron expression can't be parsed or never matches, value includes details
+    InvalidCron(String),
+    /// Sun events need location of the home, see `Scheduler::set_location`
+    NoLocation,
+    /// Action of the job is invalid, value includes details
+    InvalidAction(String),
+    /// Job with the name is already scheduled, names identify jobs across restarts
+    DuplicateName(String),
+}
+
+impl Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Self::InvalidCron(v) => write!(f, "invalid cron expression: {}", v),
+            Self::NoLocation => write!(f, "location is required by sun events"),
+            Self::InvalidAction(v) => write!(f, "invalid action: {}", v),
+            Self::DuplicateName(v) => write!(f, "job {:?} already exists", v),
+        }
+    }
+}
+
+impl Error for ScheduleError {}
@@ -0,0 +1,360 @@
+//! Events of the home: device and topology changes.
+//!
+//! Room added to the home publishes its events to the bus of the home, so
+//! subscribers of `Home::events` observe all rooms. Events are delivered to
+//! handlers synchronously by the publishing thread, or sent to channels.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::device_ref::DeviceRef;
+use crate::logical::*;
+
+/// HomeEvent describes change of the home
+#[derive(Debug, Clone, PartialEq)]
+pub enum HomeEvent {
+    /// Power state of the device is changed to the given one
+    PowerChanged(DeviceRef, PowerState),
+    DeviceAdded(DeviceRef),
+    DeviceRemoved(DeviceRef),
+    /// Physical device is bound to the logical one
+    Bound(DeviceRef),
+    /// Physical device is unbound from the logical one
+    Unbound(DeviceRef),
+    /// Room id and label
+    RoomAdded(xid::Id, String),
+    /// Room id and label
+    RoomRemoved(xid::Id, String),
+    /// Command is successfully executed by the device, value is command result
+    CommandExecuted(DeviceRef, DeviceCommand, Option<CommandResult>),
+}
+
+impl HomeEvent {
+    /// Device of the event, None for room events
+    pub fn device(&self) -> Option<&DeviceRef> {
+        match self {
+            HomeEvent::PowerChanged(d, _)
+            | HomeEvent::DeviceAdded(d)
+            | HomeEvent::DeviceRemoved(d)
+            | HomeEvent::Bound(d)
+            | HomeEvent::Unbound(d)
+            | HomeEvent::CommandExecuted(d, _, _) => Some(d),
+            HomeEvent::RoomAdded(_, _) | HomeEvent::RoomRemoved(_, _) => None,
+        }
+    }
+
+    pub fn room_id(&self) -> xid::Id {
+        match self {
+            HomeEvent::RoomAdded(id, _) | HomeEvent::RoomRemoved(id, _) => *id,
+            // every other event has device
+            _ => *self.device().unwrap().room_id(),
+        }
+    }
+}
+
+/// Filter of events, empty filter accepts all events.
+/// Filter by device or class rejects room events.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    room: Option<xid::Id>,
+    device: Option<xid::Id>,
+    class: Option<String>,
+}
+
+impl EventFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept events of the room and its devices
+    pub fn set_room(&mut self, id: xid::Id) -> &mut Self {
+        self.room = Some(id);
+        self
+    }
+
+    /// Accept events of the device
+    pub fn set_device(&mut self, id: xid::Id) -> &mut Self {
+        self.device = Some(id);
+        self
+    }
+
+    /// Accept events of devices of the class
+    pub fn set_class(&mut self, class: String) -> &mut Self {
+        self.class = Some(class);
+        self
+    }
+
+    pub fn matches(&self, event: &HomeEvent) -> bool {
+        if self.room.is_some_and(|id| id != event.room_id()) {
+            return false;
+        }
+        match event.device() {
+            Some(d) => {
+                self.device.is_none_or(|id| id == *d.device_id())
+                    && self.class.as_ref().is_none_or(|c| c == d.device_class())
+            }
+            None => self.device.is_none() && self.class.is_none(),
+        }
+    }
+}
+
+/// Identifier of the subscription, see `EventBus::unsubscribe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionId(u64);
+
+type Handler = Box<dyn Fn(&HomeEvent) + Send + Sync>;
+
+enum Delivery {
+    Sync(Handler),
+    Channel(Sender<HomeEvent>),
+}
+
+struct Subscription {
+    id: SubscriptionId,
+    filter: EventFilter,
+    delivery: Delivery,
+}
+
+/// Cloneable bus of home events, clones share subscribers
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscriptions: Arc<RwLock<Vec<Arc<Subscription>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes handler which is called by the thread publishing the event.
+    /// Handler may publish events and change subscriptions.
+    pub fn subscribe<H>(&self, filter: &EventFilter, handler: H) -> SubscriptionId
+    where
+        H: Fn(&HomeEvent) + Send + Sync + 'static,
+    {
+        self.add(filter, Delivery::Sync(Box::new(handler)))
+    }
+
+    /// Subscribes channel, subscription is removed when the receiver is dropped
+    pub fn subscribe_channel(&self, filter: &EventFilter) -> Receiver<HomeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.add(filter, Delivery::Channel(sender));
+        receiver
+    }
+
+    /// Returns false if there is no such subscription
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscriptions = self.write();
+        let count = subscriptions.len();
+        subscriptions.retain(|s| s.id != id);
+        subscriptions.len() != count
+    }
+
+    /// Delivers event to the matching subscribers
+    pub fn publish(&self, event: HomeEvent) {
+        // subscriptions aren't locked while handlers run
+        let subscriptions: Vec<Arc<Subscription>> = self
+            .subscriptions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .filter(|s| s.filter.matches(&event))
+            .cloned()
+            .collect();
+        let mut disconnected = vec![];
+        for s in subscriptions {
+            match &s.delivery {
+                Delivery::Sync(handler) => handler(&event),
+                Delivery::Channel(sender) => {
+                    if sender.send(event.clone()).is_err() {
+                        disconnected.push(s.id);
+                    }
+                }
+            }
+        }
+        if !disconnected.is_empty() {
+            self.write().retain(|s| !disconnected.contains(&s.id));
+        }
+    }
+
+    fn add(&self, filter: &EventFilter, delivery: Delivery) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.write().push(Arc::new(Subscription {
+            id,
+            filter: filter.clone(),
+            delivery,
+        }));
+        id
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Vec<Arc<Subscription>>> {
+        self.subscriptions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Barrier, Mutex};
+    use std::thread;
+
+    use super::*;
+    use crate::args::CommandArgs;
+    use crate::commands::CMD_STATUS;
+    use crate::factory::SimpleClassFactory;
+    use crate::home::HomeBuilder;
+    use crate::logical_device::Device;
+    use crate::room::{Binder, Room, RoomBuilder};
+    use crate::testing::TestDevice;
+
+    #[test]
+    fn test_filter() {
+        let (room, device) = (xid::new(), xid::new());
+        let socket = DeviceRef::new(room, String::from("Hall"), device, String::from("socket"));
+        let added = HomeEvent::DeviceAdded(socket.clone());
+        let power = HomeEvent::PowerChanged(socket, PowerState::ON);
+        let room_added = HomeEvent::RoomAdded(room, String::from("Hall"));
+
+        assert!(EventFilter::new().matches(&room_added));
+        assert!(EventFilter::new().set_room(room).matches(&power));
+        assert!(!EventFilter::new().set_room(xid::new()).matches(&power));
+        assert!(EventFilter::new().set_device(device).matches(&added));
+        assert!(!EventFilter::new().set_device(device).matches(&room_added));
+        let mut sockets = EventFilter::new();
+        sockets.set_class(String::from("socket")).set_room(room);
+        assert!(sockets.matches(&added) && sockets.matches(&power));
+        assert!(!EventFilter::new()
+            .set_class(String::from("tsensor"))
+            .matches(&added));
+    }
+
+    #[test]
+    fn test_home_events() {
+        let socket = Device::new(String::from("socket"));
+        let sensor = Device::new(String::from("tsensor"));
+        let mut home = HomeBuilder::new()
+            .add_room(Box::new(
+                RoomBuilder::new()
+                    .set_label(String::from("Hall"))
+                    .add_device(&socket)
+                    .build(),
+            ))
+            .build();
+
+        let all = home.events().subscribe_channel(&EventFilter::new());
+        let sockets = home
+            .events()
+            .subscribe_channel(EventFilter::new().set_class(String::from("socket")));
+        let count = Arc::new(Mutex::new(0));
+        let counter = count.clone();
+        let sync = home
+            .events()
+            .subscribe(EventFilter::new().set_device(socket.get_id()), move |_| {
+                *counter.lock().unwrap() += 1
+            });
+
+        let kitchen = home.add_room(Box::new(
+            RoomBuilder::new()
+                .set_label(String::from("Kitchen"))
+                .build(),
+        ));
+        home.get_room_mut(&kitchen)
+            .unwrap()
+            .add_device(sensor.clone());
+        home.bind_physical_devices(&mut Binder::new(SimpleClassFactory {}))
+            .unwrap();
+        home.switch_power(PowerState::ON).unwrap();
+        home.switch_power(PowerState::ON).unwrap(); // not changed
+        let hall = home.get_room_by_label("Hall").unwrap();
+        let hall_id = hall.get_id();
+        hall.send_cmd(&socket.get_id(), CMD_STATUS, &CommandArgs::new())
+            .unwrap();
+        assert!(home.events().unsubscribe(sync));
+        assert!(!home.events().unsubscribe(sync));
+        home.get_room_mut(&kitchen)
+            .unwrap()
+            .remove_device(&sensor.get_id());
+        home.remove_room(&kitchen);
+
+        let events: Vec<HomeEvent> = all.try_iter().collect();
+        let names: Vec<String> = events
+            .iter()
+            .map(|e| format!("{:?}", e).split('(').next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "RoomAdded",
+                "DeviceAdded",
+                "Bound",
+                "Bound",
+                "PowerChanged",
+                "PowerChanged",
+                "CommandExecuted",
+                "DeviceRemoved",
+                "RoomRemoved"
+            ]
+        );
+        assert!(events
+            .iter()
+            .all(|e| e.room_id() == kitchen || e.room_id() == hall_id));
+
+        let sockets: Vec<HomeEvent> = sockets.try_iter().collect();
+        assert_eq!(sockets.len(), 3);
+        assert!(sockets
+            .iter()
+            .all(|e| *e.device().unwrap().device_id() == socket.get_id()));
+        assert!(matches!(
+            &sockets[2],
+            HomeEvent::CommandExecuted(_, CMD_STATUS, Some(CommandResult::Str(_)))
+        ));
+        assert_eq!(*count.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_dropped_channel() {
+        let bus = EventBus::new();
+        drop(bus.subscribe_channel(&EventFilter::new()));
+        bus.publish(HomeEvent::RoomAdded(xid::new(), String::from("Hall")));
+        assert!(bus.subscriptions.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_power_changes() {
+        let mut room = Room::new();
+        let id = room.add_device(TestDevice::new().device("socket"));
+        let events = room.events().subscribe_channel(&EventFilter::new());
+
+        let barrier = Barrier::new(4);
+        thread::scope(|s| {
+            for i in 0..4 {
+                let (room, id, barrier) = (&room, &id, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    for j in 0..20000 {
+                        let state = match (i + j) % 2 {
+                            0 => PowerState::ON,
+                            _ => PowerState::OFF,
+                        };
+                        room.switch_device_power_shared(id, state).unwrap();
+                    }
+                });
+            }
+        });
+        // every change is reported once, so switches on and off balance out from the initial OFF
+        let (mut on, mut off) = (0, 0);
+        for event in events.try_iter() {
+            match event {
+                HomeEvent::PowerChanged(_, PowerState::ON) => on += 1,
+                HomeEvent::PowerChanged(_, PowerState::OFF) => off += 1,
+                _ => {}
+            }
+        }
+        let state = room.get_device(&id).unwrap().get_power_state().unwrap();
+        assert!(on > 0);
+        assert_eq!(on - off, (state == PowerState::ON) as i32);
+    }
+}
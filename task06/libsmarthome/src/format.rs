@@ -0,0 +1,20 @@
+//! Formats of the files describing the home, e.g. topology and rules
+
+use std::path::Path;
+
+/// Format of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Toml,
+}
+
+impl FileFormat {
+    /// Detects format by file extension, JSON is used for unknown ones
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::device_ref::*;
 use crate::error::DeviceError;
+use crate::events::{EventBus, HomeEvent};
 use crate::factory::PhysicalDeviceFactory;
 use crate::logical::PowerState;
 use crate::report::{Reporter, RoomRef};
@@ -15,18 +16,50 @@ pub struct Home {
     rooms: HashMap<xid::Id, Box<Room>>,
     /// Help map to find room by label
     label_map: HashMap<String, xid::Id>,
+    /// Events of the home and its rooms
+    events: EventBus,
 }
 
 impl Home {
-    /// Add room to the home
-    pub fn add_room(&mut self, room: Box<Room>) -> xid::Id {
+    /// Get home name
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Bus of the home events, including events of its rooms
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Add room to the home, room publishes its events to the bus of the home
+    pub fn add_room(&mut self, mut room: Box<Room>) -> xid::Id {
         let label = room.get_label();
         let id = room.get_id();
+        room.set_events(self.events.clone());
         self.rooms.insert(id, room);
-        self.label_map.insert(label, id);
+        self.label_map.insert(label.clone(), id);
+        self.events.publish(HomeEvent::RoomAdded(id, label));
         id
     }
 
+    /// Get references to rooms of the home
+    pub fn get_rooms(&self) -> Vec<&Room> {
+        self.rooms.values().map(|r| r.as_ref()).collect()
+    }
+
+    /// Get reference to the room by id
+    pub fn get_room(&self, id: &xid::Id) -> Option<&Room> {
+        self.rooms.get(id).map(|r| r.as_ref())
+    }
+
+    /// Get reference to the room by label
+    pub fn get_room_by_label(&self, label: &str) -> Option<&Room> {
+        self.label_map
+            .get(label)
+            .and_then(|id| self.rooms.get(id))
+            .map(|r| r.as_ref())
+    }
+
     /// Get mutable reference to the room by id
     pub fn get_room_mut(&mut self, id: &xid::Id) -> Option<&mut Box<Room>> {
         self.rooms.get_mut(id)
@@ -46,6 +79,8 @@ impl Home {
             None => None,
             Some(r) => {
                 self.label_map.remove(&r.get_label());
+                self.events
+                    .publish(HomeEvent::RoomRemoved(*id, r.get_label()));
                 Some(*id)
             }
         }
@@ -55,7 +90,12 @@ impl Home {
     pub fn remove_room_by_label(&mut self, label: &String) -> Option<xid::Id> {
         match self.label_map.remove(label) {
             None => None,
-            Some(id) => self.rooms.remove(&id).map(|r| r.get_id()),
+            Some(id) => {
+                let room = self.rooms.remove(&id)?;
+                self.events
+                    .publish(HomeEvent::RoomRemoved(id, room.get_label()));
+                Some(id)
+            }
         }
     }
 
@@ -82,6 +122,14 @@ impl Home {
         Ok(())
     }
 
+    /// Switch power of home shared by threads, devices are locked one by one
+    pub fn switch_power_shared(&self, state: PowerState) -> Result<(), DeviceError> {
+        for room in self.rooms.values() {
+            room.switch_power_shared(state)?
+        }
+        Ok(())
+    }
+
     /// Binds physical devices to logical ones
     pub fn bind_physical_devices<F: PhysicalDeviceFactory>(
         &mut self,
@@ -214,10 +262,7 @@ impl HomeBuilder {
     }
 
     pub fn add_room(&mut self, r: Box<Room>) -> &mut Self {
-        let id = r.get_id();
-        let label = r.get_label();
-        self.home.rooms.insert(id, r);
-        self.home.label_map.insert(label.clone(), id);
+        self.home.add_room(r);
         self
     }
 
@@ -0,0 +1,259 @@
+//! Client of the home server, see `home_server`
+
+use libprotocol::client::TcpClient;
+use libprotocol::commands::Status;
+use libprotocol::Packet;
+
+use crate::args::CommandArgs;
+use crate::device_ref::DeviceRef;
+use crate::error::RemoteError;
+use crate::home_server::{decode_result, HomeCommands};
+use crate::logical::*;
+
+/// Client of the home server
+pub struct HomeClient {
+    client: TcpClient,
+}
+
+fn connection_error<E: std::fmt::Display>(v: E) -> RemoteError {
+    RemoteError::Connection(v.to_string())
+}
+
+impl HomeClient {
+    pub fn connect(addr: String) -> Result<Self, RemoteError> {
+        Ok(Self {
+            client: TcpClient::connect(addr).map_err(connection_error)?,
+        })
+    }
+
+    /// Returns ids and labels of the rooms sorted by label
+    pub fn list_rooms(&mut self) -> Result<Vec<(xid::Id, String)>, RemoteError> {
+        self.request(HomeCommands::ListRooms, &[])?;
+        let count = self.recv_i32()?;
+        (0..count)
+            .map(|_| Ok((self.recv_id()?, self.recv_str()?)))
+            .collect()
+    }
+
+    /// Returns devices of the home, see `Home::get_devices`
+    pub fn list_devices(&mut self) -> Result<Vec<DeviceRef>, RemoteError> {
+        self.request(HomeCommands::ListDevices, &[])?;
+        let count = self.recv_i32()?;
+        (0..count)
+            .map(|_| {
+                Ok(DeviceRef::new(
+                    self.recv_id()?,
+                    self.recv_str()?,
+                    self.recv_id()?,
+                    self.recv_str()?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Sends command to the device in the room given by id or label, see `Room::send_cmd`
+    pub fn send_cmd(
+        &mut self,
+        room: &str,
+        device_id: &xid::Id,
+        cmd: DeviceCommand,
+        args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, RemoteError> {
+        let args = args.to_strings();
+        let mut request = vec![
+            Packet::Byte(HomeCommands::SendCommand as u8),
+            Packet::Str(room.to_string()),
+            Packet::Str(device_id.to_string()),
+            Packet::Str(cmd.name.to_string()),
+            Packet::Int32(args.len() as i32),
+        ];
+        request.extend(args.into_iter().map(Packet::Str));
+        self.send(&request)?;
+        decode_result(|| self.client.recv_response().map_err(connection_error))?
+            .ok_or(RemoteError::UnexpectedResponse)
+    }
+
+    /// Switches power of the room given by id or label
+    pub fn switch_room_power(&mut self, room: &str, state: PowerState) -> Result<(), RemoteError> {
+        self.request(
+            HomeCommands::SwitchRoomPower,
+            &[room, format!("{:?}", state).as_str()],
+        )
+    }
+
+    /// Switches power of the home
+    pub fn switch_power(&mut self, state: PowerState) -> Result<(), RemoteError> {
+        self.request(
+            HomeCommands::SwitchHomePower,
+            &[format!("{:?}", state).as_str()],
+        )
+    }
+
+    /// Returns status report of all devices, see `Home::create_report`
+    pub fn get_report(&mut self) -> Result<String, RemoteError> {
+        self.request(HomeCommands::GetReport, &[])?;
+        self.recv_str()
+    }
+
+    fn request(&mut self, cmd: HomeCommands, args: &[&str]) -> Result<(), RemoteError> {
+        let mut request = vec![Packet::Byte(cmd as u8)];
+        request.extend(args.iter().map(|v| Packet::Str(v.to_string())));
+        self.send(&request)
+    }
+
+    /// Sends request and reads status of the response
+    fn send(&mut self, request: &[Packet]) -> Result<(), RemoteError> {
+        self.client
+            .send_request(request)
+            .map_err(connection_error)?;
+        let status = match self.recv()? {
+            Packet::Byte(v) => Status::try_from(v).map_err(|_| RemoteError::UnexpectedResponse)?,
+            _ => return Err(RemoteError::UnexpectedResponse),
+        };
+        match status {
+            Status::Ok => Ok(()),
+            s => Err(RemoteError::Server(s, self.recv_str()?)),
+        }
+    }
+
+    fn recv(&mut self) -> Result<Packet, RemoteError> {
+        self.client.recv_response().map_err(connection_error)
+    }
+
+    fn recv_i32(&mut self) -> Result<i32, RemoteError> {
+        match self.recv()? {
+            Packet::Int32(v) => Ok(v),
+            _ => Err(RemoteError::UnexpectedResponse),
+        }
+    }
+
+    fn recv_str(&mut self) -> Result<String, RemoteError> {
+        match self.recv()? {
+            Packet::Str(v) => Ok(v),
+            _ => Err(RemoteError::UnexpectedResponse),
+        }
+    }
+
+    fn recv_id(&mut self) -> Result<xid::Id, RemoteError> {
+        self.recv_str()?
+            .parse()
+            .map_err(|_| RemoteError::UnexpectedResponse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::args::ArgValue;
+    use crate::commands::{CMD_GET_TEMPERATURE, CMD_SELF_TEST, CMD_SET_THRESHOLD, CMD_STATUS};
+    use crate::factory::SimpleClassFactory;
+    use crate::home::HomeBuilder;
+    use crate::home_server::HomeServer;
+    use crate::logical_device::Device;
+    use crate::room::{Binder, RoomBuilder};
+    use crate::shared::SharedHome;
+
+    #[test]
+    fn test_home_server() {
+        let socket = Device::new(String::from("socket"));
+        let sensor = Device::new(String::from("tsensor"));
+        let kitchen = RoomBuilder::new()
+            .set_label(String::from("Kitchen"))
+            .add_device(&socket)
+            .build();
+        let hall = RoomBuilder::new()
+            .set_label(String::from("Hall"))
+            .add_device(&sensor)
+            .build();
+        let hall_id = hall.get_id();
+        let mut home = HomeBuilder::new()
+            .set_name(String::from("My home"))
+            .add_room(Box::new(kitchen))
+            .add_room(Box::new(hall))
+            .build();
+
+        home.bind_physical_devices(&mut Binder::new(SimpleClassFactory {}))
+            .unwrap();
+        let home = SharedHome::from(home);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut server = HomeServer::from_listener(listener, home.clone());
+        thread::spawn(move || server.run());
+
+        let mut client = HomeClient::connect(addr.clone()).unwrap();
+        let rooms = client.list_rooms().unwrap();
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[0], (hall_id, String::from("Hall")));
+        let mut devices = client.list_devices().unwrap();
+        let mut expected = home.get_devices();
+        // clients are served in parallel
+        let mut other = HomeClient::connect(addr).unwrap();
+        assert_eq!(other.list_rooms().unwrap(), rooms);
+        devices.sort();
+        expected.sort();
+        assert_eq!(devices, expected);
+
+        client.switch_power(PowerState::ON).unwrap();
+        client
+            .switch_room_power(&hall_id.to_string(), PowerState::OFF)
+            .unwrap();
+        assert!(matches!(
+            client.send_cmd("Kitchen", &socket.get_id(), CMD_STATUS, &CommandArgs::default()),
+            Ok(Some(CommandResult::Str(v))) if v.contains("ON")
+        ));
+        assert!(matches!(
+            client.send_cmd("Hall", &sensor.get_id(), CMD_GET_TEMPERATURE, &CommandArgs::default()),
+            Ok(Some(CommandResult::Float32(v))) if v.is_nan()
+        ));
+        assert!(matches!(
+            client.send_cmd(
+                "Kitchen",
+                &socket.get_id(),
+                CMD_GET_TEMPERATURE,
+                &CommandArgs::default()
+            ),
+            Err(RemoteError::Server(Status::UnknownCommand, _))
+        ));
+        assert!(matches!(
+            client.send_cmd(
+                "Hall",
+                &socket.get_id(),
+                CMD_SELF_TEST,
+                &CommandArgs::default()
+            ),
+            Err(RemoteError::Server(Status::UnknownDevice, _))
+        ));
+        assert!(matches!(
+            client.switch_room_power("Garage", PowerState::ON),
+            Err(RemoteError::Server(Status::UnknownRoom, _))
+        ));
+
+        // arguments are validated before they reach the device
+        let threshold = CommandArgs::from([("celsius", ArgValue::Float(30.5))]);
+        assert!(matches!(
+            client.send_cmd("Hall", &sensor.get_id(), CMD_SET_THRESHOLD, &threshold),
+            Ok(None)
+        ));
+        assert!(matches!(
+            client.send_cmd("Hall", &sensor.get_id(), CMD_STATUS, &CommandArgs::default()),
+            Ok(Some(CommandResult::Str(v))) if v.contains("threshold = 30.5C")
+        ));
+        for invalid in [
+            CommandArgs::default(),
+            CommandArgs::from([("celsius", ArgValue::Float(300.0))]),
+            CommandArgs::from([("celsius", ArgValue::Str(String::from("hot")))]),
+        ] {
+            assert!(matches!(
+                client.send_cmd("Hall", &sensor.get_id(), CMD_SET_THRESHOLD, &invalid),
+                Err(RemoteError::Server(Status::BadRequest, _))
+            ));
+        }
+
+        let report = client.get_report().unwrap();
+        assert!(report.starts_with("# IoT report for the home \"My home\""));
+        assert!(report.contains("## Room 'Hall'") && report.contains("## Room 'Kitchen'"));
+    }
+}
@@ -0,0 +1,662 @@
+//! Server of the smart home over the libprotocol packets.
+//!
+//! Request is the command code followed by its string arguments. `SendCommand`
+//! is followed by the number of device command arguments and the arguments.
+//! Response is the status code (see `libprotocol::commands::Status`) followed by the result
+//! on success or by the error message.
+//!
+//! Rooms are referenced by id or by label.
+//!
+//! Every client is served by its own thread, the home is shared by threads,
+//! see `shared::SharedHome`. Number of clients is limited and idle clients
+//! are disconnected.
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libprotocol::commands::Status;
+use libprotocol::error::{BindError, CmdError};
+use libprotocol::server::{TcpConnection, TcpServer};
+use libprotocol::{log_error, log_info, Packet};
+
+use crate::commands::CommandRegistry;
+use crate::error::DeviceError;
+use crate::home::Home;
+use crate::logical::*;
+use crate::report::SimpleReporter;
+use crate::room::Room;
+use crate::shared::SharedHome;
+
+/// Commands of the home server
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HomeCommands {
+    /// Returns number of rooms, then id and label of every room
+    ListRooms = 1,
+    /// Returns number of devices, then room id, room label, device id and class of every device
+    ListDevices = 2,
+    /// Args: room, device id, command name, then number of `name=value` command
+    /// arguments and the arguments. Returns command result
+    SendCommand = 3,
+    /// Args: room, power state `on` or `off`
+    SwitchRoomPower = 4,
+    /// Args: power state `on` or `off`
+    SwitchHomePower = 5,
+    /// Returns status report of all devices
+    GetReport = 6,
+}
+
+impl HomeCommands {
+    pub const ALL: [HomeCommands; 6] = [
+        HomeCommands::ListRooms,
+        HomeCommands::ListDevices,
+        HomeCommands::SendCommand,
+        HomeCommands::SwitchRoomPower,
+        HomeCommands::SwitchHomePower,
+        HomeCommands::GetReport,
+    ];
+
+    /// Number of string arguments which follow the command code
+    pub fn args_count(&self) -> usize {
+        match self {
+            HomeCommands::ListRooms | HomeCommands::ListDevices | HomeCommands::GetReport => 0,
+            HomeCommands::SendCommand => 3,
+            HomeCommands::SwitchRoomPower => 2,
+            HomeCommands::SwitchHomePower => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for HomeCommands {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        HomeCommands::ALL
+            .into_iter()
+            .find(|c| *c as u8 == value)
+            .ok_or(value)
+    }
+}
+
+/// Encodes command result: its type name followed by the value
+pub fn encode_result(result: Option<CommandResult>) -> Vec<Packet> {
+    let (kind, mut value) = match result {
+        None => ("none", vec![]),
+        Some(CommandResult::Bool(v)) => ("bool", vec![Packet::Byte(v as u8)]),
+        Some(CommandResult::Str(v)) => ("str", vec![Packet::Str(v)]),
+        Some(CommandResult::Int32(v)) => ("i32", vec![Packet::Int32(v)]),
+        Some(CommandResult::Int64(v)) => ("i64", vec![Packet::Str(v.to_string())]),
+        Some(CommandResult::Float32(v)) => ("f32", vec![Packet::Float32(v)]),
+        Some(CommandResult::Float64(v)) => ("f64", vec![Packet::Str(v.to_string())]),
+        Some(CommandResult::VecStr(v)) => ("vec_str", {
+            let mut packets = vec![Packet::Int32(v.len() as i32)];
+            packets.extend(v.into_iter().map(Packet::Str));
+            packets
+        }),
+        Some(CommandResult::Bytes(v)) => ("bytes", {
+            let mut packets = vec![Packet::Int32(v.len() as i32)];
+            packets.extend(v.into_iter().map(Packet::Byte));
+            packets
+        }),
+    };
+    let mut packets = vec![Packet::Str(String::from(kind))];
+    packets.append(&mut value);
+    packets
+}
+
+/// Decodes command result encoded by [`encode_result`], packets are read by `next`.
+/// Returns None if packets don't match the encoding.
+pub fn decode_result<E, F>(mut next: F) -> Result<Option<Option<CommandResult>>, E>
+where
+    F: FnMut() -> Result<Packet, E>,
+{
+    let Packet::Str(kind) = next()? else {
+        return Ok(None);
+    };
+    let result = match kind.as_str() {
+        "none" => None,
+        "bool" => match next()? {
+            Packet::Byte(v) => Some(CommandResult::Bool(v != 0)),
+            _ => return Ok(None),
+        },
+        "str" => match next()? {
+            Packet::Str(v) => Some(CommandResult::Str(v)),
+            _ => return Ok(None),
+        },
+        "i32" => match next()? {
+            Packet::Int32(v) => Some(CommandResult::Int32(v)),
+            _ => return Ok(None),
+        },
+        "i64" => match next()? {
+            Packet::Str(v) => match v.parse() {
+                Ok(v) => Some(CommandResult::Int64(v)),
+                Err(_) => return Ok(None),
+            },
+            _ => return Ok(None),
+        },
+        "f32" => match next()? {
+            Packet::Float32(v) => Some(CommandResult::Float32(v)),
+            _ => return Ok(None),
+        },
+        "f64" => match next()? {
+            Packet::Str(v) => match v.parse() {
+                Ok(v) => Some(CommandResult::Float64(v)),
+                Err(_) => return Ok(None),
+            },
+            _ => return Ok(None),
+        },
+        "vec_str" => {
+            let Packet::Int32(n) = next()? else {
+                return Ok(None);
+            };
+            let mut values = vec![];
+            for _ in 0..n {
+                match next()? {
+                    Packet::Str(v) => values.push(v),
+                    _ => return Ok(None),
+                }
+            }
+            Some(CommandResult::VecStr(values))
+        }
+        "bytes" => {
+            let Packet::Int32(n) = next()? else {
+                return Ok(None);
+            };
+            let mut values = vec![];
+            for _ in 0..n {
+                match next()? {
+                    Packet::Byte(v) => values.push(v),
+                    _ => return Ok(None),
+                }
+            }
+            Some(CommandResult::Bytes(values))
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(result))
+}
+
+/// Default number of clients served at once
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+/// Default time the client may be silent before it is disconnected
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Decrements number of served clients when the client is disconnected
+struct ActiveGuard(Arc<AtomicUsize>);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Server of the home
+pub struct HomeServer {
+    server: TcpServer,
+    home: SharedHome,
+    /// Commands which may be sent by name
+    registry: Arc<CommandRegistry>,
+    max_connections: usize,
+    idle_timeout: Option<Duration>,
+    active: Arc<AtomicUsize>,
+}
+
+impl HomeServer {
+    pub fn bind(addr: String, home: SharedHome) -> Result<Self, BindError> {
+        Ok(Self::from_server(TcpServer::bind(addr)?, home))
+    }
+
+    /// Serves already bound listener
+    pub fn from_listener(listener: TcpListener, home: SharedHome) -> Self {
+        Self::from_server(TcpServer::from_listener(listener), home)
+    }
+
+    fn from_server(server: TcpServer, home: SharedHome) -> Self {
+        Self {
+            server,
+            home,
+            registry: Arc::new(CommandRegistry::new()),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Replaces registry of the builtin commands, e.g. by one with custom commands
+    pub fn set_registry(&mut self, registry: CommandRegistry) -> &mut Self {
+        self.registry = Arc::new(registry);
+        self
+    }
+
+    /// Clients connected above the limit are disconnected at once
+    pub fn set_max_connections(&mut self, max: usize) -> &mut Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Client which sends nothing for the timeout is disconnected,
+    /// `None` keeps idle clients
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Handle of the served home, it may be used while the server runs
+    pub fn get_home(&self) -> SharedHome {
+        self.home.clone()
+    }
+
+    /// Number of the clients being served
+    pub fn active_connections(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Serves every client by its own thread
+    pub fn run(&mut self) {
+        for item in self.server.incoming() {
+            match item {
+                Ok(connection) => {
+                    let peer = connection.peer_addr();
+                    let active = self.active.fetch_add(1, Ordering::Relaxed);
+                    let guard = ActiveGuard(self.active.clone());
+                    if active >= self.max_connections {
+                        log_error!("Connection limit is reached, dropping client {:?}", peer);
+                        continue;
+                    }
+                    if let Err(v) = connection.set_read_timeout(self.idle_timeout) {
+                        log_error!("Cannot set idle timeout {}", v);
+                    }
+                    log_info!("Serving home to {:?}", peer);
+                    let home = self.home.clone();
+                    let registry = self.registry.clone();
+                    thread::spawn(move || {
+                        let _guard = guard;
+                        if let Err(v) = serve_requests(connection, &home, &registry) {
+                            log_info!("Client {:?} is disconnected: {}", peer, v);
+                        }
+                    });
+                }
+                Err(v) => log_error!("Cannot accept client {:?}", v),
+            }
+        }
+    }
+}
+
+fn error_response(status: Status, msg: String) -> Vec<Packet> {
+    vec![Packet::Byte(status as u8), Packet::Str(msg)]
+}
+
+fn device_error_response(v: DeviceError) -> Vec<Packet> {
+    let status = match v {
+        DeviceError::DeviceIsMissing(_) => Status::UnknownDevice,
+        DeviceError::RoomIsMissing(_) => Status::UnknownRoom,
+        DeviceError::UnsupportedCommand => Status::UnknownCommand,
+        _ => Status::BadRequest,
+    };
+    error_response(status, v.to_string())
+}
+
+fn parse_power_state(s: &str) -> Option<PowerState> {
+    match s.to_lowercase().as_str() {
+        "on" => Some(PowerState::ON),
+        "off" => Some(PowerState::OFF),
+        _ => None,
+    }
+}
+
+/// Finds room by id or label
+fn find_room<'a>(home: &'a Home, room: &str) -> Option<&'a Room> {
+    room.parse::<xid::Id>()
+        .ok()
+        .and_then(|id| home.get_room(&id))
+        .or_else(|| home.get_room_by_label(room))
+}
+
+fn room_not_found(room: &str) -> Vec<Packet> {
+    error_response(Status::UnknownRoom, format!("room {:?} is not found", room))
+}
+
+/// Executes command, `cmd_args` are `name=value` arguments of the device command
+fn execute_cmd(
+    home: &Home,
+    registry: &CommandRegistry,
+    cmd: HomeCommands,
+    args: &[String],
+    cmd_args: Vec<String>,
+) -> Vec<Packet> {
+    let mut response = vec![Packet::Byte(Status::Ok as u8)];
+    match cmd {
+        HomeCommands::ListRooms => {
+            let mut rooms = home.get_rooms();
+            rooms.sort_by_key(|r| r.get_label());
+            response.push(Packet::Int32(rooms.len() as i32));
+            rooms.iter().for_each(|r| {
+                response.push(Packet::Str(r.get_id().to_string()));
+                response.push(Packet::Str(r.get_label()));
+            });
+        }
+        HomeCommands::ListDevices => {
+            let mut devices = home.get_devices();
+            devices.sort();
+            response.push(Packet::Int32(devices.len() as i32));
+            devices.iter().for_each(|d| {
+                response.push(Packet::Str(d.room_id().to_string()));
+                response.push(Packet::Str(d.room_name().clone()));
+                response.push(Packet::Str(d.device_id().to_string()));
+                response.push(Packet::Str(d.device_class().clone()));
+            });
+        }
+        HomeCommands::SendCommand => {
+            let Some(room) = find_room(home, &args[0]) else {
+                return room_not_found(&args[0]);
+            };
+            let Some(device) = args[1]
+                .parse::<xid::Id>()
+                .ok()
+                .filter(|id| room.has_device(*id))
+            else {
+                return error_response(
+                    Status::UnknownDevice,
+                    format!("device {:?} is not found", args[1]),
+                );
+            };
+            let Some(command) = registry.find(&args[2]) else {
+                return error_response(
+                    Status::UnknownCommand,
+                    format!("unknown command {:?}", args[2]),
+                );
+            };
+            let cmd_args = match command.parse_args(&cmd_args) {
+                Ok(v) => v,
+                Err(v) => return device_error_response(v),
+            };
+            match room.send_cmd_shared(&device, command, &cmd_args) {
+                Ok(v) => response.append(&mut encode_result(v)),
+                Err(v) => return device_error_response(v),
+            }
+        }
+        HomeCommands::SwitchRoomPower => {
+            let Some(state) = parse_power_state(&args[1]) else {
+                return error_response(
+                    Status::BadRequest,
+                    format!("invalid power state {:?}", args[1]),
+                );
+            };
+            let Some(room) = find_room(home, &args[0]) else {
+                return room_not_found(&args[0]);
+            };
+            if let Err(v) = room.switch_power_shared(state) {
+                return device_error_response(v);
+            }
+        }
+        HomeCommands::SwitchHomePower => {
+            let Some(state) = parse_power_state(&args[0]) else {
+                return error_response(
+                    Status::BadRequest,
+                    format!("invalid power state {:?}", args[0]),
+                );
+            };
+            if let Err(v) = home.switch_power_shared(state) {
+                return device_error_response(v);
+            }
+        }
+        HomeCommands::GetReport => {
+            let mut reporter = SimpleReporter::default();
+            home.get_devices()
+                .iter()
+                .for_each(|d| reporter.add_device(d.room_id(), d.device_id()));
+            response.push(Packet::Str(home.create_report(&reporter)));
+        }
+    }
+    response
+}
+
+/// Maximal number of device command arguments in the request
+pub const MAX_CMD_ARGS: i32 = 64;
+/// Time the rest of the malformed request is read before the connection is closed
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Reads packets sent after the malformed request until the client is silent,
+/// closing the socket with unread data resets the connection and the client
+/// may lose the error response
+fn drain(connection: &mut TcpConnection) {
+    let deadline = Instant::now() + DRAIN_TIMEOUT * 5;
+    if connection.set_read_timeout(Some(DRAIN_TIMEOUT)).is_err() {
+        return;
+    }
+    while Instant::now() < deadline && connection.recv_request().is_ok() {}
+}
+
+/// Serves client requests until it disconnects. Requests aren't framed, so the rest
+/// of the malformed request can't be told from the next one: the error is sent
+/// and the connection is closed.
+fn serve_requests(
+    mut connection: TcpConnection,
+    home: &SharedHome,
+    registry: &CommandRegistry,
+) -> Result<(), CmdError> {
+    loop {
+        let (cmd, args, cmd_args) = match read_request(&mut connection)? {
+            Ok(request) => request,
+            Err(response) => {
+                log_error!(
+                    "Malformed request from {:?}, closing connection",
+                    connection.peer_addr()
+                );
+                connection.send_response_vec(&response)?;
+                drain(&mut connection);
+                return Ok(());
+            }
+        };
+        let response = execute_cmd(&home.read(), registry, cmd, &args, cmd_args);
+        connection.send_response_vec(&response)?;
+    }
+}
+
+/// Command, its arguments and arguments of the device command
+type Request = (HomeCommands, Vec<String>, Vec<String>);
+
+/// Reads the command with its arguments and arguments of the device command.
+/// Returns error response if packets don't match the command.
+fn read_request(connection: &mut TcpConnection) -> Result<Result<Request, Vec<Packet>>, CmdError> {
+    let bad_request = |msg: &str| Ok(Err(error_response(Status::BadRequest, msg.to_string())));
+    let cmd = match connection.recv_request()? {
+        Packet::Byte(v) => match HomeCommands::try_from(v) {
+            Ok(cmd) => cmd,
+            Err(v) => {
+                return Ok(Err(error_response(
+                    Status::UnknownCommand,
+                    format!("unsupported command {}", v),
+                )))
+            }
+        },
+        _ => return bad_request("command code expected"),
+    };
+    let mut args = vec![];
+    for _ in 0..cmd.args_count() {
+        match connection.recv_request()? {
+            Packet::Str(v) => args.push(v),
+            _ => return bad_request("invalid arguments"),
+        }
+    }
+    let mut cmd_args = vec![];
+    if cmd == HomeCommands::SendCommand {
+        let n = match connection.recv_request()? {
+            Packet::Int32(n) if (0..=MAX_CMD_ARGS).contains(&n) => n,
+            _ => return bad_request("invalid number of command arguments"),
+        };
+        for _ in 0..n {
+            match connection.recv_request()? {
+                Packet::Str(v) => cmd_args.push(v),
+                _ => return bad_request("invalid arguments"),
+            }
+        }
+    }
+    Ok(Ok((cmd, args, cmd_args)))
+}
+
+#[cfg(test)]
+mod tests {
+    use libprotocol::client::TcpClient;
+
+    use super::*;
+    use crate::home::HomeBuilder;
+
+    #[test]
+    fn test_result_encoding() {
+        let results = [
+            None,
+            Some(CommandResult::Bool(true)),
+            Some(CommandResult::Str(String::from("PASSED"))),
+            Some(CommandResult::Int32(-5)),
+            Some(CommandResult::Int64(1 << 40)),
+            Some(CommandResult::Float32(1.5)),
+            Some(CommandResult::Float64(0.1)),
+            Some(CommandResult::VecStr(vec![
+                String::from("a"),
+                String::from("b"),
+            ])),
+            Some(CommandResult::Bytes(vec![1, 2, 3])),
+        ];
+        for result in results {
+            let expected = format!("{:?}", result);
+            let mut packets = encode_result(result).into_iter();
+            let decoded = decode_result(|| packets.next().ok_or(())).unwrap();
+            assert_eq!(format!("{:?}", decoded.unwrap()), expected);
+            assert!(packets.next().is_none());
+        }
+        let mut packets = vec![Packet::Str(String::from("i32")), Packet::Float32(1.0)].into_iter();
+        assert!(decode_result(|| packets.next().ok_or(()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_error_status() {
+        let status = |v: DeviceError| device_error_response(v)[0].clone();
+        assert_eq!(
+            status(DeviceError::RoomIsMissing(xid::new())),
+            Packet::Byte(Status::UnknownRoom as u8)
+        );
+        assert_eq!(
+            status(DeviceError::DeviceIsMissing(xid::new())),
+            Packet::Byte(Status::UnknownDevice as u8)
+        );
+        assert_eq!(
+            status(DeviceError::UnsupportedCommand),
+            Packet::Byte(Status::UnknownCommand as u8)
+        );
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        let home = SharedHome::from(HomeBuilder::new().build());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut server = HomeServer::from_listener(listener, home);
+        thread::spawn(move || server.run());
+
+        let send_cmd = |count: Packet| {
+            vec![
+                Packet::Byte(HomeCommands::SendCommand as u8),
+                Packet::Str(String::from("Kitchen")),
+                Packet::Str(xid::new().to_string()),
+                Packet::Str(String::from("status")),
+                count,
+            ]
+        };
+        let requests = [
+            vec![Packet::Str(String::from("list"))],
+            vec![Packet::Byte(100), Packet::Str(String::from("Kitchen"))],
+            vec![
+                Packet::Byte(HomeCommands::SwitchRoomPower as u8),
+                Packet::Int32(1),
+                Packet::Str(String::from("on")),
+            ],
+            send_cmd(Packet::Int32(-1)),
+            send_cmd(Packet::Int32(MAX_CMD_ARGS + 1)),
+            send_cmd(Packet::Str(String::from("1"))),
+        ];
+        for request in requests {
+            let mut client = TcpClient::connect(addr.clone()).unwrap();
+            client.send_request(&request).unwrap();
+            // the rest of the request isn't executed as the next one
+            client
+                .send_request(&[Packet::Byte(HomeCommands::ListRooms as u8)])
+                .unwrap();
+            assert!(matches!(
+                client.recv_response().unwrap(),
+                Packet::Byte(v) if v != Status::Ok as u8
+            ));
+            assert!(matches!(client.recv_response().unwrap(), Packet::Str(_)));
+            assert!(client.recv_response().is_err());
+        }
+
+        let mut client = TcpClient::connect(addr).unwrap();
+        let mut request = send_cmd(Packet::Int32(1));
+        request.push(Packet::Str(String::from("x=1")));
+        client.send_request(&request).unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::UnknownRoom as u8)
+        );
+        // error of the well formed request keeps the connection
+        assert!(matches!(client.recv_response().unwrap(), Packet::Str(_)));
+        client
+            .send_request(&[Packet::Byte(HomeCommands::ListRooms as u8)])
+            .unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+        assert_eq!(client.recv_response().unwrap(), Packet::Int32(0));
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let home = SharedHome::from(HomeBuilder::new().build());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut server = HomeServer::from_listener(listener, home);
+        server
+            .set_max_connections(1)
+            .set_idle_timeout(Some(Duration::from_millis(200)));
+        let active = server.active.clone();
+        thread::spawn(move || server.run());
+
+        let list_rooms = |client: &mut TcpClient| {
+            client
+                .send_request(&[Packet::Byte(HomeCommands::ListRooms as u8)])
+                .and_then(|_| {
+                    client
+                        .recv_response()
+                        .map_err(|_| libprotocol::error::SendError::UnexpectedPacket)
+                })
+        };
+        let mut first = TcpClient::connect(addr.clone()).unwrap();
+        assert_eq!(
+            list_rooms(&mut first).unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+        assert_eq!(first.recv_response().unwrap(), Packet::Int32(0));
+        // the second client is dropped while the first one is served
+        let mut second = TcpClient::connect(addr.clone()).unwrap();
+        assert!(list_rooms(&mut second).is_err());
+
+        // idle client is disconnected, so the new client is served
+        thread::sleep(Duration::from_millis(400));
+        assert!(first.recv_response().is_err());
+        assert_eq!(active.load(Ordering::Relaxed), 0);
+        let mut third = TcpClient::connect(addr).unwrap();
+        assert_eq!(
+            list_rooms(&mut third).unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+    }
+}
@@ -1,12 +1,26 @@
+pub mod args;
+pub mod clock;
 pub mod commands;
 pub mod device_ref;
 pub mod error;
+pub mod events;
 pub mod factory;
+pub mod format;
 pub mod home;
+pub mod home_client;
+pub mod home_server;
 pub mod logical;
 pub mod logical_device;
 pub mod physical;
+pub mod remote;
 pub mod report;
 pub mod room;
+pub mod rules;
+pub mod scene;
+pub mod scheduler;
+pub mod shared;
 pub mod socket;
+#[cfg(test)]
+mod testing;
+pub mod topology;
 pub mod tsensor;
@@ -1,22 +1,105 @@
 //! Logical module implements logical model of smarthome.
 
+use serde::{Deserialize, Serialize};
+
+use crate::args::CommandArgs;
 use crate::error::DeviceError;
 use crate::physical;
 
-/// PowerState describes power state of IoT device
-#[derive(Debug, Clone, Copy, Default)]
+/// PowerState describes power state of IoT device, serialized as `on` or `off`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PowerState {
     #[default]
     ON,
     OFF,
 }
 
-/// DeviceCommand describes command that can be executed by IoT device
+/// DeviceCommand describes command that can be executed by IoT device.
+/// Commands are identified by unique id and name, see `commands::CommandRegistry`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceCommand {
+    pub id: u64,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Arguments taken by the command, see `args::CommandArgs`
+    pub args: &'static [ArgSpec],
+    /// Type of the command result
+    pub result: ResultType,
+}
+
+/// ArgType describes type of command argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Bool,
+    Int,
+    Float,
+    Str,
+}
+
+/// ArgDefault describes value of omitted argument
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub struct DeviceCommand(pub u64, pub &'static str, pub &'static str);
+pub enum ArgDefault {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(&'static str),
+}
+
+/// ArgSpec describes argument of the command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub arg_type: ArgType,
+    /// Minimum of numeric argument, inclusive
+    pub min: Option<f64>,
+    /// Maximum of numeric argument, inclusive
+    pub max: Option<f64>,
+    /// Argument without default value is required
+    pub default: Option<ArgDefault>,
+}
+
+impl ArgSpec {
+    pub const fn new(name: &'static str, arg_type: ArgType) -> Self {
+        Self {
+            name,
+            arg_type,
+            min: None,
+            max: None,
+            default: None,
+        }
+    }
+
+    /// Limits numeric argument to the inclusive range
+    pub const fn range(mut self, min: f64, max: f64) -> Self {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+
+    /// Makes argument optional
+    pub const fn with_default(mut self, default: ArgDefault) -> Self {
+        self.default = Some(default);
+        self
+    }
+}
+
+/// ResultType describes type of command result, `None` if command returns nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultType {
+    None,
+    Bool,
+    Str,
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+    VecStr,
+    Bytes,
+}
 
 /// CommandResult describes result of device command
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CommandResult {
     Bool(bool),
     Str(String),
@@ -28,6 +111,21 @@ pub enum CommandResult {
     Bytes(Vec<u8>),
 }
 
+impl CommandResult {
+    pub fn result_type(&self) -> ResultType {
+        match self {
+            CommandResult::Bool(_) => ResultType::Bool,
+            CommandResult::Str(_) => ResultType::Str,
+            CommandResult::Int32(_) => ResultType::Int32,
+            CommandResult::Int64(_) => ResultType::Int64,
+            CommandResult::Float32(_) => ResultType::Float32,
+            CommandResult::Float64(_) => ResultType::Float64,
+            CommandResult::VecStr(_) => ResultType::VecStr,
+            CommandResult::Bytes(_) => ResultType::Bytes,
+        }
+    }
+}
+
 /// IoTDevice defines common interface for any logical IoT device
 pub trait IoTDevice {
     /// return logical ID of the device
@@ -46,16 +144,16 @@ pub trait IoTDevice {
     fn set_power_state(&mut self, s: PowerState) -> Result<PowerState, DeviceError>;
     /// return list of supported command of the device
     fn get_supported_commands(&self) -> Result<Vec<DeviceCommand>, DeviceError>;
-    /// execute command on device. Command may take arguments as strings (devices usually talks MQTT, so string is Ok for generic interface)
+    /// execute command on device with validated arguments
     fn execute_cmd_mut(
         &mut self,
         cmd: DeviceCommand,
-        ars: Option<Vec<String>>,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError>;
-    /// execute command on device (immutable). Command may take arguments as strings (devices usually talks MQTT, so string is Ok for generic interface)
+    /// execute command on device (immutable) with validated arguments
     fn execute_cmd(
         &self,
         cmd: DeviceCommand,
-        ars: Option<Vec<String>>,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError>;
 }
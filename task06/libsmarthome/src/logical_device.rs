@@ -1,13 +1,18 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::args::CommandArgs;
 use crate::error::DeviceError;
 use crate::logical::*;
 use crate::physical::*;
 
 /// Default implementation of logical IoT device
-/// Logical IoT device is used to define topology of room/home
+/// Logical IoT device is used to define topology of room/home.
+/// Physical device is locked per device, so device may be used from several
+/// threads and slow device doesn't block others.
 pub struct Device {
     id: xid::Id,
     class: String,
-    physical: Option<Box<dyn PhysicalDevice>>,
+    physical: Option<Mutex<Box<dyn PhysicalDevice>>>,
 }
 
 /// Clone implementation for logical device
@@ -31,6 +36,15 @@ impl Device {
         }
     }
 
+    /// Creates device with known id, e.g. loaded from the topology
+    pub fn with_id(id: xid::Id, class: String) -> Self {
+        Device {
+            id,
+            class,
+            physical: Option::None,
+        }
+    }
+
     pub fn get_id(&self) -> xid::Id {
         self.id
     }
@@ -43,7 +57,7 @@ impl Device {
         match self.physical {
             Some(_) => Err(DeviceError::AlreadyBound),
             None => {
-                self.physical = Option::Some(d);
+                self.physical = Option::Some(Mutex::new(d));
                 Ok(())
             }
         }
@@ -63,47 +77,95 @@ impl Device {
         self.physical.is_some()
     }
 
-    pub fn get_power_state(&self) -> Result<PowerState, DeviceError> {
+    /// Locks physical device. Device which panicked is still used.
+    fn lock(&self) -> Result<MutexGuard<'_, Box<dyn PhysicalDevice>>, DeviceError> {
         match &self.physical {
-            Some(d) => d.get_power_state(),
+            Some(d) => Ok(d.lock().unwrap_or_else(PoisonError::into_inner)),
             None => Err(DeviceError::Unbound),
         }
     }
 
-    pub fn set_power_state(&mut self, s: PowerState) -> Result<PowerState, DeviceError> {
+    /// Physical device of the exclusively borrowed device, no locking is needed
+    fn get_mut(&mut self) -> Result<&mut Box<dyn PhysicalDevice>, DeviceError> {
         match &mut self.physical {
-            Some(d) => d.set_power_state(s),
+            Some(d) => Ok(d.get_mut().unwrap_or_else(PoisonError::into_inner)),
             None => Err(DeviceError::Unbound),
         }
     }
 
+    pub fn get_power_state(&self) -> Result<PowerState, DeviceError> {
+        self.lock()?.get_power_state()
+    }
+
+    pub fn set_power_state(&mut self, s: PowerState) -> Result<PowerState, DeviceError> {
+        self.get_mut()?.set_power_state(s)
+    }
+
+    /// Sets power state of the shared device, returns the previous state if it can be read.
+    /// Both are done under the same lock, so concurrent switches don't see the same previous state.
+    /// Changes are published by the room, see `Room::switch_device_power_shared`
+    pub(crate) fn swap_power_state_shared(
+        &self,
+        s: PowerState,
+    ) -> Result<Option<PowerState>, DeviceError> {
+        let mut d = self.lock()?;
+        let previous = d.get_power_state().ok();
+        d.set_power_state(s)?;
+        Ok(previous)
+    }
+
     pub fn get_supported_commands(&self) -> Result<Vec<DeviceCommand>, DeviceError> {
-        match &self.physical {
-            Some(d) => d.get_supported_commands(),
-            None => Err(DeviceError::Unbound),
-        }
+        self.lock()?.get_supported_commands()
+    }
+
+    /// Commands which restore current settings of the device
+    pub fn get_settings(&self) -> Result<Vec<(DeviceCommand, CommandArgs)>, DeviceError> {
+        self.lock()?.get_settings()
     }
 
     pub fn execute_cmd_mut(
         &mut self,
         cmd: DeviceCommand,
-        ars: Option<Vec<String>>,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError> {
-        match &mut self.physical {
-            Some(d) => d.execute_cmd_mut(cmd, ars),
-            None => Err(DeviceError::Unbound),
-        }
+        let args = cmd.validate(args)?;
+        check_result(cmd, self.get_mut()?.execute_cmd_mut(cmd, &args))
     }
 
     pub fn execute_cmd(
         &self,
         cmd: DeviceCommand,
-        ars: Option<Vec<String>>,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError> {
-        match &self.physical {
-            Some(d) => d.execute_cmd(cmd, ars),
-            None => Err(DeviceError::Unbound),
-        }
+        let args = cmd.validate(args)?;
+        check_result(cmd, self.lock()?.execute_cmd(cmd, &args))
+    }
+
+    /// Executes command which may change the shared device, physical device is locked.
+    /// Results are published by the room, see `Room::send_cmd_shared`
+    pub(crate) fn execute_cmd_shared(
+        &self,
+        cmd: DeviceCommand,
+        args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        let args = cmd.validate(args)?;
+        check_result(cmd, self.lock()?.execute_cmd_mut(cmd, &args))
+    }
+}
+
+/// Checks that result of the command has declared type
+fn check_result(
+    cmd: DeviceCommand,
+    result: Result<Option<CommandResult>, DeviceError>,
+) -> Result<Option<CommandResult>, DeviceError> {
+    let result_type = match &result {
+        Ok(Some(r)) => r.result_type(),
+        Ok(None) => ResultType::None,
+        Err(_) => return result,
+    };
+    match result_type == cmd.result {
+        true => result,
+        false => Err(DeviceError::UnexpectedResultFormat),
     }
 }
 
@@ -152,7 +214,7 @@ mod tests {
         })
         .is_ok());
         assert!(
-            (match md.execute_cmd_mut(CMD_GET_POWER_CONSUMPTION, Option::None) {
+            (match md.execute_cmd_mut(CMD_GET_POWER_CONSUMPTION, &CommandArgs::default()) {
                 Err(DeviceError::Unbound) => Ok(()),
                 _ => Err("invalid state"),
             })
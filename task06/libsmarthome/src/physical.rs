@@ -1,10 +1,12 @@
 //! Physical module implements physical models for IoT devices
 
+use crate::args::CommandArgs;
 use crate::error::DeviceError;
 use crate::logical::*;
 
-/// PhysicalDevice defines common interface for physical IoT devices
-pub trait PhysicalDevice {
+/// PhysicalDevice defines common interface for physical IoT devices.
+/// Devices are shared by threads under the device lock, see `shared::SharedHome`.
+pub trait PhysicalDevice: Send {
     /// returns serial number of the device
     fn get_serial(&self) -> String;
     // returns manufactor of the device
@@ -15,16 +17,21 @@ pub trait PhysicalDevice {
     fn set_power_state(&mut self, state: PowerState) -> Result<PowerState, DeviceError>;
     /// return list of supported command of the device
     fn get_supported_commands(&self) -> Result<Vec<DeviceCommand>, DeviceError>;
-    /// execute command on device. Command may take arguments as strings (devices usually talks MQTT, so string is Ok for generic interface)
+    /// returns commands which restore current settings of the device, e.g. `CMD_SET_THRESHOLD`.
+    /// Scenes change and roll back only these settings, see `scene::Scene`
+    fn get_settings(&self) -> Result<Vec<(DeviceCommand, CommandArgs)>, DeviceError> {
+        Ok(vec![])
+    }
+    /// execute command on device. Arguments are validated against the command declaration before the call
     fn execute_cmd_mut(
         &mut self,
         cmd: DeviceCommand,
-        args: Option<Vec<String>>,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError>;
-    /// execute command on device (immutable). Command may take arguments as strings (devices usually talks MQTT, so string is Ok for generic interface)
+    /// execute command on device (immutable). Arguments are validated against the command declaration before the call
     fn execute_cmd(
         &self,
         cmd: DeviceCommand,
-        ars: Option<Vec<String>>,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError>;
 }
@@ -0,0 +1,449 @@
+//! Physical devices served by the remote IoT server (see `libprotocol::commands`).
+//!
+//! `RemoteSocket` forwards power and command calls to the server device
+//! through `TcpClient`, `RemoteSocketFactory` binds logical sockets
+//! to the remote devices by id.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use libprotocol::client::TcpClient;
+use libprotocol::commands::{self, Commands, Status};
+use libprotocol::Packet;
+
+use crate::args::CommandArgs;
+use crate::commands::{CMD_GET_POWER_CONSUMPTION, CMD_SELF_TEST, CMD_STATUS};
+use crate::error::DeviceError;
+use crate::factory::PhysicalDeviceFactory;
+use crate::logical::*;
+use crate::logical_device::Device;
+use crate::physical::*;
+
+/// Default limit of connecting to the server and of waiting for its response
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Socket served by the remote server
+pub struct RemoteSocket {
+    addr: String,
+    id: String,
+    token: Option<String>,
+    /// Requests are sent under the device lock, so they must not hang
+    timeout: Duration,
+    /// Connection is dropped after connection errors and opened again
+    /// by the next request. Commands may be sent through the shared reference
+    client: Mutex<Option<TcpClient>>,
+}
+
+fn connection_error<E: std::fmt::Display>(v: E) -> DeviceError {
+    DeviceError::CommandFailed(format!("connection error: {}", v))
+}
+
+/// Sends request with the argument and reads the payload of the successful response.
+/// Outer error means that the connection is broken or out of sync,
+/// inner one is the error status of the server.
+fn exchange(
+    client: &mut TcpClient,
+    cmd: Commands,
+    arg: &str,
+    payload: usize,
+) -> Result<Result<Vec<Packet>, DeviceError>, DeviceError> {
+    client
+        .send_request(&[Packet::Byte(cmd as u8), Packet::Str(arg.to_string())])
+        .map_err(connection_error)?;
+    let mut recv = || client.recv_response().map_err(connection_error);
+    let status = match recv()? {
+        Packet::Byte(v) => Status::try_from(v).map_err(|_| DeviceError::UnexpectedResultFormat)?,
+        _ => return Err(DeviceError::UnexpectedResultFormat),
+    };
+    if status != Status::Ok {
+        return match recv()? {
+            Packet::Str(v) => Ok(Err(DeviceError::CommandFailed(format!(
+                "{:?}: {}",
+                status, v
+            )))),
+            _ => Err(DeviceError::UnexpectedResultFormat),
+        };
+    }
+    (0..payload)
+        .map(|_| recv())
+        .collect::<Result<_, _>>()
+        .map(Ok)
+}
+
+impl RemoteSocket {
+    /// Connects to the server, authenticates by the token if it is given
+    /// and checks that the server has device with the id
+    pub fn connect(addr: String, id: String, token: Option<&str>) -> Result<Self, DeviceError> {
+        Self::connect_timeout(addr, id, token, DEFAULT_TIMEOUT)
+    }
+
+    /// Connects as `connect`, connecting to the server and each response
+    /// are limited by the timeout
+    pub fn connect_timeout(
+        addr: String,
+        id: String,
+        token: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Self, DeviceError> {
+        let socket = Self {
+            addr,
+            id,
+            token: token.map(String::from),
+            timeout,
+            client: Mutex::new(None),
+        };
+        *socket.client.lock().unwrap_or_else(PoisonError::into_inner) = Some(socket.open()?);
+        socket.get_power_state()?;
+        Ok(socket)
+    }
+
+    /// Id of the device on the server
+    pub fn get_remote_id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Opens authenticated connection
+    fn open(&self) -> Result<TcpClient, DeviceError> {
+        let mut client = TcpClient::connect_timeout(self.addr.clone(), self.timeout)
+            .map_err(connection_error)?;
+        if let Some(token) = &self.token {
+            exchange(&mut client, Commands::Authenticate, token, 0)??;
+        }
+        Ok(client)
+    }
+
+    /// Sends request with the argument and reads the payload of the successful response.
+    /// Requests of the socket are idempotent, so the request failed on the broken
+    /// connection is repeated once on the new one.
+    fn request(
+        &self,
+        cmd: Commands,
+        arg: &str,
+        payload: usize,
+    ) -> Result<Vec<Packet>, DeviceError> {
+        let mut client = self.client.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(c) = client.as_mut() {
+            match exchange(c, cmd, arg, payload) {
+                Ok(result) => return result,
+                Err(_) => *client = None,
+            }
+        }
+        let mut c = self.open()?;
+        let result = exchange(&mut c, cmd, arg, payload)?;
+        *client = Some(c);
+        result
+    }
+
+    fn device_request(&self, cmd: Commands, payload: usize) -> Result<Vec<Packet>, DeviceError> {
+        self.request(cmd, &self.id, payload)
+    }
+
+    fn execute(&self, cmd: DeviceCommand) -> Result<Option<CommandResult>, DeviceError> {
+        match cmd {
+            CMD_STATUS => match self.device_request(Commands::GetStatus, 1)?.pop() {
+                Some(Packet::Str(v)) => Ok(Some(CommandResult::Str(v))),
+                _ => Err(DeviceError::UnexpectedResultFormat),
+            },
+            CMD_GET_POWER_CONSUMPTION => {
+                match self.device_request(Commands::GetConsumption, 1)?.pop() {
+                    Some(Packet::Float32(v)) => Ok(Some(CommandResult::Float32(v))),
+                    _ => Err(DeviceError::UnexpectedResultFormat),
+                }
+            }
+            // device answering the request is considered healthy
+            CMD_SELF_TEST => self
+                .get_power_state()
+                .map(|_| Some(CommandResult::Str(String::from("PASSED")))),
+            _ => Err(DeviceError::UnsupportedCommand),
+        }
+    }
+}
+
+impl PhysicalDevice for RemoteSocket {
+    /// Serial is the id of the device on the server
+    fn get_serial(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Manufactor is the address of the server
+    fn get_manufactor(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn get_power_state(&self) -> Result<PowerState, DeviceError> {
+        // id, name, type and power state
+        match self.device_request(Commands::DescribeDevice, 4)?.pop() {
+            Some(Packet::Byte(v)) => match commands::PowerState::try_from(v) {
+                Ok(commands::PowerState::ON) => Ok(PowerState::ON),
+                Ok(commands::PowerState::OFF) => Ok(PowerState::OFF),
+                Err(_) => Err(DeviceError::UnexpectedResultFormat),
+            },
+            _ => Err(DeviceError::UnexpectedResultFormat),
+        }
+    }
+
+    fn set_power_state(&mut self, state: PowerState) -> Result<PowerState, DeviceError> {
+        let cmd = match state {
+            PowerState::ON => Commands::PowerOn,
+            PowerState::OFF => Commands::PowerOff,
+        };
+        self.device_request(cmd, 0)?;
+        Ok(state)
+    }
+
+    fn get_supported_commands(&self) -> Result<Vec<DeviceCommand>, DeviceError> {
+        Ok(vec![CMD_STATUS, CMD_GET_POWER_CONSUMPTION, CMD_SELF_TEST])
+    }
+
+    fn execute_cmd_mut(
+        &mut self,
+        cmd: DeviceCommand,
+        _args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        self.execute(cmd)
+    }
+
+    fn execute_cmd(
+        &self,
+        cmd: DeviceCommand,
+        _args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        self.execute(cmd)
+    }
+}
+
+/// Binds logical devices of class "socket" to the devices of the remote server
+#[derive(Clone)]
+pub struct RemoteSocketFactory {
+    addr: String,
+    token: Option<String>,
+    timeout: Duration,
+    /// Remote device ids by logical device ids
+    devices: HashMap<xid::Id, String>,
+}
+
+impl RemoteSocketFactory {
+    pub fn new(addr: String) -> Self {
+        Self {
+            addr,
+            token: None,
+            timeout: DEFAULT_TIMEOUT,
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Token used to authenticate connections of the sockets
+    pub fn set_token(&mut self, token: String) -> &mut Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Limit of connecting to the server and of waiting for its response
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Maps logical device to the remote device
+    pub fn add_device(&mut self, logical: xid::Id, remote: String) -> &mut Self {
+        self.devices.insert(logical, remote);
+        self
+    }
+}
+
+impl PhysicalDeviceFactory for RemoteSocketFactory {
+    fn create_physical_device(&self, d: &Device) -> Result<Box<dyn PhysicalDevice>, &'static str> {
+        if d.get_class() != "socket" {
+            return Err("unsupported device class");
+        }
+        let id = self
+            .devices
+            .get(&d.get_id())
+            .ok_or("device is not mapped to the remote one")?;
+        match RemoteSocket::connect_timeout(
+            self.addr.clone(),
+            id.clone(),
+            self.token.as_deref(),
+            self.timeout,
+        ) {
+            Ok(socket) => Ok(Box::new(socket)),
+            Err(_) => Err("remote device is unavailable"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    use libprotocol::server::TcpServer;
+    use libserver::iotserver::IotServerBuilder;
+
+    use super::*;
+    use crate::room::{Binder, RoomBuilder};
+
+    fn start_server(socket: libserver::ACSocket) -> String {
+        start_server_with(IotServerBuilder::new().add_device(socket))
+    }
+
+    fn start_server_with(builder: &mut IotServerBuilder) -> String {
+        let server = builder
+            .set_addr(String::from("127.0.0.1:0"))
+            .add_admin_token(String::from("secret"))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    #[test]
+    fn test_remote_socket() {
+        let socket = libserver::ACSocket::new();
+        let id = socket.get_id().to_string();
+        let addr = start_server(socket);
+
+        let mut remote = RemoteSocket::connect(addr.clone(), id.clone(), Some("secret")).unwrap();
+        assert_eq!(remote.get_serial(), id);
+        assert!(matches!(
+            remote.set_power_state(PowerState::ON),
+            Ok(PowerState::ON)
+        ));
+        assert!(matches!(remote.get_power_state(), Ok(PowerState::ON)));
+        assert!(matches!(
+            remote.execute_cmd(CMD_STATUS, &CommandArgs::default()),
+            Ok(Some(CommandResult::Str(_)))
+        ));
+        assert!(matches!(
+            remote.execute_cmd(CMD_GET_POWER_CONSUMPTION, &CommandArgs::default()),
+            Ok(Some(CommandResult::Float32(_)))
+        ));
+        assert!(matches!(
+            remote.execute_cmd(
+                crate::commands::CMD_GET_TEMPERATURE,
+                &CommandArgs::default()
+            ),
+            Err(DeviceError::UnsupportedCommand)
+        ));
+        remote.set_power_state(PowerState::OFF).unwrap();
+        assert!(matches!(remote.get_power_state(), Ok(PowerState::OFF)));
+
+        assert!(matches!(
+            RemoteSocket::connect(addr.clone(), String::from("missing"), None),
+            Err(DeviceError::CommandFailed(_))
+        ));
+        assert!(matches!(
+            RemoteSocket::connect(addr, id, Some("wrong")),
+            Err(DeviceError::CommandFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let socket = libserver::ACSocket::new();
+        let id = socket.get_id().to_string();
+        let addr = start_server_with(
+            IotServerBuilder::new()
+                .set_idle_timeout(Duration::from_millis(100))
+                .add_device(socket),
+        );
+        let mut remote = RemoteSocket::connect(addr, id, Some("secret")).unwrap();
+        // server closes the idle connection, the request is repeated on the new one
+        thread::sleep(Duration::from_millis(300));
+        assert!(matches!(
+            remote.set_power_state(PowerState::ON),
+            Ok(PowerState::ON)
+        ));
+        assert!(matches!(remote.get_power_state(), Ok(PowerState::ON)));
+
+        // the thread panicked while holding the connection doesn't break the socket
+        let result = thread::scope(|s| {
+            s.spawn(|| {
+                let _client = remote.client.lock();
+                panic!("device thread failed");
+            })
+            .join()
+        });
+        assert!(result.is_err());
+        assert!(remote.client.is_poisoned());
+        assert!(matches!(remote.get_power_state(), Ok(PowerState::ON)));
+    }
+
+    #[test]
+    fn test_hung_server() {
+        let socket = libserver::ACSocket::new();
+        let id = socket.get_id().to_string();
+        // connection is accepted by the backlog, but requests are never answered
+        let hung = TcpListener::bind("127.0.0.1:0").unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            RemoteSocket::connect_timeout(
+                hung.local_addr().unwrap().to_string(),
+                id.clone(),
+                None,
+                Duration::from_millis(200)
+            ),
+            Err(DeviceError::CommandFailed(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // server answers the check of the device and stops answering
+        let server = TcpServer::bind(String::from("127.0.0.1:0")).unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        let remote_id = id.clone();
+        thread::spawn(move || {
+            let mut conn = server.incoming().next().unwrap().unwrap();
+            conn.recv_request().unwrap();
+            conn.recv_request().unwrap();
+            conn.send_response_vec(&[
+                Packet::Byte(Status::Ok as u8),
+                Packet::Str(remote_id),
+                Packet::Str(String::from("socket")),
+                Packet::Str(String::from(commands::SOCKET_KIND)),
+                Packet::Byte(commands::PowerState::OFF as u8),
+            ])
+            .unwrap();
+            while conn.recv_request().is_ok() {}
+        });
+        let remote =
+            RemoteSocket::connect_timeout(addr, id, None, Duration::from_millis(200)).unwrap();
+        let started = Instant::now();
+        assert!(matches!(
+            remote.get_power_state(),
+            Err(DeviceError::CommandFailed(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_remote_factory() {
+        let socket = libserver::ACSocket::new();
+        let id = socket.get_id().to_string();
+        let addr = start_server(socket);
+
+        let logical = Device::new(String::from("socket"));
+        let unmapped = Device::new(String::from("socket"));
+        let mut factory = RemoteSocketFactory::new(addr);
+        factory
+            .set_token(String::from("secret"))
+            .add_device(logical.get_id(), id);
+        assert!(factory
+            .create_physical_device(&Device::new(String::from("tsensor")))
+            .is_err());
+        assert!(factory.create_physical_device(&unmapped).is_err());
+
+        let mut room = RoomBuilder::new()
+            .set_label(String::from("Kitchen"))
+            .add_device(&logical)
+            .build();
+        room.accept_mut(&mut Binder::new(factory)).unwrap();
+        room.switch_power(PowerState::ON).unwrap();
+        assert!(matches!(
+            room.send_cmd(&logical.get_id(), CMD_STATUS, &CommandArgs::default()),
+            Ok(Some(CommandResult::Str(v))) if v.contains("ON")
+        ));
+    }
+}
@@ -1,5 +1,6 @@
 //! Defines module for report generation
 
+use crate::args::CommandArgs;
 use crate::commands::*;
 use crate::error::DeviceError;
 use crate::logical::*;
@@ -37,7 +38,7 @@ impl Reporter for SimpleReporter {
     }
 
     fn get_device_status(&self, room: &Room, device_id: &xid::Id) -> Result<String, DeviceError> {
-        match room.send_cmd(device_id, CMD_STATUS, Option::None) {
+        match room.send_cmd(device_id, CMD_STATUS, &CommandArgs::default()) {
             Ok(Some(CommandResult::Str(status))) => Ok(status),
             Err(v) => Err(v),
             _ => Err(DeviceError::UnexpectedResultFormat),
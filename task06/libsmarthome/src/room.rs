@@ -2,8 +2,10 @@
 
 use std::collections::HashMap;
 
+use crate::args::CommandArgs;
 use crate::device_ref::DeviceRef;
 use crate::error::DeviceError;
+use crate::events::{EventBus, HomeEvent};
 use crate::factory::*;
 use crate::logical::*;
 use crate::logical_device::*;
@@ -14,6 +16,8 @@ pub struct Room {
     id: xid::Id,
     label: String,
     devices: HashMap<xid::Id, Device>,
+    /// Bus of the home when room is added to the home
+    events: EventBus,
 }
 
 impl Default for Room {
@@ -42,6 +46,17 @@ impl Room {
             id: xid::new(),
             label: String::default(),
             devices: HashMap::new(),
+            events: EventBus::new(),
+        }
+    }
+
+    /// Creates room with known id, e.g. loaded from the topology
+    pub fn with_id(id: xid::Id) -> Self {
+        Self {
+            id,
+            label: String::default(),
+            devices: HashMap::new(),
+            events: EventBus::new(),
         }
     }
 
@@ -67,44 +82,130 @@ impl Room {
     }
 
     pub fn get_devices(&self) -> Vec<DeviceRef> {
-        self.devices
-            .iter()
-            .map(|d| DeviceRef::new(self.get_id(), self.get_label(), *d.0, d.1.get_class()))
-            .collect()
+        self.devices.values().map(|d| self.device_ref(d)).collect()
+    }
+
+    fn device_ref(&self, d: &Device) -> DeviceRef {
+        DeviceRef::new(self.get_id(), self.get_label(), d.get_id(), d.get_class())
+    }
+
+    /// Bus of the room events, it is the bus of the home when room is added to the home
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub(crate) fn set_events(&mut self, events: EventBus) {
+        self.events = events;
     }
 
     pub fn has_device(&self, id: xid::Id) -> bool {
         self.devices.contains_key(&id)
     }
 
+    pub fn get_device(&self, id: &xid::Id) -> Option<&Device> {
+        self.devices.get(id)
+    }
+
     pub fn add_device(&mut self, d: Device) -> xid::Id {
         let id = d.get_id();
+        self.events
+            .publish(HomeEvent::DeviceAdded(self.device_ref(&d)));
         self.devices.insert(d.get_id(), d);
         id
     }
 
     pub fn remove_device(&mut self, id: &xid::Id) -> &mut Self {
-        self.devices.remove(id);
+        if let Some(d) = self.devices.remove(id) {
+            self.events
+                .publish(HomeEvent::DeviceRemoved(self.device_ref(&d)));
+        }
         self
     }
 
     /// Set power state for devices in the room
     pub fn switch_power(&mut self, state: PowerState) -> Result<(), DeviceError> {
-        for d in self.devices.values_mut() {
-            match d.set_power_state(state) {
-                Ok(_) => (),
-                Err(v) => return Err(v),
+        let mut changed = vec![];
+        let result = self.devices.values_mut().try_for_each(|d| {
+            let previous = d.get_power_state().ok();
+            let current = d.set_power_state(state)?;
+            if previous != Some(current) {
+                changed.push((d.get_id(), current));
             }
+            Ok(())
+        });
+        for (id, current) in changed {
+            self.power_changed(&self.devices[&id], current);
+        }
+        result
+    }
+
+    /// Set power state for devices of the room shared by threads, devices are locked one by one
+    pub fn switch_power_shared(&self, state: PowerState) -> Result<(), DeviceError> {
+        for id in self.devices.keys() {
+            self.switch_device_power_shared(id, state)?;
         }
         Ok(())
     }
 
+    /// Set power state of the device in the room shared by threads,
+    /// returns the previous state if it can be read
+    pub fn switch_device_power_shared(
+        &self,
+        &device_id: &xid::Id,
+        state: PowerState,
+    ) -> Result<Option<PowerState>, DeviceError> {
+        let Some(d) = self.devices.get(&device_id) else {
+            return Err(DeviceError::DeviceIsMissing(device_id));
+        };
+        let previous = d.swap_power_state_shared(state)?;
+        if previous != Some(state) {
+            self.power_changed(d, state);
+        }
+        Ok(previous)
+    }
+
+    fn power_changed(&self, d: &Device, state: PowerState) {
+        self.events
+            .publish(HomeEvent::PowerChanged(self.device_ref(d), state));
+    }
+
+    /// Publishes result of the command executed by the device
+    fn executed(
+        &self,
+        device_id: &xid::Id,
+        cmd: DeviceCommand,
+        result: Result<Option<CommandResult>, DeviceError>,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        if let (Ok(v), Some(d)) = (&result, self.devices.get(device_id)) {
+            self.events.publish(HomeEvent::CommandExecuted(
+                self.device_ref(d),
+                cmd,
+                v.clone(),
+            ));
+        }
+        result
+    }
+
     /// send command to the device in room
     pub fn send_cmd(
         &self,
         &device_id: &xid::Id,
         cmd: DeviceCommand,
-        args: Option<Vec<String>>,
+        args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        match self.devices.get(&device_id) {
+            Some(d) => self.executed(&device_id, cmd, d.execute_cmd(cmd, args)),
+            None => Err(DeviceError::DeviceIsMissing(device_id)),
+        }
+    }
+
+    /// Executes command which doesn't change the device, result isn't published.
+    /// Used to poll sensors, so subscribers aren't flooded by readings
+    pub fn poll(
+        &self,
+        &device_id: &xid::Id,
+        cmd: DeviceCommand,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError> {
         match self.devices.get(&device_id) {
             Some(d) => d.execute_cmd(cmd, args),
@@ -112,16 +213,61 @@ impl Room {
         }
     }
 
-    /// Mutable visitor
+    /// send command to the device in room, command may change device state
+    pub fn send_cmd_mut(
+        &mut self,
+        &device_id: &xid::Id,
+        cmd: DeviceCommand,
+        args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        let result = match self.devices.get_mut(&device_id) {
+            Some(d) => d.execute_cmd_mut(cmd, args),
+            None => return Err(DeviceError::DeviceIsMissing(device_id)),
+        };
+        self.executed(&device_id, cmd, result)
+    }
+
+    /// send command which may change device state to the device in room shared by threads
+    pub fn send_cmd_shared(
+        &self,
+        &device_id: &xid::Id,
+        cmd: DeviceCommand,
+        args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        match self.devices.get(&device_id) {
+            Some(d) => self.executed(&device_id, cmd, d.execute_cmd_shared(cmd, args)),
+            None => Err(DeviceError::DeviceIsMissing(device_id)),
+        }
+    }
+
+    /// Mutable visitor, publishes binding changes made by the visitor
     pub fn accept_mut<T: DeviceVisitor>(&mut self, visitor: &mut T) -> Result<(), DeviceError> {
+        let mut changed = vec![];
+        let mut result = Ok(());
         for v in self.devices.values_mut() {
-            match visitor.accept_mut(v) {
-                Ok(true) => (),             // continue iteration
-                Ok(false) => return Ok(()), // break iteration
-                Err(v) => return Err(v),    // some error with device
+            let bound = v.is_bound();
+            let accepted = visitor.accept_mut(v);
+            if v.is_bound() != bound {
+                changed.push(v.get_id());
+            }
+            match accepted {
+                Ok(true) => (), // continue iteration
+                Ok(false) => break,
+                Err(v) => {
+                    // some error with device
+                    result = Err(v);
+                    break;
+                }
             }
         }
-        Ok(())
+        for id in changed {
+            let d = &self.devices[&id];
+            self.events.publish(match d.is_bound() {
+                true => HomeEvent::Bound(self.device_ref(d)),
+                false => HomeEvent::Unbound(self.device_ref(d)),
+            });
+        }
+        result
     }
 
     /// Immutable visitor
@@ -0,0 +1,719 @@
+//! Automation rules of the smart home.
+//!
+//! Rule is fired by its trigger when all its conditions hold, then it executes
+//! its actions on the selected devices. Rules are loaded from JSON or TOML file
+//! and evaluated periodically by `RuleEngine::evaluate`.
+//!
+//! ```toml
+//! [[rules]]
+//! name = "Heater off"
+//! trigger = { type = "above", value = 28.0, hysteresis = 2.0, reading = { devices = { room = "Kitchen", class = "tsensor" } } }
+//! actions = [{ type = "set_power", state = "off", devices = { room = "Kitchen", class = "socket" } }]
+//!
+//! [[rules]]
+//! name = "Save power"
+//! trigger = { type = "above", value = 3000.0, reading = { command = "get_power_consumption", aggregate = "sum" } }
+//! actions = [{ type = "set_power", state = "off", devices = { class = "socket" } }]
+//! ```
+//!
+//! Threshold triggers use hysteresis: trigger fires once the reading crosses
+//! the value and fires again only after the reading returns past the value
+//! by `hysteresis`. Time of day is UTC, see `clock`.
+
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::CommandArgs;
+use crate::clock::*;
+use crate::commands::CommandRegistry;
+use crate::device_ref::DeviceRef;
+use crate::error::{DeviceError, RuleError};
+use crate::events::{EventFilter, HomeEvent};
+use crate::format::FileFormat;
+use crate::home::Home;
+use crate::logical::*;
+
+/// Selector of devices, empty selector selects all devices of the home
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Selector {
+    /// Room id or label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    /// Device id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+impl Selector {
+    pub fn matches(&self, d: &DeviceRef) -> bool {
+        self.room
+            .as_ref()
+            .is_none_or(|r| *r == d.room_id().to_string() || r == d.room_name())
+            && self.class.as_ref().is_none_or(|c| c == d.device_class())
+            && self
+                .device
+                .as_ref()
+                .is_none_or(|id| *id == d.device_id().to_string())
+    }
+
+    fn select(&self, home: &Home) -> Vec<DeviceRef> {
+        let mut devices: Vec<DeviceRef> = home
+            .get_devices()
+            .into_iter()
+            .filter(|d| self.matches(d))
+            .collect();
+        devices.sort();
+        devices
+    }
+}
+
+/// Aggregate of readings of several devices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    /// Highest reading, e.g. any sensor reads above the value
+    #[default]
+    Max,
+    /// Lowest reading, e.g. any sensor reads below the value
+    Min,
+    /// Total of readings, e.g. consumption of the home
+    Sum,
+}
+
+fn default_reading_command() -> String {
+    String::from("get_temperature")
+}
+
+/// Numeric result of the command executed by the selected devices.
+/// Devices which fail the command or return NaN are skipped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reading {
+    #[serde(default)]
+    pub devices: Selector,
+    /// Command without arguments, `get_temperature` by default
+    #[serde(default = "default_reading_command")]
+    pub command: String,
+    #[serde(default)]
+    pub aggregate: Aggregate,
+}
+
+/// Trigger of the rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Reading rises above the value, fires again after it falls below `value - hysteresis`
+    Above {
+        reading: Reading,
+        value: f64,
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// Reading falls below the value, fires again after it rises above `value + hysteresis`
+    Below {
+        reading: Reading,
+        value: f64,
+        #[serde(default)]
+        hysteresis: f64,
+    },
+    /// Power state of any selected device is changed, to the given state if any
+    PowerChanged {
+        #[serde(default)]
+        devices: Selector,
+        #[serde(default)]
+        state: Option<PowerState>,
+    },
+    /// Periodically, period in seconds
+    Every { seconds: u64 },
+    /// Every day at the time of day `HH:MM`
+    At { time: String },
+}
+
+/// Condition of the rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    Above {
+        reading: Reading,
+        value: f64,
+    },
+    Below {
+        reading: Reading,
+        value: f64,
+    },
+    /// All selected devices are in the power state
+    Power {
+        devices: Selector,
+        state: PowerState,
+    },
+    /// Time of day is between `HH:MM` times, the range may pass midnight
+    Between {
+        from: String,
+        to: String,
+    },
+}
+
+/// Action of the rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    SetPower {
+        devices: Selector,
+        state: PowerState,
+    },
+    /// Command of the registry with `name=value` arguments
+    Command {
+        devices: Selector,
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+/// Rule of the automation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+}
+
+/// Serializable list of rules
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleSet {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn to_json(&self) -> Result<String, RuleError> {
+        serde_json::to_string_pretty(self).map_err(|v| RuleError::Format(v.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, RuleError> {
+        serde_json::from_str(s).map_err(|v| RuleError::Format(v.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, RuleError> {
+        toml::to_string(self).map_err(|v| RuleError::Format(v.to_string()))
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, RuleError> {
+        toml::from_str(s).map_err(|v| RuleError::Format(v.to_string()))
+    }
+
+    pub fn decode(s: &str, format: FileFormat) -> Result<Self, RuleError> {
+        match format {
+            FileFormat::Json => Self::from_json(s),
+            FileFormat::Toml => Self::from_toml(s),
+        }
+    }
+
+    /// Reads rules from the file, format is detected by extension
+    pub fn load(path: &Path) -> Result<Self, RuleError> {
+        Self::decode(&std::fs::read_to_string(path)?, FileFormat::from_path(path))
+    }
+}
+
+/// Outcome of the fired rule, errors of its actions per device
+#[derive(Debug, Clone)]
+pub struct RuleOutcome {
+    pub rule: String,
+    pub errors: Vec<(xid::Id, DeviceError)>,
+}
+
+/// State of the rule between evaluations
+struct RuleState {
+    rule: Rule,
+    /// Threshold trigger may fire
+    armed: bool,
+    /// Time of the last periodic firing
+    last_fired: SystemTime,
+}
+
+/// Engine of the rules, see module documentation
+pub struct RuleEngine<C: Clock> {
+    rules: Vec<RuleState>,
+    registry: CommandRegistry,
+    clock: C,
+    /// Time of the previous evaluation
+    evaluated: SystemTime,
+    events: Option<Receiver<HomeEvent>>,
+}
+
+impl<C: Clock> RuleEngine<C> {
+    /// Creates engine, commands of the rules are looked up in the registry
+    pub fn new(rules: RuleSet, registry: CommandRegistry, clock: C) -> Result<Self, RuleError> {
+        let now = clock.now();
+        let mut engine = Self {
+            rules: vec![],
+            registry,
+            clock,
+            evaluated: now,
+            events: None,
+        };
+        for rule in rules.rules {
+            engine
+                .validate(&rule)
+                .map_err(|v| RuleError::Invalid(rule.name.clone(), v))?;
+            engine.rules.push(RuleState {
+                rule,
+                armed: true,
+                last_fired: now,
+            });
+        }
+        Ok(engine)
+    }
+
+    /// Watches power changes of the home for `Trigger::PowerChanged`
+    pub fn watch(&mut self, home: &Home) -> &mut Self {
+        self.events = Some(home.events().subscribe_channel(&EventFilter::new()));
+        self
+    }
+
+    /// Evaluates rules and executes actions of the fired ones
+    pub fn evaluate(&mut self, home: &Home) -> Vec<RuleOutcome> {
+        let now = self.clock.now();
+        let events: Vec<HomeEvent> = match &self.events {
+            Some(v) => v.try_iter().collect(),
+            None => vec![],
+        };
+        let mut outcomes = vec![];
+        for i in 0..self.rules.len() {
+            if !self.triggered(i, home, now, &events) {
+                continue;
+            }
+            let state = &self.rules[i];
+            if !state
+                .rule
+                .conditions
+                .iter()
+                .all(|c| self.holds(c, home, now))
+            {
+                continue;
+            }
+            // threshold trigger fires once until it is re-armed
+            let state = &mut self.rules[i];
+            state.armed = false;
+            let rule = state.rule.clone();
+            let mut errors = vec![];
+            for action in &rule.actions {
+                errors.append(&mut execute_action(&self.registry, action, home));
+            }
+            outcomes.push(RuleOutcome {
+                rule: rule.name,
+                errors,
+            });
+        }
+        self.evaluated = now;
+        outcomes
+    }
+
+    fn triggered(&mut self, i: usize, home: &Home, now: SystemTime, events: &[HomeEvent]) -> bool {
+        let trigger = self.rules[i].rule.trigger.clone();
+        match trigger {
+            Trigger::Above {
+                reading,
+                value,
+                hysteresis,
+            } => self.threshold(
+                i,
+                self.read(&reading, home),
+                |v| v > value,
+                |v| v < value - hysteresis,
+            ),
+            Trigger::Below {
+                reading,
+                value,
+                hysteresis,
+            } => self.threshold(
+                i,
+                self.read(&reading, home),
+                |v| v < value,
+                |v| v > value + hysteresis,
+            ),
+            Trigger::PowerChanged { devices, state } => events.iter().any(|e| match e {
+                HomeEvent::PowerChanged(d, s) => {
+                    devices.matches(d) && state.is_none_or(|state| state == *s)
+                }
+                _ => false,
+            }),
+            Trigger::Every { seconds } => {
+                let state = &mut self.rules[i];
+                let period = Duration::from_secs(seconds);
+                match now.duration_since(state.last_fired) {
+                    Ok(v) if v >= period => {
+                        state.last_fired = now;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Trigger::At { time } => {
+                // validated by new
+                let at = latest_time_of_day(now, parse_time_of_day(&time).unwrap());
+                self.evaluated < at && at <= now
+            }
+        }
+    }
+
+    /// Re-arms the threshold trigger and checks whether it fires
+    fn threshold<A, R>(&mut self, i: usize, value: Option<f64>, fires: A, rearm: R) -> bool
+    where
+        A: Fn(f64) -> bool,
+        R: Fn(f64) -> bool,
+    {
+        let Some(v) = value else {
+            return false;
+        };
+        let state = &mut self.rules[i];
+        if !state.armed && rearm(v) {
+            state.armed = true;
+        }
+        state.armed && fires(v)
+    }
+
+    fn holds(&self, condition: &Condition, home: &Home, now: SystemTime) -> bool {
+        match condition {
+            Condition::Above { reading, value } => {
+                self.read(reading, home).is_some_and(|v| v > *value)
+            }
+            Condition::Below { reading, value } => {
+                self.read(reading, home).is_some_and(|v| v < *value)
+            }
+            Condition::Power { devices, state } => devices.select(home).iter().all(|d| {
+                home.get_room(d.room_id())
+                    .and_then(|r| r.get_device(d.device_id()))
+                    .is_some_and(|d| d.get_power_state().is_ok_and(|s| s == *state))
+            }),
+            Condition::Between { from, to } => {
+                // validated by new
+                let from = parse_time_of_day(from).unwrap();
+                let to = parse_time_of_day(to).unwrap();
+                let t = time_of_day(now);
+                match from <= to {
+                    true => from <= t && t < to,
+                    false => from <= t || t < to,
+                }
+            }
+        }
+    }
+
+    /// Aggregated reading, None if no device returns a number
+    fn read(&self, reading: &Reading, home: &Home) -> Option<f64> {
+        // validated by new
+        let cmd = self.registry.find(&reading.command).unwrap();
+        let values: Vec<f64> = reading
+            .devices
+            .select(home)
+            .iter()
+            .filter_map(|d| {
+                let room = home.get_room(d.room_id())?;
+                match room.poll(d.device_id(), cmd, &CommandArgs::new()) {
+                    Ok(Some(CommandResult::Float32(v))) => Some(v as f64),
+                    Ok(Some(CommandResult::Float64(v))) => Some(v),
+                    Ok(Some(CommandResult::Int32(v))) => Some(v as f64),
+                    Ok(Some(CommandResult::Int64(v))) => Some(v as f64),
+                    _ => None,
+                }
+            })
+            .filter(|v| !v.is_nan())
+            .collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(match reading.aggregate {
+            Aggregate::Max => values.into_iter().fold(f64::NEG_INFINITY, f64::max),
+            Aggregate::Min => values.into_iter().fold(f64::INFINITY, f64::min),
+            Aggregate::Sum => values.into_iter().sum(),
+        })
+    }
+
+    fn validate(&self, rule: &Rule) -> Result<(), String> {
+        let time = |s: &String| match parse_time_of_day(s) {
+            Some(_) => Ok(()),
+            None => Err(format!("invalid time of day {:?}", s)),
+        };
+        let reading = |r: &Reading| match self.registry.find(&r.command) {
+            Some(cmd)
+                if cmd.args.iter().all(|a| a.default.is_some())
+                    && [
+                        ResultType::Float32,
+                        ResultType::Float64,
+                        ResultType::Int32,
+                        ResultType::Int64,
+                    ]
+                    .contains(&cmd.result) =>
+            {
+                Ok(())
+            }
+            _ => Err(format!("{:?} is not a reading command", r.command)),
+        };
+        match &rule.trigger {
+            Trigger::Above {
+                reading: r,
+                hysteresis,
+                ..
+            }
+            | Trigger::Below {
+                reading: r,
+                hysteresis,
+                ..
+            } => {
+                reading(r)?;
+                if *hysteresis < 0.0 {
+                    return Err(String::from("hysteresis must not be negative"));
+                }
+            }
+            Trigger::Every { seconds: 0 } => return Err(String::from("period must be positive")),
+            Trigger::At { time: t } => time(t)?,
+            Trigger::PowerChanged { .. } | Trigger::Every { .. } => (),
+        }
+        for condition in &rule.conditions {
+            match condition {
+                Condition::Above { reading: r, .. } | Condition::Below { reading: r, .. } => {
+                    reading(r)?
+                }
+                Condition::Between { from, to } => {
+                    time(from)?;
+                    time(to)?;
+                }
+                Condition::Power { .. } => (),
+            }
+        }
+        for action in &rule.actions {
+            validate_action(&self.registry, action)?;
+        }
+        Ok(())
+    }
+}
+
+/// Executes action on the selected devices, returns errors per device.
+/// Command of the action must be validated by [`validate_action`].
+pub(crate) fn execute_action(
+    registry: &CommandRegistry,
+    action: &Action,
+    home: &Home,
+) -> Vec<(xid::Id, DeviceError)> {
+    let (Action::SetPower { devices, .. } | Action::Command { devices, .. }) = action;
+    let mut errors = vec![];
+    for d in devices.select(home) {
+        // device is selected from the home
+        let room = home.get_room(d.room_id()).unwrap();
+        let result = match action {
+            Action::SetPower { state, .. } => room
+                .switch_device_power_shared(d.device_id(), *state)
+                .map(|_| ()),
+            Action::Command { command, args, .. } => {
+                // validated by validate_action
+                let cmd = registry.find(command).unwrap();
+                cmd.parse_args(args)
+                    .and_then(|args| room.send_cmd_shared(d.device_id(), cmd, &args))
+                    .map(|_| ())
+            }
+        };
+        if let Err(v) = result {
+            errors.push((*d.device_id(), v));
+        }
+    }
+    errors
+}
+
+/// Checks that command of the action is registered and its arguments are valid
+pub(crate) fn validate_action(registry: &CommandRegistry, action: &Action) -> Result<(), String> {
+    if let Action::Command { command, args, .. } = action {
+        let Some(cmd) = registry.find(command) else {
+            return Err(format!("unknown command {:?}", command));
+        };
+        cmd.parse_args(args).map_err(|v| v.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::commands::{CMD_GET_TEMPERATURE, CMD_STATUS};
+    use crate::home::HomeBuilder;
+    use crate::logical_device::Device;
+    use crate::room::Room;
+    use crate::socket::ACSocket;
+    use crate::testing::{power, TestDevice};
+
+    const RULES: &str = r#"
+        [[rules]]
+        name = "Heater off"
+        trigger = { type = "above", value = 28.0, hysteresis = 2.0, reading = { devices = { room = "Kitchen", class = "tsensor" } } }
+        actions = [{ type = "set_power", state = "off", devices = { room = "Kitchen", class = "socket" } }]
+
+        [[rules]]
+        name = "Morning"
+        trigger = { type = "at", time = "07:00" }
+        actions = [{ type = "set_power", state = "on", devices = { class = "socket" } }]
+
+        [[rules]]
+        name = "Night status"
+        trigger = { type = "power_changed", state = "on" }
+        conditions = [{ type = "between", from = "22:00", to = "06:00" }]
+        actions = [{ type = "command", command = "status", devices = { class = "socket" } }]
+    "#;
+
+    /// Home with the kitchen of the thermometer and the heater socket
+    fn kitchen(temperature: &Arc<Mutex<f32>>) -> (Box<Home>, xid::Id) {
+        let sensor = TestDevice::new()
+            .set_power(PowerState::ON)
+            .set_temperature(temperature.clone())
+            .device("tsensor");
+        let mut heater = Device::new(String::from("socket"));
+        heater
+            .bind(Box::new(ACSocket::new(
+                String::from("2"),
+                String::from("IBM"),
+            )))
+            .unwrap();
+        let heater_id = heater.get_id();
+        let mut room = Room::new();
+        room.set_label(String::from("Kitchen"));
+        room.add_device(sensor);
+        room.add_device(heater);
+        let mut home = HomeBuilder::new().build();
+        home.add_room(Box::new(room));
+        (home, heater_id)
+    }
+
+    #[test]
+    fn test_rules() {
+        let temperature = Arc::new(Mutex::new(20.0));
+        let (home, heater) = kitchen(&temperature);
+        let kitchen = home.get_room_by_label("Kitchen").unwrap();
+        let kitchen_id = kitchen.get_id();
+        let clock = SimulatedClock::new(UNIX_EPOCH + Duration::from_secs(6 * 3600));
+        let rules = RuleSet::from_toml(RULES).unwrap();
+        assert_eq!(
+            RuleSet::from_json(&rules.to_json().unwrap()).unwrap(),
+            rules
+        );
+        let mut engine = RuleEngine::new(rules, CommandRegistry::new(), clock.clone()).unwrap();
+        engine.watch(&home);
+        let events = home
+            .events()
+            .subscribe_channel(EventFilter::new().set_room(kitchen_id));
+
+        let fired = |engine: &mut RuleEngine<SimulatedClock>| -> Vec<String> {
+            engine.evaluate(&home).into_iter().map(|o| o.rule).collect()
+        };
+        assert!(fired(&mut engine).is_empty());
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(fired(&mut engine), ["Morning"]);
+        assert_eq!(power(&home, &kitchen_id, &heater), PowerState::ON);
+        assert!(fired(&mut engine).is_empty());
+
+        // heater is switched off once until temperature falls by hysteresis
+        for (t, expected) in [(29.0, true), (29.5, false), (27.0, false), (29.0, false)] {
+            *temperature.lock().unwrap() = t;
+            kitchen.switch_power_shared(PowerState::ON).unwrap();
+            assert_eq!(
+                fired(&mut engine).contains(&String::from("Heater off")),
+                expected
+            );
+        }
+        assert_eq!(power(&home, &kitchen_id, &heater), PowerState::ON);
+        *temperature.lock().unwrap() = 25.0;
+        assert!(fired(&mut engine).is_empty());
+        *temperature.lock().unwrap() = 28.5;
+        assert_eq!(fired(&mut engine), ["Heater off"]);
+        assert_eq!(power(&home, &kitchen_id, &heater), PowerState::OFF);
+
+        // power change triggers the rule at night only
+        kitchen.switch_power_shared(PowerState::ON).unwrap();
+        assert!(fired(&mut engine).is_empty());
+        clock.advance(Duration::from_secs(16 * 3600));
+        kitchen.switch_power_shared(PowerState::OFF).unwrap();
+        kitchen.switch_power_shared(PowerState::ON).unwrap();
+        let status = home
+            .events()
+            .subscribe_channel(EventFilter::new().set_device(heater));
+        assert_eq!(fired(&mut engine), ["Night status"]);
+        assert!(matches!(
+            status.try_recv(),
+            Ok(HomeEvent::CommandExecuted(_, CMD_STATUS, _))
+        ));
+        // polling of the thermometer isn't published
+        let events: Vec<HomeEvent> = events.try_iter().collect();
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, HomeEvent::PowerChanged(..))));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, HomeEvent::CommandExecuted(_, CMD_GET_TEMPERATURE, _))));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let registry = CommandRegistry::new();
+        let rule = |trigger: Trigger, action: Action| RuleSet {
+            rules: vec![Rule {
+                name: String::from("Invalid"),
+                trigger,
+                conditions: vec![],
+                actions: vec![action],
+            }],
+        };
+        let every = Trigger::Every { seconds: 60 };
+        let status = |args: Vec<String>| Action::Command {
+            devices: Selector::default(),
+            command: String::from("status"),
+            args,
+        };
+        let reading = Reading {
+            devices: Selector::default(),
+            command: String::from("status"),
+            aggregate: Aggregate::Max,
+        };
+        for rules in [
+            rule(Trigger::Every { seconds: 0 }, status(vec![])),
+            rule(
+                Trigger::At {
+                    time: String::from("25:00"),
+                },
+                status(vec![]),
+            ),
+            rule(every.clone(), status(vec![String::from("celsius=1")])),
+            rule(
+                every.clone(),
+                Action::Command {
+                    devices: Selector::default(),
+                    command: String::from("unknown"),
+                    args: vec![],
+                },
+            ),
+            rule(
+                Trigger::Above {
+                    reading,
+                    value: 1.0,
+                    hysteresis: 0.0,
+                },
+                status(vec![]),
+            ),
+        ] {
+            assert!(matches!(
+                RuleEngine::new(rules, registry.clone(), SystemClock),
+                Err(RuleError::Invalid(_, _))
+            ));
+        }
+        assert!(RuleEngine::new(rule(every, status(vec![])), registry, SystemClock).is_ok());
+        assert!(matches!(
+            RuleSet::from_toml("[[rules]]\nname = 1"),
+            Err(RuleError::Format(_))
+        ));
+    }
+}
@@ -0,0 +1,486 @@
+//! Scenes: named desired states of devices across rooms, e.g. "Night" or "Away".
+//!
+//! Scene is applied all-or-nothing. Devices are checked before any change,
+//! then power states are set and commands are executed. Commands are limited
+//! to the settings of the device (see `PhysicalDevice::get_settings`), which
+//! are captured before the commands run. If a device fails, settings and power
+//! states which were already changed are restored.
+//!
+//! Devices are locked one by one, so commands of other threads may interleave
+//! with the scene, e.g. a rollback overrides a change made meanwhile.
+
+use crate::args::CommandArgs;
+use crate::error::DeviceError;
+use crate::home::Home;
+use crate::logical::*;
+use crate::logical_device::Device;
+use crate::room::Room;
+use crate::shared::SharedHome;
+
+/// Desired state of the device in the scene
+#[derive(Debug, Clone)]
+pub struct DeviceSetting {
+    pub room_id: xid::Id,
+    pub device_id: xid::Id,
+    pub power: Option<PowerState>,
+    /// Commands executed in order, e.g. `CMD_SET_THRESHOLD`
+    pub commands: Vec<(DeviceCommand, CommandArgs)>,
+}
+
+impl DeviceSetting {
+    fn device<'a>(&self, home: &'a Home) -> Result<(&'a Room, &'a Device), DeviceError> {
+        let room = home
+            .get_room(&self.room_id)
+            .ok_or(DeviceError::RoomIsMissing(self.room_id))?;
+        room.get_device(&self.device_id)
+            .map(|d| (room, d))
+            .ok_or(DeviceError::DeviceIsMissing(self.device_id))
+    }
+
+    /// Checks that the device is bound and the commands change its settings
+    fn check(&self, home: &Home) -> Result<(), DeviceError> {
+        let (_, d) = self.device(home)?;
+        d.get_power_state()?;
+        if self.commands.is_empty() {
+            return Ok(());
+        }
+        let supported = d.get_supported_commands()?;
+        let settings = d.get_settings()?;
+        for (cmd, args) in &self.commands {
+            if !supported.contains(cmd) {
+                return Err(DeviceError::UnsupportedCommand);
+            }
+            // other commands can't be rolled back
+            if !settings.iter().any(|(v, _)| v == cmd) {
+                return Err(DeviceError::UnsupportedOperation);
+            }
+            cmd.validate(args)?;
+        }
+        Ok(())
+    }
+
+    /// Sets power state, returns the previous one if it is changed
+    fn set_power(&self, home: &Home, state: PowerState) -> Result<Option<PowerState>, DeviceError> {
+        let (room, d) = self.device(home)?;
+        if d.get_power_state()? == state {
+            return Ok(None);
+        }
+        let previous = room.switch_device_power_shared(&self.device_id, state)?;
+        Ok(previous.filter(|v| *v != state))
+    }
+
+    fn execute(&self, home: &Home) -> Result<(), DeviceError> {
+        let (room, _) = self.device(home)?;
+        for (cmd, args) in &self.commands {
+            room.send_cmd_shared(&self.device_id, *cmd, args)?;
+        }
+        Ok(())
+    }
+
+    /// Restores settings and then power state of the device, every step is
+    /// tried, returns the first error
+    fn restore(&self, home: &Home, changes: &Changes) -> Result<(), DeviceError> {
+        let (room, _) = self.device(home)?;
+        let mut result = Ok(());
+        for (cmd, args) in changes.settings.iter().flatten() {
+            if let Err(v) = room.send_cmd_shared(&self.device_id, *cmd, args) {
+                result = result.and(Err(v));
+            }
+        }
+        if let Some(state) = changes.power {
+            if let Err(v) = room.switch_device_power_shared(&self.device_id, state) {
+                result = result.and(Err(v));
+            }
+        }
+        result
+    }
+}
+
+/// State of the device before the scene, Some if it may be changed
+#[derive(Debug, Clone, Default)]
+struct Changes {
+    power: Option<PowerState>,
+    settings: Option<Vec<(DeviceCommand, CommandArgs)>>,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        self.power.is_none() && self.settings.is_none()
+    }
+}
+
+/// Outcome of the scene for the device
+#[derive(Debug, Clone)]
+pub enum DeviceOutcome {
+    /// Device is set to the scene state
+    Applied,
+    /// Device failed the scene, value is the error
+    Failed(DeviceError),
+    /// Device isn't changed, since the scene failed before
+    Skipped,
+    /// Settings and power state of the device are restored
+    RolledBack,
+    /// Device can't be restored, cause is Some if the device failed the scene
+    RollbackFailed {
+        cause: Option<DeviceError>,
+        rollback: DeviceError,
+    },
+}
+
+/// Report of the applied scene, outcomes are in order of the scene devices
+#[derive(Debug, Clone)]
+pub struct SceneReport {
+    pub scene: String,
+    /// All devices are set to the scene state
+    pub applied: bool,
+    pub devices: Vec<(xid::Id, DeviceOutcome)>,
+}
+
+/// Scene describes desired states of devices
+#[derive(Debug, Clone, Default)]
+pub struct Scene {
+    name: String,
+    settings: Vec<DeviceSetting>,
+}
+
+impl Scene {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            settings: vec![],
+        }
+    }
+
+    /// Captures power states and settings of the bound devices of the home
+    pub fn capture(name: String, home: &Home) -> Self {
+        let mut devices = home.get_devices();
+        devices.sort();
+        let mut scene = Self::new(name);
+        for d in devices {
+            let Some(device) = home
+                .get_room(d.room_id())
+                .and_then(|r| r.get_device(d.device_id()))
+            else {
+                continue;
+            };
+            if let Ok(state) = device.get_power_state() {
+                scene.set_power(*d.room_id(), *d.device_id(), state);
+            }
+            // settings which can't be read aren't captured
+            for (cmd, args) in device.get_settings().unwrap_or_default() {
+                scene.add_command(*d.room_id(), *d.device_id(), cmd, args);
+            }
+        }
+        scene
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_settings(&self) -> &[DeviceSetting] {
+        &self.settings
+    }
+
+    fn setting(&mut self, room_id: xid::Id, device_id: xid::Id) -> &mut DeviceSetting {
+        let i = match self.settings.iter().position(|s| s.device_id == device_id) {
+            Some(i) => i,
+            None => {
+                self.settings.push(DeviceSetting {
+                    room_id,
+                    device_id,
+                    power: None,
+                    commands: vec![],
+                });
+                self.settings.len() - 1
+            }
+        };
+        &mut self.settings[i]
+    }
+
+    /// Sets desired power state of the device, replaces previous one
+    pub fn set_power(
+        &mut self,
+        room_id: xid::Id,
+        device_id: xid::Id,
+        state: PowerState,
+    ) -> &mut Self {
+        self.setting(room_id, device_id).power = Some(state);
+        self
+    }
+
+    /// Adds command executed by the device after power states are set,
+    /// the command must change a setting of the device
+    pub fn add_command(
+        &mut self,
+        room_id: xid::Id,
+        device_id: xid::Id,
+        cmd: DeviceCommand,
+        args: CommandArgs,
+    ) -> &mut Self {
+        self.setting(room_id, device_id).commands.push((cmd, args));
+        self
+    }
+
+    /// Applies scene to the home, see module documentation
+    pub fn apply(&self, home: &Home) -> SceneReport {
+        let mut changes = vec![Changes::default(); self.settings.len()];
+        let Err((failed, error)) = self.change(home, &mut changes) else {
+            return SceneReport {
+                scene: self.name.clone(),
+                applied: true,
+                devices: self
+                    .settings
+                    .iter()
+                    .map(|s| (s.device_id, DeviceOutcome::Applied))
+                    .collect(),
+            };
+        };
+        let mut error = Some(error);
+        let devices = self
+            .settings
+            .iter()
+            .zip(&changes)
+            .enumerate()
+            .map(|(i, (s, changes))| {
+                let restored = (!changes.is_empty()).then(|| s.restore(home, changes));
+                let cause = (i == failed).then(|| error.take().unwrap());
+                let outcome = match (restored, cause) {
+                    (Some(Err(rollback)), cause) => {
+                        DeviceOutcome::RollbackFailed { cause, rollback }
+                    }
+                    (_, Some(cause)) => DeviceOutcome::Failed(cause),
+                    (Some(Ok(())), None) => DeviceOutcome::RolledBack,
+                    (None, None) => DeviceOutcome::Skipped,
+                };
+                (s.device_id, outcome)
+            })
+            .collect();
+        SceneReport {
+            scene: self.name.clone(),
+            applied: false,
+            devices,
+        }
+    }
+
+    /// Checks devices and changes them, returns index of the failed device and its error
+    fn change(&self, home: &Home, changes: &mut [Changes]) -> Result<(), (usize, DeviceError)> {
+        for (i, s) in self.settings.iter().enumerate() {
+            s.check(home).map_err(|v| (i, v))?;
+        }
+        for (i, s) in self.settings.iter().enumerate() {
+            if let Some(state) = s.power {
+                changes[i].power = s.set_power(home, state).map_err(|v| (i, v))?;
+            }
+        }
+        for (i, s) in self.settings.iter().enumerate() {
+            if s.commands.is_empty() {
+                continue;
+            }
+            // settings are restored even if only some of the commands were executed
+            let (_, d) = s.device(home).map_err(|v| (i, v))?;
+            changes[i].settings = Some(d.get_settings().map_err(|v| (i, v))?);
+            s.execute(home).map_err(|v| (i, v))?;
+        }
+        Ok(())
+    }
+}
+
+impl SharedHome {
+    /// Applies scene while home is locked for reading, devices are locked one by one
+    pub fn apply_scene(&self, scene: &Scene) -> SceneReport {
+        scene.apply(&self.read())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::ArgValue;
+    use crate::commands::{CMD_SELF_TEST, CMD_SET_THRESHOLD, CMD_STATUS};
+    use crate::physical::PhysicalDevice;
+    use crate::socket::ACSocket;
+    use crate::testing::{power, TestDevice};
+    use crate::tsensor::TSensor;
+
+    fn device(class: &str, physical: Box<dyn PhysicalDevice>) -> Device {
+        let mut d = Device::new(String::from(class));
+        d.bind(physical).unwrap();
+        d
+    }
+
+    #[test]
+    fn test_scenes() {
+        let (serial, manufactor) = (String::from("1"), String::from("IBM"));
+        let devices = [
+            device(
+                "socket",
+                Box::new(ACSocket::new(serial.clone(), manufactor.clone())),
+            ),
+            device("tsensor", Box::new(TSensor::new(serial, manufactor))),
+            TestDevice::new().set_broken().device("socket"),
+            Device::new(String::from("socket")),
+        ];
+        let [socket, sensor, stuck, unbound] = devices.each_ref().map(|d| d.get_id());
+        let [socket_device, kitchen_devices @ ..] = devices;
+        let mut hall = Room::new();
+        hall.add_device(socket_device);
+        let mut kitchen = Room::new();
+        for d in kitchen_devices {
+            kitchen.add_device(d);
+        }
+        let (hall_id, kitchen_id) = (hall.get_id(), kitchen.get_id());
+        let home = SharedHome::default();
+        home.write().add_room(Box::new(hall));
+        home.write().add_room(Box::new(kitchen));
+
+        let mut evening = Scene::new(String::from("Evening"));
+        evening
+            .set_power(hall_id, socket, PowerState::ON)
+            .add_command(
+                kitchen_id,
+                sensor,
+                CMD_SET_THRESHOLD,
+                CommandArgs::from([("celsius", ArgValue::Float(30.0))]),
+            )
+            .set_power(kitchen_id, sensor, PowerState::ON);
+        let report = home.apply_scene(&evening);
+        assert!(report.applied);
+        assert_eq!(report.devices.len(), 2);
+        assert!(matches!(
+            home.send_cmd(&kitchen_id, &sensor, CMD_STATUS, &CommandArgs::new()),
+            Ok(Some(CommandResult::Str(v))) if v.contains("power state = ON") && v.contains("threshold = 30C")
+        ));
+
+        // unbound device isn't captured, stuck device is already off
+        let captured = Scene::capture(String::from("Before"), &home.read());
+        assert_eq!(captured.get_settings().len(), 3);
+        assert!(captured
+            .get_settings()
+            .iter()
+            .all(|s| s.commands.len() == (s.device_id == sensor) as usize));
+
+        // stuck device fails, changed devices are rolled back
+        let mut night = Scene::new(String::from("Night"));
+        night
+            .set_power(hall_id, socket, PowerState::OFF)
+            .set_power(kitchen_id, sensor, PowerState::OFF)
+            .set_power(kitchen_id, stuck, PowerState::ON);
+        let report = home.apply_scene(&night);
+        assert!(!report.applied);
+        assert!(matches!(
+            report.devices.as_slice(),
+            [
+                (_, DeviceOutcome::RolledBack),
+                (_, DeviceOutcome::RolledBack),
+                (_, DeviceOutcome::Failed(DeviceError::CommandFailed(_)))
+            ]
+        ));
+        assert_eq!(power(&home.read(), &hall_id, &socket), PowerState::ON);
+        assert_eq!(power(&home.read(), &kitchen_id, &sensor), PowerState::ON);
+
+        // devices are checked before any change
+        for (room, id, cmd) in [
+            (kitchen_id, unbound, CMD_STATUS),
+            (hall_id, socket, CMD_SET_THRESHOLD),
+            // isn't a setting, so it can't be rolled back
+            (hall_id, socket, CMD_SELF_TEST),
+        ] {
+            let mut broken = Scene::new(String::from("Broken"));
+            broken
+                .set_power(kitchen_id, sensor, PowerState::OFF)
+                .add_command(room, id, cmd, CommandArgs::new());
+            let report = home.apply_scene(&broken);
+            assert!(matches!(
+                report.devices.as_slice(),
+                [(_, DeviceOutcome::Skipped), (_, DeviceOutcome::Failed(_))]
+            ));
+        }
+        assert_eq!(power(&home.read(), &kitchen_id, &sensor), PowerState::ON);
+
+        let mut away = Scene::new(String::from("Away"));
+        away.set_power(hall_id, socket, PowerState::OFF).set_power(
+            kitchen_id,
+            sensor,
+            PowerState::OFF,
+        );
+        assert!(home.apply_scene(&away).applied);
+        assert!(home.apply_scene(&captured).applied);
+        assert_eq!(power(&home.read(), &hall_id, &socket), PowerState::ON);
+        assert_eq!(power(&home.read(), &kitchen_id, &sensor), PowerState::ON);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let (serial, manufactor) = (String::from("1"), String::from("IBM"));
+        let mut room = Room::new();
+        let sensor = room.add_device(device(
+            "tsensor",
+            Box::new(TSensor::new(serial, manufactor)),
+        ));
+        let heater = room.add_device(TestDevice::new().device("heater"));
+        let room_id = room.get_id();
+        let home = SharedHome::default();
+        home.write().add_room(Box::new(room));
+        let threshold = |celsius| CommandArgs::from([("celsius", ArgValue::Float(celsius))]);
+        let status = |id| match home.send_cmd(&room_id, id, CMD_STATUS, &CommandArgs::new()) {
+            Ok(Some(CommandResult::Str(v))) => v,
+            v => panic!("unexpected status {:?}", v),
+        };
+
+        // heater rejects the threshold after the sensor is changed
+        let mut hot = Scene::new(String::from("Hot"));
+        hot.set_power(room_id, sensor, PowerState::ON)
+            .add_command(room_id, sensor, CMD_SET_THRESHOLD, threshold(35.0))
+            .set_power(room_id, heater, PowerState::ON)
+            .add_command(room_id, heater, CMD_SET_THRESHOLD, threshold(60.0));
+        let report = home.apply_scene(&hot);
+        assert!(!report.applied);
+        assert!(matches!(
+            report.devices.as_slice(),
+            [
+                (_, DeviceOutcome::RolledBack),
+                (_, DeviceOutcome::Failed(DeviceError::InvalidArgument(_)))
+            ]
+        ));
+        assert!(status(&sensor).contains("power state = OFF"));
+        assert!(status(&sensor).contains("threshold = 25C"));
+        assert_eq!(status(&heater), "power state = OFF, threshold = 25C");
+
+        let mut fixed = Scene::new(String::from("Hot"));
+        fixed
+            .add_command(room_id, sensor, CMD_SET_THRESHOLD, threshold(35.0))
+            .add_command(room_id, heater, CMD_SET_THRESHOLD, threshold(40.0));
+        assert!(home.apply_scene(&fixed).applied);
+        assert!(status(&sensor).contains("threshold = 35C"));
+        assert_eq!(status(&heater), "power state = OFF, threshold = 40C");
+
+        // locked device fails and its threshold can't be restored, power is still restored
+        let mut locked = Room::new();
+        let lamp = locked.add_device(TestDevice::new().set_locked().device("lamp"));
+        let locked_id = locked.get_id();
+        home.write().add_room(Box::new(locked));
+        let mut bright = Scene::new(String::from("Bright"));
+        bright
+            .set_power(room_id, sensor, PowerState::ON)
+            .set_power(locked_id, lamp, PowerState::ON)
+            .add_command(locked_id, lamp, CMD_SET_THRESHOLD, threshold(30.0));
+        let report = home.apply_scene(&bright);
+        assert!(!report.applied);
+        assert!(matches!(
+            report.devices.as_slice(),
+            [
+                (_, DeviceOutcome::RolledBack),
+                (
+                    _,
+                    DeviceOutcome::RollbackFailed {
+                        cause: Some(DeviceError::CommandFailed(_)),
+                        rollback: DeviceError::CommandFailed(_)
+                    }
+                )
+            ]
+        ));
+        assert_eq!(power(&home.read(), &room_id, &sensor), PowerState::OFF);
+        assert_eq!(power(&home.read(), &locked_id, &lamp), PowerState::OFF);
+    }
+}
@@ -0,0 +1,770 @@
+//! Scheduler of actions on rooms and devices, see `rules::Action`.
+//!
+//! Jobs run at cron times (`minute hour day month weekday`), at sunrise or
+//! sunset with offset, or once after a delay. Times are UTC, sun events are
+//! computed offline from the location of the home.
+//!
+//! Scheduler doesn't own a thread: `Scheduler::run_pending` runs jobs which are
+//! due by its clock, so the caller polls it and tests move a simulated clock.
+//! Runs missed while the scheduler wasn't polled, e.g. after downtime, are
+//! handled according to `MissedRuns` of the job. Runs missed while the process
+//! was stopped are found by restoring the saved `JobState` of the jobs, pending
+//! one-shot jobs are saved and scheduled again with their due times.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::*;
+use crate::commands::CommandRegistry;
+use crate::error::{DeviceError, ScheduleError};
+use crate::home::Home;
+use crate::rules::{execute_action, validate_action, Action};
+
+/// Limit of runs of the job at once, see `MissedRuns::RunAll`
+pub const MAX_MISSED_RUNS: usize = 100;
+
+/// Default delay after which due run is considered missed
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(60);
+
+/// Cron expression `minute hour day month weekday`.
+///
+/// Fields are `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n` and their
+/// comma separated lists. Weekday 0 or 7 is Sunday. If both day and weekday
+/// are restricted, either of them matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parses cron field to the bit set, `*` is reported by the flag
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), String> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(v) if v > 0 => (range, v),
+                _ => return Err(format!("invalid step {:?}", part)),
+            },
+            None => (part, 1),
+        };
+        let number = |s: &str| match s.parse::<u32>() {
+            Ok(v) if (min..=max).contains(&v) => Ok(v),
+            _ => Err(format!("{:?} is out of range {}-{}", s, min, max)),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (number(from)?, number(to)?),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if from > to {
+            return Err(format!("invalid range {:?}", range));
+        }
+        for v in (from..=to).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok((bits, field == "*"))
+}
+
+fn has(bits: u64, v: u64) -> bool {
+    bits & (1 << v) != 0
+}
+
+impl CronExpr {
+    pub fn parse(s: &str) -> Result<Self, ScheduleError> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ScheduleError::InvalidCron(format!(
+                "5 fields expected in {:?}",
+                s
+            )));
+        }
+        let field = |i: usize, min, max| {
+            parse_field(fields[i], min, max).map_err(ScheduleError::InvalidCron)
+        };
+        let (days, any_day) = field(2, 1, 31)?;
+        let (weekdays, any_weekday) = field(4, 0, 7)?;
+        let expr = Self {
+            minutes: field(0, 0, 59)?.0,
+            hours: field(1, 0, 23)?.0,
+            days,
+            months: field(3, 1, 12)?.0,
+            // Sunday is 0 or 7
+            weekdays: weekdays | (weekdays >> 7),
+            any_day,
+            any_weekday,
+        };
+        match expr.next_after(UNIX_EPOCH) {
+            Some(_) => Ok(expr),
+            None => Err(ScheduleError::InvalidCron(format!("{:?} never matches", s))),
+        }
+    }
+
+    fn matches_day(&self, days: u64) -> bool {
+        let (_, month, day) = civil_date(days);
+        let by_day = has(self.days, day as u64);
+        let by_weekday = has(self.weekdays, weekday(days) as u64);
+        has(self.months, month as u64)
+            && match (self.any_day, self.any_weekday) {
+                (false, false) => by_day || by_weekday,
+                _ => by_day && by_weekday,
+            }
+    }
+
+    /// The first matching minute after the time, None if it isn't found in 5 years
+    pub fn next_after(&self, t: SystemTime) -> Option<SystemTime> {
+        let mut minute = unix_seconds(t) / 60 + 1;
+        let end = minute + 5 * 366 * 24 * 60;
+        while minute < end {
+            let days = minute / (24 * 60);
+            if !self.matches_day(days) {
+                minute = (days + 1) * 24 * 60;
+            } else if !has(self.hours, minute / 60 % 24) {
+                minute = (minute / 60 + 1) * 60;
+            } else if !has(self.minutes, minute % 60) {
+                minute += 1;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(minute * 60));
+            }
+        }
+        None
+    }
+}
+
+/// Location of the home, degrees. Longitude is positive to the east.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+impl Location {
+    /// Time of the sun event at the day since the unix epoch,
+    /// None if the sun doesn't rise or set that day (polar day or night)
+    pub fn sun_event(&self, days: u64, event: SunEvent) -> Option<SystemTime> {
+        // sunrise equation, accurate to a couple of minutes
+        let julian = |days: f64| days + 2440587.5;
+        let n = (julian(days as f64 + 0.5) - 2451545.0 + 0.0008).round();
+        let mean_noon = n - self.longitude / 360.0;
+        let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0);
+        let m = anomaly.to_radians();
+        let center = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+        let ecliptic = (anomaly + center + 180.0 + 102.9372)
+            .rem_euclid(360.0)
+            .to_radians();
+        let transit = 2451545.0 + mean_noon + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic).sin();
+        let declination = (ecliptic.sin() * 23.4397_f64.to_radians().sin()).asin();
+        let latitude = self.latitude.to_radians();
+        let cos_hour_angle = ((-0.833_f64).to_radians().sin() - latitude.sin() * declination.sin())
+            / (latitude.cos() * declination.cos());
+        if !(-1.0..=1.0).contains(&cos_hour_angle) {
+            return None;
+        }
+        let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+        let time = match event {
+            SunEvent::Sunrise => transit - hour_angle,
+            SunEvent::Sunset => transit + hour_angle,
+        };
+        let seconds = (time - julian(0.0)) * DAY as f64;
+        (seconds >= 0.0).then(|| UNIX_EPOCH + Duration::from_secs_f64(seconds))
+    }
+}
+
+/// Time of the job
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum When {
+    Cron(CronExpr),
+    /// Sun event with offset in seconds, negative offset is before the event
+    Sun(SunEvent, i64),
+    /// Once at the time
+    Once(SystemTime),
+}
+
+/// Handling of the runs missed by the scheduler, e.g. after downtime.
+/// Run is missed if it is due by more than tolerance of the scheduler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissedRuns {
+    /// Missed runs are dropped
+    Skip,
+    /// Missed runs are replaced by single run
+    #[default]
+    RunOnce,
+    /// Every missed run is executed, up to `MAX_MISSED_RUNS` latest ones
+    RunAll,
+}
+
+/// Identifier of the job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JobId(u64);
+
+struct Job {
+    id: JobId,
+    name: String,
+    when: When,
+    missed: MissedRuns,
+    actions: Vec<Action>,
+    /// Time of the next run, None if job is finished
+    next: Option<SystemTime>,
+    /// Time of the last due run, whether it was executed or skipped
+    last: Option<SystemTime>,
+}
+
+/// State of the job saved across restarts, see `Scheduler::save_state`.
+/// Jobs are matched by names, as their ids differ after restart.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobState {
+    pub name: String,
+    /// Last due run of the repeated job
+    #[serde(default)]
+    pub last_run: Option<SystemTime>,
+    /// Pending one-shot job, it is scheduled again by `Scheduler::restore_state`
+    #[serde(default)]
+    pub timer: Option<TimerState>,
+}
+
+/// Pending one-shot job, see `Scheduler::add_timer`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimerState {
+    pub due: SystemTime,
+    pub actions: Vec<Action>,
+}
+
+/// Outcome of the job run, errors of its actions per device
+#[derive(Debug, Clone)]
+pub struct JobOutcome {
+    pub job: JobId,
+    pub name: String,
+    /// Time the run was scheduled at
+    pub scheduled: SystemTime,
+    pub errors: Vec<(xid::Id, DeviceError)>,
+}
+
+/// Scheduler of jobs, see module documentation
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    registry: CommandRegistry,
+    location: Option<Location>,
+    tolerance: Duration,
+    jobs: Vec<Job>,
+    next_id: u64,
+}
+
+impl<C: Clock> Scheduler<C> {
+    /// Creates scheduler, commands of the actions are looked up in the registry
+    pub fn new(registry: CommandRegistry, clock: C) -> Self {
+        Self {
+            clock,
+            registry,
+            location: None,
+            tolerance: DEFAULT_TOLERANCE,
+            jobs: vec![],
+            next_id: 1,
+        }
+    }
+
+    /// Sets location of the home for the sun events
+    pub fn set_location(&mut self, location: Location) -> &mut Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Sets delay after which due run is considered missed
+    pub fn set_tolerance(&mut self, tolerance: Duration) -> &mut Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Adds job running at times of the cron expression
+    pub fn add_cron(
+        &mut self,
+        name: &str,
+        expr: &str,
+        missed: MissedRuns,
+        actions: Vec<Action>,
+    ) -> Result<JobId, ScheduleError> {
+        let expr = CronExpr::parse(expr)?;
+        self.add(name, When::Cron(expr), missed, actions)
+    }
+
+    /// Adds job running every day at the sun event with offset
+    pub fn add_sun(
+        &mut self,
+        name: &str,
+        event: SunEvent,
+        offset: i64,
+        missed: MissedRuns,
+        actions: Vec<Action>,
+    ) -> Result<JobId, ScheduleError> {
+        if self.location.is_none() {
+            return Err(ScheduleError::NoLocation);
+        }
+        self.add(name, When::Sun(event, offset), missed, actions)
+    }
+
+    /// Adds job running once after the delay, e.g. "turn off in 30 minutes".
+    /// Run missed while the scheduler isn't polled is executed once
+    pub fn add_timer(
+        &mut self,
+        name: &str,
+        delay: Duration,
+        actions: Vec<Action>,
+    ) -> Result<JobId, ScheduleError> {
+        let at = self.clock.now() + delay;
+        self.add(name, When::Once(at), MissedRuns::RunOnce, actions)
+    }
+
+    /// Returns false if there is no such job
+    pub fn cancel(&mut self, id: JobId) -> bool {
+        let count = self.jobs.len();
+        self.jobs.retain(|j| j.id != id);
+        self.jobs.len() != count
+    }
+
+    /// Time of the next run of the job, None if it is finished or cancelled
+    pub fn next_run(&self, id: JobId) -> Option<SystemTime> {
+        self.jobs.iter().find(|j| j.id == id).and_then(|j| j.next)
+    }
+
+    /// Last runs of the repeated jobs which have run and pending one-shot jobs
+    pub fn save_state(&self) -> Vec<JobState> {
+        self.jobs
+            .iter()
+            .filter_map(|j| match j.when {
+                When::Once(due) => Some(JobState {
+                    name: j.name.clone(),
+                    last_run: None,
+                    timer: Some(TimerState {
+                        due,
+                        actions: j.actions.clone(),
+                    }),
+                }),
+                _ => j.last.map(|last_run| JobState {
+                    name: j.name.clone(),
+                    last_run: Some(last_run),
+                    timer: None,
+                }),
+            })
+            .collect()
+    }
+
+    /// Restores last runs of the repeated jobs, which are added by then, and
+    /// schedules saved one-shot jobs unless jobs with their names are added.
+    /// Runs between the last run or due time and now are missed and handled
+    /// by `run_pending`. Fails if saved actions are invalid.
+    pub fn restore_state(&mut self, states: &[JobState]) -> Result<&mut Self, ScheduleError> {
+        for state in states {
+            if let Some(timer) = &state.timer {
+                if !self.jobs.iter().any(|j| j.name == state.name) {
+                    let (when, actions) = (When::Once(timer.due), timer.actions.clone());
+                    self.add(&state.name, when, MissedRuns::RunOnce, actions)?;
+                }
+                continue;
+            }
+            let Some(last_run) = state.last_run else {
+                continue;
+            };
+            let Some(i) = self
+                .jobs
+                .iter()
+                .position(|j| j.name == state.name && !matches!(j.when, When::Once(_)))
+            else {
+                continue;
+            };
+            let next = self.next_time(&self.jobs[i].when, last_run);
+            let job = &mut self.jobs[i];
+            job.last = Some(last_run);
+            job.next = next;
+        }
+        Ok(self)
+    }
+
+    /// Runs jobs which are due, outcomes are sorted by scheduled time.
+    /// Finished one-shot jobs are removed.
+    pub fn run_pending(&mut self, home: &Home) -> Vec<JobOutcome> {
+        let now = self.clock.now();
+        let mut runs = vec![];
+        for i in 0..self.jobs.len() {
+            let job = &self.jobs[i];
+            let mut due = VecDeque::new();
+            let mut next = job.next;
+            while let Some(t) = next.filter(|t| *t <= now) {
+                if due.len() == MAX_MISSED_RUNS {
+                    due.pop_front();
+                }
+                due.push_back(t);
+                next = self.next_time(&job.when, t);
+            }
+            let last = due.back().copied().or(job.last);
+            let missed =
+                |t: &SystemTime| now.duration_since(*t).unwrap_or_default() > self.tolerance;
+            let due: Vec<SystemTime> = match job.missed {
+                MissedRuns::Skip => due.into_iter().filter(|t| !missed(t)).collect(),
+                MissedRuns::RunOnce => due.pop_back().into_iter().collect(),
+                MissedRuns::RunAll => due.into(),
+            };
+            runs.extend(due.into_iter().map(|t| (t, i)));
+            self.jobs[i].next = next;
+            self.jobs[i].last = last;
+        }
+        runs.sort_by_key(|(t, _)| *t);
+        let outcomes = runs
+            .into_iter()
+            .map(|(scheduled, i)| {
+                let job = &self.jobs[i];
+                JobOutcome {
+                    job: job.id,
+                    name: job.name.clone(),
+                    scheduled,
+                    errors: job
+                        .actions
+                        .iter()
+                        .flat_map(|a| execute_action(&self.registry, a, home))
+                        .collect(),
+                }
+            })
+            .collect();
+        self.jobs.retain(|j| j.next.is_some());
+        outcomes
+    }
+
+    fn add(
+        &mut self,
+        name: &str,
+        when: When,
+        missed: MissedRuns,
+        actions: Vec<Action>,
+    ) -> Result<JobId, ScheduleError> {
+        if self.jobs.iter().any(|j| j.name == name) {
+            return Err(ScheduleError::DuplicateName(String::from(name)));
+        }
+        for action in &actions {
+            validate_action(&self.registry, action).map_err(ScheduleError::InvalidAction)?;
+        }
+        let id = JobId(self.next_id);
+        self.next_id += 1;
+        let next = match when {
+            When::Once(t) => Some(t),
+            _ => self.next_time(&when, self.clock.now()),
+        };
+        self.jobs.push(Job {
+            id,
+            name: String::from(name),
+            when,
+            missed,
+            actions,
+            next,
+            last: None,
+        });
+        Ok(id)
+    }
+
+    /// The first time of the repeated job after the time
+    fn next_time(&self, when: &When, t: SystemTime) -> Option<SystemTime> {
+        match when {
+            When::Cron(expr) => expr.next_after(t),
+            When::Sun(event, offset) => {
+                // sun jobs are added with location
+                let location = self.location.unwrap();
+                let shift = Duration::from_secs(offset.unsigned_abs());
+                // polar days and nights are skipped
+                (unix_days(t).saturating_sub(1)..unix_days(t) + 366)
+                    .filter_map(|days| location.sun_event(days, *event))
+                    .filter_map(|v| match *offset < 0 {
+                        true => v.checked_sub(shift),
+                        false => v.checked_add(shift),
+                    })
+                    .find(|v| *v > t)
+            }
+            When::Once(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::home::HomeBuilder;
+    use crate::logical::PowerState;
+    use crate::logical_device::Device;
+    use crate::room::Room;
+    use crate::rules::Selector;
+    use crate::socket::ACSocket;
+
+    /// 2024-03-01, Friday
+    const FRIDAY: u64 = 19783;
+
+    fn at(days: u64, hour: u64, minute: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(days * DAY + hour * 3600 + minute * 60)
+    }
+
+    #[test]
+    fn test_cron() {
+        let next = |expr: &str, t| CronExpr::parse(expr).unwrap().next_after(t);
+        assert_eq!(
+            next("30 7 * * 1-5", at(FRIDAY, 20, 0)),
+            Some(at(FRIDAY + 3, 7, 30))
+        );
+        assert_eq!(
+            next("*/15 * * * *", at(FRIDAY, 10, 7)),
+            Some(at(FRIDAY, 10, 15))
+        );
+        assert_eq!(next("0 0 1 1,7 *", at(FRIDAY, 0, 0)), Some(at(19905, 0, 0)));
+        // day or weekday
+        assert_eq!(
+            next("0 12 1 * 7", at(FRIDAY, 12, 0)),
+            Some(at(FRIDAY + 2, 12, 0))
+        );
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "0 0 31 2 *",
+        ] {
+            assert!(matches!(
+                CronExpr::parse(invalid),
+                Err(ScheduleError::InvalidCron(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_sun_events() {
+        // London, 2024-06-21: sunrise 03:43, sunset 20:21 UTC
+        let london = Location {
+            latitude: 51.5072,
+            longitude: -0.1276,
+        };
+        let midsummer = 19895;
+        for (event, expected) in [
+            (SunEvent::Sunrise, at(midsummer, 3, 43)),
+            (SunEvent::Sunset, at(midsummer, 20, 21)),
+        ] {
+            let t = london.sun_event(midsummer, event).unwrap();
+            let diff = t
+                .duration_since(expected)
+                .or_else(|_| expected.duration_since(t))
+                .unwrap();
+            assert!(diff < Duration::from_secs(180), "{:?} {:?}", event, diff);
+        }
+        let svalbard = Location {
+            latitude: 78.2,
+            longitude: 15.6,
+        };
+        assert_eq!(svalbard.sun_event(midsummer, SunEvent::Sunset), None);
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let mut socket = Device::new(String::from("socket"));
+        socket
+            .bind(Box::new(ACSocket::new(
+                String::from("1"),
+                String::from("IBM"),
+            )))
+            .unwrap();
+        let mut room = Room::new();
+        room.set_label(String::from("Kitchen"));
+        room.add_device(socket);
+        let mut home = HomeBuilder::new().build();
+        let kitchen = home.add_room(Box::new(room));
+        home.switch_power(PowerState::ON).unwrap();
+
+        let clock = SimulatedClock::new(at(FRIDAY, 0, 0));
+        let mut scheduler = Scheduler::new(CommandRegistry::new(), clock.clone());
+        let sockets = Selector {
+            class: Some(String::from("socket")),
+            ..Selector::default()
+        };
+        let status = vec![Action::Command {
+            devices: sockets.clone(),
+            command: String::from("status"),
+            args: vec![],
+        }];
+        let off = vec![Action::SetPower {
+            devices: sockets.clone(),
+            state: PowerState::OFF,
+        }];
+        for (name, missed) in [
+            ("all", MissedRuns::RunAll),
+            ("skip", MissedRuns::Skip),
+            ("once", MissedRuns::RunOnce),
+        ] {
+            scheduler
+                .add_cron(name, "0 * * * *", missed, status.clone())
+                .unwrap();
+        }
+        let timer = scheduler
+            .add_timer("off", Duration::from_secs(30 * 60), off)
+            .unwrap();
+        let mut run = |minutes: u64| -> Vec<(String, SystemTime)> {
+            clock.advance(Duration::from_secs(minutes * 60));
+            scheduler
+                .run_pending(&home)
+                .into_iter()
+                .inspect(|o| assert!(o.errors.is_empty()))
+                .map(|o| (o.name, o.scheduled))
+                .collect()
+        };
+
+        assert_eq!(run(30), [(String::from("off"), at(FRIDAY, 0, 30))]);
+        let room = home.get_room(&kitchen).unwrap();
+        assert!(room.get_devices().iter().all(|d| room
+            .get_device(d.device_id())
+            .unwrap()
+            .get_power_state()
+            .unwrap()
+            == PowerState::OFF));
+        let mut hourly: Vec<String> = run(30).into_iter().map(|(n, _)| n).collect();
+        hourly.sort();
+        assert_eq!(hourly, ["all", "once", "skip"]);
+
+        // downtime: 02:00, 03:00 and 04:00 are missed
+        let runs = run(3 * 60 + 30);
+        assert_eq!(runs.iter().filter(|(n, _)| n == "all").count(), 3);
+        assert!(runs.contains(&(String::from("once"), at(FRIDAY, 4, 0))));
+        assert_eq!(runs.len(), 4);
+        assert!(runs.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert!(run(0).is_empty());
+        assert_eq!(scheduler.next_run(timer), None);
+
+        assert!(matches!(
+            scheduler.add_sun("dusk", SunEvent::Sunset, -1800, MissedRuns::Skip, vec![]),
+            Err(ScheduleError::NoLocation)
+        ));
+        let location = Location {
+            latitude: 51.5072,
+            longitude: -0.1276,
+        };
+        scheduler.set_location(location);
+        let dusk = scheduler
+            .add_sun("dusk", SunEvent::Sunset, -1800, MissedRuns::Skip, vec![])
+            .unwrap();
+        assert_eq!(
+            scheduler.next_run(dusk),
+            location
+                .sun_event(FRIDAY, SunEvent::Sunset)
+                .map(|t| t - Duration::from_secs(1800))
+        );
+        assert!(scheduler.cancel(dusk));
+        assert!(!scheduler.cancel(dusk));
+        assert!(matches!(
+            scheduler.add_timer(
+                "invalid",
+                Duration::ZERO,
+                vec![Action::Command {
+                    devices: sockets,
+                    command: String::from("unknown"),
+                    args: vec![],
+                }]
+            ),
+            Err(ScheduleError::InvalidAction(_))
+        ));
+    }
+
+    #[test]
+    fn test_restart() {
+        let home = HomeBuilder::new().build();
+        let clock = SimulatedClock::new(at(FRIDAY, 0, 0));
+        let start = |clock: &SimulatedClock| {
+            let mut scheduler = Scheduler::new(CommandRegistry::new(), clock.clone());
+            for (name, missed) in [
+                ("all", MissedRuns::RunAll),
+                ("skip", MissedRuns::Skip),
+                ("once", MissedRuns::RunOnce),
+            ] {
+                scheduler
+                    .add_cron(name, "0 * * * *", missed, vec![])
+                    .unwrap();
+            }
+            scheduler
+                .add_timer("timer", Duration::ZERO, vec![])
+                .unwrap();
+            scheduler
+        };
+        let mut scheduler = start(&clock);
+        let state = scheduler.save_state();
+        assert_eq!(state.len(), 1);
+        assert_eq!(state[0].name, "timer");
+        assert_eq!(state[0].last_run, None);
+        clock.advance(Duration::from_secs(60 * 60));
+        assert_eq!(scheduler.run_pending(&home).len(), 4);
+        assert!(matches!(
+            scheduler.add_cron("all", "0 * * * *", MissedRuns::Skip, vec![]),
+            Err(ScheduleError::DuplicateName(_))
+        ));
+        let off = vec![Action::SetPower {
+            devices: Selector::default(),
+            state: PowerState::OFF,
+        }];
+        scheduler
+            .add_timer("off", Duration::from_secs(60 * 60), off.clone())
+            .unwrap();
+        let state = scheduler.save_state();
+        assert_eq!(state.len(), 4);
+        assert!(state
+            .iter()
+            .filter(|s| s.name != "off")
+            .all(|s| s.last_run == Some(at(FRIDAY, 1, 0)) && s.timer.is_none()));
+        assert!(state.contains(&JobState {
+            name: String::from("off"),
+            last_run: None,
+            timer: Some(TimerState {
+                due: at(FRIDAY, 2, 0),
+                actions: off,
+            }),
+        }));
+        let saved = serde_json::to_string(&state).unwrap();
+
+        // process is stopped at 01:30 and started at 04:30, runs since 02:00 are missed
+        clock.set(at(FRIDAY, 4, 30));
+        assert!(start(&clock)
+            .run_pending(&home)
+            .iter()
+            .all(|o| o.name == "timer"));
+        let mut scheduler = start(&clock);
+        let state: Vec<JobState> = serde_json::from_str(&saved).unwrap();
+        scheduler.restore_state(&state).unwrap();
+        let runs: Vec<(String, SystemTime)> = scheduler
+            .run_pending(&home)
+            .into_iter()
+            .map(|o| (o.name, o.scheduled))
+            .filter(|(n, _)| n != "timer")
+            .collect();
+        assert_eq!(runs.iter().filter(|(n, _)| n == "all").count(), 3);
+        assert!(runs.contains(&(String::from("all"), at(FRIDAY, 2, 0))));
+        assert!(runs.contains(&(String::from("once"), at(FRIDAY, 4, 0))));
+        // the timer missed during downtime runs once
+        assert!(runs.contains(&(String::from("off"), at(FRIDAY, 2, 0))));
+        assert_eq!(runs.len(), 5);
+        assert!(scheduler
+            .save_state()
+            .iter()
+            .all(|s| s.last_run == Some(at(FRIDAY, 4, 0))));
+
+        // timer added again after restart keeps its own due time
+        let mut scheduler = start(&clock);
+        let timer = scheduler
+            .add_timer("off", Duration::from_secs(60), vec![])
+            .unwrap();
+        scheduler.restore_state(&state).unwrap();
+        assert_eq!(scheduler.next_run(timer), Some(at(FRIDAY, 4, 31)));
+        assert_eq!(scheduler.save_state().len(), 5);
+    }
+}
@@ -0,0 +1,203 @@
+//! Home shared by threads, e.g. by the home server and a scheduler.
+//!
+//! Topology of the home (rooms and devices) is guarded by the read-write lock,
+//! while physical devices are locked one by one (see `logical_device::Device`),
+//! so commands run in parallel under the read lock and a slow device doesn't
+//! block the others.
+
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::args::CommandArgs;
+use crate::device_ref::DeviceRef;
+use crate::error::DeviceError;
+use crate::home::Home;
+use crate::logical::*;
+use crate::report::Reporter;
+
+/// Cloneable handle of the home shared by threads
+#[derive(Clone, Default)]
+pub struct SharedHome {
+    home: Arc<RwLock<Box<Home>>>,
+}
+
+impl From<Box<Home>> for SharedHome {
+    fn from(home: Box<Home>) -> Self {
+        Self::new(home)
+    }
+}
+
+impl SharedHome {
+    pub fn new(home: Box<Home>) -> Self {
+        Self {
+            home: Arc::new(RwLock::new(home)),
+        }
+    }
+
+    /// Locks home for reading, e.g. to send commands by `Room::send_cmd_shared`.
+    /// Home which was locked by panicked thread is still used.
+    pub fn read(&self) -> RwLockReadGuard<'_, Box<Home>> {
+        self.home.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks home for writing, e.g. to change topology or bind devices
+    pub fn write(&self) -> RwLockWriteGuard<'_, Box<Home>> {
+        self.home.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn get_devices(&self) -> Vec<DeviceRef> {
+        self.read().get_devices()
+    }
+
+    /// Sends command to the device, only the device is locked while it executes the command
+    pub fn send_cmd(
+        &self,
+        room_id: &xid::Id,
+        device_id: &xid::Id,
+        cmd: DeviceCommand,
+        args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        match self.read().get_room(room_id) {
+            Some(room) => room.send_cmd_shared(device_id, cmd, args),
+            None => Err(DeviceError::RoomIsMissing(*room_id)),
+        }
+    }
+
+    /// Switches power of the home, devices are locked one by one
+    pub fn switch_power(&self, state: PowerState) -> Result<(), DeviceError> {
+        self.read().switch_power_shared(state)
+    }
+
+    pub fn switch_room_power(
+        &self,
+        room_id: &xid::Id,
+        state: PowerState,
+    ) -> Result<(), DeviceError> {
+        match self.read().get_room(room_id) {
+            Some(room) => room.switch_power_shared(state),
+            None => Err(DeviceError::RoomIsMissing(*room_id)),
+        }
+    }
+
+    pub fn create_report<T: Reporter>(&self, reporter: &T) -> String {
+        self.read().create_report(reporter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::commands::{CMD_SELF_TEST, CMD_STATUS};
+    use crate::home::HomeBuilder;
+    use crate::logical_device::Device;
+    use crate::report::SimpleReporter;
+    use crate::room::{Room, RoomBuilder};
+    use crate::socket::ACSocket;
+    use crate::testing::{power, Gate, TestDevice};
+
+    #[test]
+    fn test_shared_home() {
+        let gate = Gate::new();
+        let slow = TestDevice::new().set_gate(gate.clone()).device("slow");
+        let mut socket = Device::new(String::from("socket"));
+        socket
+            .bind(Box::new(ACSocket::new(
+                String::from("2"),
+                String::from("IBM"),
+            )))
+            .unwrap();
+        let (slow_id, socket_id) = (slow.get_id(), socket.get_id());
+        let mut room = RoomBuilder::new().set_label(String::from("Hall")).build();
+        room.add_device(slow);
+        room.add_device(socket);
+        let room_id = room.get_id();
+        // builder clones rooms without physical devices
+        let mut home = HomeBuilder::new().build();
+        home.add_room(Box::new(room));
+        let home = SharedHome::from(home);
+
+        let handle = home.clone();
+        let (done, finished) = mpsc::channel();
+        let slow_test = thread::spawn(move || {
+            let result = handle.send_cmd(&room_id, &slow_id, CMD_SELF_TEST, &CommandArgs::new());
+            done.send(()).unwrap();
+            result
+        });
+
+        // other device is served while the slow one executes the command
+        gate.wait_held();
+        assert!(matches!(
+            home.send_cmd(&room_id, &socket_id, CMD_STATUS, &CommandArgs::new()),
+            Ok(Some(CommandResult::Str(v))) if v.contains("OFF")
+        ));
+        assert_eq!(home.get_devices().len(), 2);
+        {
+            let home = home.read();
+            let room = home.get_room(&room_id).unwrap();
+            room.switch_device_power_shared(&socket_id, PowerState::ON)
+                .unwrap();
+        }
+        let mut reporter = SimpleReporter::default();
+        reporter.add_device(&room_id, &socket_id);
+        assert!(home.create_report(&reporter).contains("ON"));
+        assert!(finished.recv_timeout(Duration::from_millis(50)).is_err());
+
+        gate.open();
+        assert!(matches!(
+            slow_test.join().unwrap(),
+            Ok(Some(CommandResult::Str(v))) if v == "PASSED"
+        ));
+        home.switch_room_power(&room_id, PowerState::ON).unwrap();
+        assert!(matches!(
+            home.switch_room_power(&xid::new(), PowerState::ON),
+            Err(DeviceError::RoomIsMissing(_))
+        ));
+        assert!(matches!(
+            home.send_cmd(&xid::new(), &socket_id, CMD_STATUS, &CommandArgs::new()),
+            Err(DeviceError::RoomIsMissing(_))
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_switching() {
+        let mut home = HomeBuilder::new().build();
+        let mut rooms = vec![];
+        for _ in 0..2 {
+            let mut room = Room::new();
+            for _ in 0..3 {
+                room.add_device(TestDevice::new().device("socket"));
+            }
+            rooms.push(home.add_room(Box::new(room)));
+        }
+        let home = SharedHome::from(home);
+
+        thread::scope(|s| {
+            for i in 0..4 {
+                let (home, rooms) = (&home, &rooms);
+                s.spawn(move || {
+                    for j in 0..200 {
+                        let state = match (i + j) % 2 {
+                            0 => PowerState::ON,
+                            _ => PowerState::OFF,
+                        };
+                        match j % 3 {
+                            0 => home.switch_power(state).unwrap(),
+                            _ => home.switch_room_power(&rooms[j % 2], state).unwrap(),
+                        }
+                        assert_eq!(home.get_devices().len(), 6);
+                    }
+                });
+            }
+        });
+        home.switch_power(PowerState::ON).unwrap();
+        for d in home.get_devices() {
+            assert_eq!(
+                power(&home.read(), d.room_id(), d.device_id()),
+                PowerState::ON
+            );
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::args::CommandArgs;
 use crate::commands::CMD_GET_POWER_CONSUMPTION;
 use crate::commands::CMD_SELF_TEST;
 use crate::commands::CMD_STATUS;
@@ -69,7 +70,7 @@ impl PhysicalDevice for ACSocket {
     fn execute_cmd_mut(
         &mut self,
         cmd: DeviceCommand,
-        _ars: Option<Vec<String>>,
+        _args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError> {
         match cmd {
             CMD_GET_POWER_CONSUMPTION => Ok(Some(CommandResult::Float32(self.get_consumption()))),
@@ -82,7 +83,7 @@ impl PhysicalDevice for ACSocket {
     fn execute_cmd(
         &self,
         cmd: DeviceCommand,
-        _ars: Option<Vec<String>>,
+        _args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError> {
         match cmd {
             CMD_GET_POWER_CONSUMPTION => Ok(Some(CommandResult::Float32(self.get_consumption()))),
@@ -120,11 +121,11 @@ mod tests {
             Ok(PowerState::ON)
         ));
         assert!(matches!(
-            socket.execute_cmd_mut(CMD_SELF_TEST, Option::None),
+            socket.execute_cmd_mut(CMD_SELF_TEST, &CommandArgs::default()),
             Ok(Some(CommandResult::Str(_)))
         ));
         assert!({
-            match socket.execute_cmd_mut(CMD_STATUS, Option::None) {
+            match socket.execute_cmd_mut(CMD_STATUS, &CommandArgs::default()) {
                 Ok(Some(CommandResult::Str(v))) => {
                     println!("{:?}", v);
                     true
@@ -133,7 +134,7 @@ mod tests {
             }
         });
         assert!({
-            match socket.execute_cmd_mut(CMD_GET_POWER_CONSUMPTION, Option::None) {
+            match socket.execute_cmd_mut(CMD_GET_POWER_CONSUMPTION, &CommandArgs::default()) {
                 Ok(Some(CommandResult::Float32(v))) => v > 0.0,
                 _ => false,
             }
@@ -0,0 +1,217 @@
+//! Physical device of the tests, its behaviour is set by the test
+
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use crate::args::{ArgValue, CommandArgs};
+use crate::commands::{CMD_GET_TEMPERATURE, CMD_SELF_TEST, CMD_SET_THRESHOLD, CMD_STATUS};
+use crate::error::DeviceError;
+use crate::home::Home;
+use crate::logical::*;
+use crate::logical_device::Device;
+use crate::physical::PhysicalDevice;
+
+/// Holds self tests of the devices until the test opens it
+#[derive(Clone, Default)]
+pub struct Gate {
+    /// Number of held devices and whether the gate is open
+    state: Arc<(Mutex<(usize, bool)>, Condvar)>,
+}
+
+impl Gate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits until the device is held by the gate
+    pub fn wait_held(&self) {
+        let (state, changed) = &*self.state;
+        let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        let _held = changed.wait_while(state, |(held, _)| *held == 0);
+    }
+
+    pub fn open(&self) {
+        let (state, changed) = &*self.state;
+        state.lock().unwrap_or_else(PoisonError::into_inner).1 = true;
+        changed.notify_all();
+    }
+
+    fn pass(&self) {
+        let (state, changed) = &*self.state;
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.0 += 1;
+        changed.notify_all();
+        let _open = changed.wait_while(state, |(_, open)| !*open);
+    }
+}
+
+/// Device which supports status, self test, temperature and threshold commands
+#[derive(Clone)]
+pub struct TestDevice {
+    power: PowerState,
+    /// Temperature threshold, celsius
+    threshold: f32,
+    /// Temperature returned by `CMD_GET_TEMPERATURE`, it is changed by the test
+    temperature: Arc<Mutex<f32>>,
+    /// Switching, commands and settings fail, power state is still read
+    broken: bool,
+    /// Settings can't be changed, switching still works
+    locked: bool,
+    gate: Option<Gate>,
+}
+
+impl Default for TestDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestDevice {
+    pub fn new() -> Self {
+        Self {
+            power: PowerState::OFF,
+            threshold: 25.0,
+            temperature: Arc::new(Mutex::new(20.0)),
+            broken: false,
+            locked: false,
+            gate: None,
+        }
+    }
+
+    pub fn set_power(&mut self, state: PowerState) -> &mut Self {
+        self.power = state;
+        self
+    }
+
+    pub fn set_temperature(&mut self, temperature: Arc<Mutex<f32>>) -> &mut Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn set_broken(&mut self) -> &mut Self {
+        self.broken = true;
+        self
+    }
+
+    pub fn set_locked(&mut self) -> &mut Self {
+        self.locked = true;
+        self
+    }
+
+    /// Self test waits until the gate is opened
+    pub fn set_gate(&mut self, gate: Gate) -> &mut Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// Logical device of the class bound to the copy of the device
+    pub fn device(&self, class: &str) -> Device {
+        let mut d = Device::new(String::from(class));
+        d.bind(Box::new(self.clone())).unwrap();
+        d
+    }
+
+    fn check(&self) -> Result<(), DeviceError> {
+        match self.broken {
+            true => Err(DeviceError::CommandFailed(String::from("broken"))),
+            false => Ok(()),
+        }
+    }
+}
+
+impl PhysicalDevice for TestDevice {
+    fn get_serial(&self) -> String {
+        String::from("1")
+    }
+
+    fn get_manufactor(&self) -> String {
+        String::from("Test")
+    }
+
+    fn get_power_state(&self) -> Result<PowerState, DeviceError> {
+        Ok(self.power)
+    }
+
+    fn set_power_state(&mut self, state: PowerState) -> Result<PowerState, DeviceError> {
+        self.check()?;
+        self.power = state;
+        Ok(state)
+    }
+
+    fn get_supported_commands(&self) -> Result<Vec<DeviceCommand>, DeviceError> {
+        Ok(vec![
+            CMD_STATUS,
+            CMD_SELF_TEST,
+            CMD_GET_TEMPERATURE,
+            CMD_SET_THRESHOLD,
+        ])
+    }
+
+    fn get_settings(&self) -> Result<Vec<(DeviceCommand, CommandArgs)>, DeviceError> {
+        self.check()?;
+        Ok(vec![(
+            CMD_SET_THRESHOLD,
+            CommandArgs::from([("celsius", ArgValue::Float(self.threshold as f64))]),
+        )])
+    }
+
+    fn execute_cmd_mut(
+        &mut self,
+        cmd: DeviceCommand,
+        args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        match cmd {
+            CMD_SET_THRESHOLD => {
+                self.check()?;
+                if self.locked {
+                    return Err(DeviceError::CommandFailed(String::from("locked")));
+                }
+                let celsius = args.get_float("celsius")?;
+                if !(0.0..=50.0).contains(&celsius) {
+                    return Err(DeviceError::InvalidArgument(format!(
+                        "threshold {}C is out of range",
+                        celsius
+                    )));
+                }
+                self.threshold = celsius as f32;
+                Ok(None)
+            }
+            _ => self.execute_cmd(cmd, args),
+        }
+    }
+
+    fn execute_cmd(
+        &self,
+        cmd: DeviceCommand,
+        _args: &CommandArgs,
+    ) -> Result<Option<CommandResult>, DeviceError> {
+        self.check()?;
+        match cmd {
+            CMD_STATUS => Ok(Some(CommandResult::Str(format!(
+                "power state = {:?}, threshold = {}C",
+                self.power, self.threshold
+            )))),
+            CMD_SELF_TEST => {
+                if let Some(gate) = &self.gate {
+                    gate.pass();
+                }
+                Ok(Some(CommandResult::Str(String::from("PASSED"))))
+            }
+            CMD_GET_TEMPERATURE => Ok(Some(CommandResult::Float32(
+                *self
+                    .temperature
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner),
+            ))),
+            _ => Err(DeviceError::UnsupportedCommand),
+        }
+    }
+}
+
+/// Power state of the device in the room
+pub fn power(home: &Home, room_id: &xid::Id, device_id: &xid::Id) -> PowerState {
+    let room = home.get_room(room_id).unwrap();
+    room.get_device(device_id)
+        .unwrap()
+        .get_power_state()
+        .unwrap()
+}
@@ -0,0 +1,272 @@
+//! Topology of the smart home: name, rooms and logical devices with their ids.
+//!
+//! Topology is saved to JSON or TOML file and loaded back, so ids of the rooms
+//! and devices are kept between runs. Physical devices are not part of the
+//! topology, loaded devices are bound again through `PhysicalDeviceFactory`.
+//!
+//! ```toml
+//! name = "My home"
+//!
+//! [[rooms]]
+//! id = "cpbfcv4q6r8t2e9p6rlg"
+//! label = "Kitchen"
+//!
+//! [[rooms.devices]]
+//! id = "cpbfcv4q6r8t2e9p6rm0"
+//! class = "socket"
+//! ```
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::TopologyError;
+use crate::factory::PhysicalDeviceFactory;
+use crate::format::FileFormat;
+use crate::home::*;
+use crate::logical_device::Device;
+use crate::room::*;
+
+/// Serializable description of the home
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HomeTopology {
+    pub name: String,
+    #[serde(default)]
+    pub rooms: Vec<RoomTopology>,
+}
+
+/// Serializable description of the room
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomTopology {
+    pub id: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub devices: Vec<DeviceTopology>,
+}
+
+/// Serializable description of the logical device
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceTopology {
+    pub id: String,
+    pub class: String,
+}
+
+fn parse_id(id: &str) -> Result<xid::Id, TopologyError> {
+    xid::Id::from_str(id).map_err(|_| TopologyError::InvalidId(String::from(id)))
+}
+
+impl HomeTopology {
+    /// Describes topology of the home, rooms and devices are sorted by id
+    pub fn new(home: &Home) -> Self {
+        let mut rooms: Vec<RoomTopology> = home
+            .get_rooms()
+            .iter()
+            .map(|r| {
+                let mut devices: Vec<DeviceTopology> = r
+                    .get_devices()
+                    .iter()
+                    .map(|d| DeviceTopology {
+                        id: d.device_id().to_string(),
+                        class: d.device_class().clone(),
+                    })
+                    .collect();
+                devices.sort_by(|a, b| a.id.cmp(&b.id));
+                RoomTopology {
+                    id: r.get_id().to_string(),
+                    label: r.get_label(),
+                    devices,
+                }
+            })
+            .collect();
+        rooms.sort_by(|a, b| a.id.cmp(&b.id));
+        Self {
+            name: home.get_name(),
+            rooms,
+        }
+    }
+
+    /// Builds home with unbound devices, ids must be valid and unique
+    pub fn build_home(&self) -> Result<Box<Home>, TopologyError> {
+        let mut ids: HashSet<xid::Id> = HashSet::default();
+        let mut builder = HomeBuilder::new();
+        builder.set_name(self.name.clone());
+        for r in &self.rooms {
+            let id = parse_id(&r.id)?;
+            if !ids.insert(id) {
+                return Err(TopologyError::DuplicateId(id));
+            }
+            let mut room = Room::with_id(id);
+            room.set_label(r.label.clone());
+            for d in &r.devices {
+                let id = parse_id(&d.id)?;
+                if !ids.insert(id) {
+                    return Err(TopologyError::DuplicateId(id));
+                }
+                room.add_device(Device::with_id(id, d.class.clone()));
+            }
+            builder.add_room(Box::new(room));
+        }
+        Ok(builder.build())
+    }
+
+    /// Builds home and binds its devices to physical ones
+    pub fn bind_home<F: PhysicalDeviceFactory>(
+        &self,
+        binder: &mut Binder<F>,
+    ) -> Result<Box<Home>, TopologyError> {
+        let mut home = self.build_home()?;
+        home.bind_physical_devices(binder)
+            .map_err(TopologyError::Bind)?;
+        Ok(home)
+    }
+
+    pub fn to_json(&self) -> Result<String, TopologyError> {
+        serde_json::to_string_pretty(self).map_err(|v| TopologyError::Format(v.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, TopologyError> {
+        serde_json::from_str(s).map_err(|v| TopologyError::Format(v.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, TopologyError> {
+        toml::to_string(self).map_err(|v| TopologyError::Format(v.to_string()))
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, TopologyError> {
+        toml::from_str(s).map_err(|v| TopologyError::Format(v.to_string()))
+    }
+
+    pub fn encode(&self, format: FileFormat) -> Result<String, TopologyError> {
+        match format {
+            FileFormat::Json => self.to_json(),
+            FileFormat::Toml => self.to_toml(),
+        }
+    }
+
+    pub fn decode(s: &str, format: FileFormat) -> Result<Self, TopologyError> {
+        match format {
+            FileFormat::Json => Self::from_json(s),
+            FileFormat::Toml => Self::from_toml(s),
+        }
+    }
+
+    /// Writes topology to the file, format is detected by extension
+    pub fn save(&self, path: &Path) -> Result<(), TopologyError> {
+        std::fs::write(path, self.encode(FileFormat::from_path(path))?)?;
+        Ok(())
+    }
+
+    /// Reads topology from the file, format is detected by extension
+    pub fn load(path: &Path) -> Result<Self, TopologyError> {
+        Self::decode(&std::fs::read_to_string(path)?, FileFormat::from_path(path))
+    }
+}
+
+/// Saves topology of the home to the file
+pub fn save_home(home: &Home, path: &Path) -> Result<(), TopologyError> {
+    HomeTopology::new(home).save(path)
+}
+
+/// Loads home from the topology file and binds its devices to physical ones
+pub fn load_home<F: PhysicalDeviceFactory>(
+    path: &Path,
+    binder: &mut Binder<F>,
+) -> Result<Box<Home>, TopologyError> {
+    HomeTopology::load(path)?.bind_home(binder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factory::SimpleClassFactory;
+
+    fn create_home() -> Box<Home> {
+        let kitchen = RoomBuilder::new()
+            .set_label(String::from("Kitchen"))
+            .add_device(&Device::new(String::from("socket")))
+            .add_device(&Device::new(String::from("tsensor")))
+            .build();
+        let hall = RoomBuilder::new().set_label(String::from("Hall")).build();
+        HomeBuilder::new()
+            .set_name(String::from("My home"))
+            .add_room(Box::new(kitchen))
+            .add_room(Box::new(hall))
+            .build()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let home = create_home();
+        let topology = HomeTopology::new(&home);
+        assert_eq!(topology.rooms.len(), 2);
+        for format in [FileFormat::Json, FileFormat::Toml] {
+            let s = topology.encode(format).unwrap();
+            assert_eq!(HomeTopology::decode(&s, format).unwrap(), topology);
+        }
+
+        let mut binder = Binder::new(SimpleClassFactory {});
+        let loaded = topology.bind_home(&mut binder).unwrap();
+        assert_eq!(loaded.get_name(), "My home");
+        assert_eq!(HomeTopology::new(&loaded), topology);
+        let mut devices = loaded.get_devices();
+        devices.sort_by_key(|d| d.device_class().clone());
+        assert_eq!(devices[0].room_name(), "Kitchen");
+        let kitchen = loaded
+            .get_rooms()
+            .into_iter()
+            .find(|r| r.get_label() == "Kitchen")
+            .unwrap();
+        let status = kitchen.send_cmd(
+            devices[0].device_id(),
+            crate::commands::CMD_STATUS,
+            &Default::default(),
+        );
+        assert!(status.is_ok());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let home = create_home();
+        for ext in ["json", "toml"] {
+            let path = std::env::temp_dir().join(format!("home-{}.{}", xid::new(), ext));
+            save_home(&home, &path).unwrap();
+            let mut binder = Binder::new(SimpleClassFactory {});
+            let loaded = load_home(&path, &mut binder).unwrap();
+            assert_eq!(HomeTopology::new(&loaded), HomeTopology::new(&home));
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_invalid_topology() {
+        let mut topology = HomeTopology::new(&create_home());
+        let id = topology.rooms[0].id.clone();
+        topology.rooms[1].id = id.clone();
+        assert!(matches!(
+            topology.build_home(),
+            Err(TopologyError::DuplicateId(v)) if v.to_string() == id
+        ));
+        topology.rooms[1].id = String::from("room");
+        assert!(matches!(
+            topology.build_home(),
+            Err(TopologyError::InvalidId(v)) if v == "room"
+        ));
+        assert!(matches!(
+            HomeTopology::from_toml("rooms = 1"),
+            Err(TopologyError::Format(_))
+        ));
+
+        // class unknown to the factory
+        let mut topology = HomeTopology::new(&create_home());
+        let room = topology.rooms.iter_mut().find(|r| !r.devices.is_empty());
+        room.unwrap().devices[0].class = String::from("kettle");
+        let mut binder = Binder::new(SimpleClassFactory {});
+        assert!(matches!(
+            topology.bind_home(&mut binder),
+            Err(TopologyError::Bind(_))
+        ));
+    }
+}
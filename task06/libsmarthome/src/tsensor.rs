@@ -1,8 +1,8 @@
-use std::f32::NAN;
-
+use crate::args::{ArgValue, CommandArgs};
 use crate::commands::CMD_GET_POWER_CONSUMPTION;
 use crate::commands::CMD_GET_TEMPERATURE;
 use crate::commands::CMD_SELF_TEST;
+use crate::commands::CMD_SET_THRESHOLD;
 use crate::commands::CMD_STATUS;
 use crate::error::DeviceError;
 use crate::logical;
@@ -10,11 +10,16 @@ use crate::logical::*;
 use crate::physical::*;
 use rand::Rng;
 
+/// Default temperature threshold of the sensor, celsius
+pub const DEFAULT_THRESHOLD: f32 = 25.0;
+
 /// TSensor describes temperature sensor
 pub struct TSensor {
     serial: String,
     manufactor: String,
     state: logical::PowerState,
+    /// Temperature threshold, celsius
+    threshold: f32,
 }
 
 // convert temperature from fahrenheit to celsius
@@ -33,6 +38,7 @@ impl TSensor {
             serial,
             manufactor,
             state: PowerState::OFF,
+            threshold: DEFAULT_THRESHOLD,
         }
     }
 
@@ -50,7 +56,7 @@ impl TSensor {
     /// Returns current temperature as celsius
     fn get_temperature(&self) -> f32 {
         match &self.state {
-            PowerState::OFF => NAN,
+            PowerState::OFF => f32::NAN,
             PowerState::ON => {
                 let mut rng = rand::thread_rng();
                 rng.gen_range(10.0..32.0)
@@ -60,12 +66,13 @@ impl TSensor {
 
     fn get_status(&self) -> String {
         format!(
-            "Temperature sensor: serial = {}, manufactor = {}, power state = {:?}, consumption = {}, temperature = {}C",
+            "Temperature sensor: serial = {}, manufactor = {}, power state = {:?}, consumption = {}, temperature = {}C, threshold = {}C",
             self.serial,
             self.manufactor,
             self.state,
             self.get_consumption(),
             self.get_temperature(),
+            self.threshold,
         )
     }
 }
@@ -94,15 +101,27 @@ impl PhysicalDevice for TSensor {
             CMD_GET_POWER_CONSUMPTION,
             CMD_SELF_TEST,
             CMD_GET_TEMPERATURE,
+            CMD_SET_THRESHOLD,
         ])
     }
 
+    fn get_settings(&self) -> Result<Vec<(DeviceCommand, CommandArgs)>, DeviceError> {
+        Ok(vec![(
+            CMD_SET_THRESHOLD,
+            CommandArgs::from([("celsius", ArgValue::Float(self.threshold as f64))]),
+        )])
+    }
+
     fn execute_cmd_mut(
         &mut self,
         cmd: DeviceCommand,
-        _args: Option<Vec<String>>,
+        args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError> {
         match cmd {
+            CMD_SET_THRESHOLD => {
+                self.threshold = args.get_float("celsius")? as f32;
+                Ok(None)
+            }
             CMD_GET_POWER_CONSUMPTION => Ok(Some(CommandResult::Float32(self.get_consumption()))),
             CMD_SELF_TEST => Ok(Some(CommandResult::Str(String::from("PASSED")))),
             CMD_STATUS => Ok(Some(CommandResult::Str(self.get_status()))),
@@ -114,13 +133,14 @@ impl PhysicalDevice for TSensor {
     fn execute_cmd(
         &self,
         cmd: DeviceCommand,
-        _args: Option<Vec<String>>,
+        _args: &CommandArgs,
     ) -> Result<Option<CommandResult>, DeviceError> {
         match cmd {
             CMD_GET_POWER_CONSUMPTION => Ok(Some(CommandResult::Float32(self.get_consumption()))),
             CMD_SELF_TEST => Ok(Some(CommandResult::Str(String::from("PASSED")))),
             CMD_STATUS => Ok(Some(CommandResult::Str(self.get_status()))),
             CMD_GET_TEMPERATURE => Ok(Some(CommandResult::Float32(self.get_temperature()))),
+            CMD_SET_THRESHOLD => Err(DeviceError::UnsupportedOperation), // changes the sensor
             _ => Err(DeviceError::UnsupportedCommand),
         }
     }
@@ -155,13 +175,13 @@ mod tests {
             Ok(PowerState::ON)
         ));
         assert!({
-            match sensor.execute_cmd_mut(CMD_SELF_TEST, Option::None) {
+            match sensor.execute_cmd_mut(CMD_SELF_TEST, &CommandArgs::default()) {
                 Ok(Some(CommandResult::Str(v))) => v == "PASSED",
                 _ => false,
             }
         });
         assert!({
-            match sensor.execute_cmd_mut(CMD_STATUS, Option::None) {
+            match sensor.execute_cmd_mut(CMD_STATUS, &CommandArgs::default()) {
                 Ok(Some(CommandResult::Str(v))) => {
                     println!("{:?}", v);
                     true
@@ -170,13 +190,13 @@ mod tests {
             }
         });
         assert!({
-            match sensor.execute_cmd_mut(CMD_GET_POWER_CONSUMPTION, Option::None) {
+            match sensor.execute_cmd_mut(CMD_GET_POWER_CONSUMPTION, &CommandArgs::default()) {
                 Ok(Some(CommandResult::Float32(v))) => v > 0.0,
                 _ => false,
             }
         });
         assert!({
-            match sensor.execute_cmd_mut(CMD_GET_TEMPERATURE, Option::None) {
+            match sensor.execute_cmd_mut(CMD_GET_TEMPERATURE, &CommandArgs::default()) {
                 Ok(Some(CommandResult::Float32(v))) => v > 0.0,
                 _ => false,
             }
@@ -0,0 +1,46 @@
+//! This example serves smart home over the network protocol
+//!
+//! Run `cargo run --example server [topology.json|topology.toml]`
+
+use std::path::Path;
+
+use libsmarthome::factory::*;
+use libsmarthome::home::*;
+use libsmarthome::home_server::HomeServer;
+use libsmarthome::logical_device::Device;
+use libsmarthome::room::*;
+use libsmarthome::topology::load_home;
+
+/// Address of the server
+const ADDR: &str = "127.0.0.1:8090";
+
+fn main() {
+    let mut binder = Binder::new(SimpleClassFactory {});
+    let home = match std::env::args().nth(1) {
+        Some(path) => match load_home(Path::new(&path), &mut binder) {
+            Ok(v) => v,
+            Err(v) => panic!("ERROR: Cannot load home {}", v),
+        },
+        None => {
+            let mut home = HomeBuilder::new()
+                .set_name(String::from("Jacks home"))
+                .add_room(Box::new(
+                    RoomBuilder::new()
+                        .set_label(String::from("Kitchen"))
+                        .add_device(&Device::new(String::from("socket")))
+                        .add_device(&Device::new(String::from("tsensor")))
+                        .build(),
+                ))
+                .build();
+            match home.bind_physical_devices(&mut binder) {
+                Ok(_) => println!("INFO: bound logical devices to physical"),
+                Err(_) => panic!("ERROR: Cannot bind to physical devices"),
+            }
+            home
+        }
+    };
+    match HomeServer::bind(String::from(ADDR), home.into()) {
+        Ok(mut server) => server.run(),
+        Err(v) => panic!("ERROR: Cannot start server {}", v),
+    }
+}
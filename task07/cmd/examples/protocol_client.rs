@@ -1,13 +1,11 @@
-use libprotocol::client::{self, *};
+use libprotocol::client;
 
 pub fn main() {
-    let client = client::TcpClient::connect(format!("127.0.0.1:8088"));
+    let client = client::TcpClient::connect(String::from("127.0.0.1:8088"));
     match client {
         Err(v) => panic!("cannot connect to the server {}", v),
         Ok(mut conn) => loop {
-            loop {
-                println!("command {:?}", conn.send_cmd(123));
-            }
+            println!("command {:?}", conn.send_cmd(123));
         },
     }
 }
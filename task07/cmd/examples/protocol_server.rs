@@ -1,7 +1,7 @@
 use libprotocol::{error, server::*};
 
 pub fn main() {
-    let server = TcpServer::bind(format!("0.0.0.0:8088")).unwrap();
+    let server = TcpServer::bind(String::from("0.0.0.0:8088")).unwrap();
     server.incoming().for_each(|conn| match conn {
         Ok(mut client) => {
             println!("INFO: connected from {:?}", client.peer_addr().unwrap());
@@ -1,3 +1,659 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use libclient::{broadcast_target, discover, error::ClientError, IotClient};
+use libprotocol::commands::DeviceInfo;
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use signal_hook::consts::SIGINT;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:8088";
+const HISTORY_FILE: &str = ".iot_client_history";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn usage() -> ! {
+    println!("Usage: client [address | --discover] [--token <token>] [--history <path>]");
+    println!();
+    println!("--discover connects to the first server answering the LAN broadcast.");
+    std::process::exit(1)
+}
+
+/// Command of the shell: name, usage and description
+struct ShellCommand {
+    name: &'static str,
+    usage: &'static str,
+    about: &'static str,
+}
+
+const COMMANDS: &[ShellCommand] = &[
+    ShellCommand {
+        name: "list",
+        usage: "list",
+        about: "refresh and show devices",
+    },
+    ShellCommand {
+        name: "on",
+        usage: "on <device>",
+        about: "switch device on",
+    },
+    ShellCommand {
+        name: "off",
+        usage: "off <device>",
+        about: "switch device off",
+    },
+    ShellCommand {
+        name: "status",
+        usage: "status <device>",
+        about: "show device status",
+    },
+    ShellCommand {
+        name: "consumption",
+        usage: "consumption <device>",
+        about: "show current power, W",
+    },
+    ShellCommand {
+        name: "energy",
+        usage: "energy <device>",
+        about: "show energy since the last reset, kWh",
+    },
+    ShellCommand {
+        name: "reset-energy",
+        usage: "reset-energy <device>",
+        about: "reset energy meter",
+    },
+    ShellCommand {
+        name: "watch",
+        usage: "watch consumption|energy <device> [seconds]",
+        about: "print value periodically until Ctrl-C",
+    },
+    ShellCommand {
+        name: "subscribe",
+        usage: "subscribe [seconds]",
+        about: "print changes of devices until Ctrl-C",
+    },
+    ShellCommand {
+        name: "auth",
+        usage: "auth <token>",
+        about: "authenticate to manage devices",
+    },
+    ShellCommand {
+        name: "create",
+        usage: "create <name>",
+        about: "create socket",
+    },
+    ShellCommand {
+        name: "remove",
+        usage: "remove <device>",
+        about: "remove device",
+    },
+    ShellCommand {
+        name: "rename",
+        usage: "rename <device> <name>",
+        about: "rename device",
+    },
+    ShellCommand {
+        name: "commands",
+        usage: "commands [device]",
+        about: "show protocol commands of the server or device",
+    },
+    ShellCommand {
+        name: "help",
+        usage: "help",
+        about: "show commands",
+    },
+    ShellCommand {
+        name: "quit",
+        usage: "quit",
+        about: "exit shell",
+    },
+];
+
+const WATCH_VALUES: &[&str] = &["consumption", "energy"];
+
+/// Commands which take device id or name as the first argument
+const DEVICE_COMMANDS: &[&str] = &[
+    "on",
+    "off",
+    "status",
+    "consumption",
+    "energy",
+    "reset-energy",
+    "remove",
+    "rename",
+    "commands",
+];
+
+/// Completes command names, watched values and device ids and names
+struct ShellHelper {
+    devices: Vec<DeviceInfo>,
+}
+
+fn candidates<'a>(prefix: &str, values: impl Iterator<Item = &'a str>) -> Vec<Pair> {
+    let mut result: Vec<Pair> = values
+        .filter(|v| !v.is_empty() && v.starts_with(prefix))
+        .map(|v| Pair {
+            display: v.to_string(),
+            replacement: format!("{} ", v),
+        })
+        .collect();
+    result.sort_by(|a, b| a.display.cmp(&b.display));
+    result.dedup_by(|a, b| a.display == b.display);
+    result
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let prefix = &line[start..];
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        let devices = self
+            .devices
+            .iter()
+            .flat_map(|d| [d.id.as_str(), d.name.as_str()]);
+        let result = match words.as_slice() {
+            [] => candidates(prefix, COMMANDS.iter().map(|c| c.name)),
+            ["watch"] => candidates(prefix, WATCH_VALUES.iter().copied()),
+            ["watch", _] => candidates(prefix, devices),
+            [cmd] if DEVICE_COMMANDS.contains(cmd) => candidates(prefix, devices),
+            _ => vec![],
+        };
+        Ok((start, result))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// Interactive session with the server
+struct Shell {
+    client: IotClient,
+    devices: Vec<DeviceInfo>,
+    /// Set by Ctrl-C, it stops watch and subscribe
+    interrupted: Arc<AtomicBool>,
+}
+
+impl Shell {
+    fn refresh(&mut self) -> Result<(), ClientError> {
+        let ids = self.client.list_devices()?;
+        self.devices = ids
+            .iter()
+            .map(|id| self.client.describe_device(id))
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    fn print_devices(&self) {
+        if self.devices.is_empty() {
+            println!("no devices");
+        }
+        for d in &self.devices {
+            println!(
+                "{}  {:<5} {:<8} {}",
+                d.id,
+                format!("{:?}", d.power),
+                d.kind,
+                d.name
+            );
+        }
+    }
+
+    /// Finds id of the device by id or name
+    fn resolve(&self, key: Option<&str>) -> Result<String, String> {
+        let key = key.ok_or_else(|| String::from("device is required"))?;
+        if self.devices.iter().any(|d| d.id == key) {
+            return Ok(key.to_string());
+        }
+        let named: Vec<&DeviceInfo> = self.devices.iter().filter(|d| d.name == key).collect();
+        match named.as_slice() {
+            [d] => Ok(d.id.clone()),
+            [] => Ok(key.to_string()),
+            _ => Err(format!("name {} is ambiguous, use device id", key)),
+        }
+    }
+
+    /// Sleeps for the interval, returns false if interrupted
+    fn wait(&self, interval: Duration) -> bool {
+        let deadline = Instant::now() + interval;
+        while Instant::now() < deadline {
+            if self.interrupted.load(Ordering::Relaxed) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        !self.interrupted.load(Ordering::Relaxed)
+    }
+
+    fn watch(&mut self, value: &str, id: &str, interval: Duration) -> Result<(), String> {
+        self.interrupted.store(false, Ordering::Relaxed);
+        loop {
+            let reading = match value {
+                "consumption" => format!("{} W", self.client.get_consumption(id).map_err(err)?),
+                "energy" => format!("{} kWh", self.client.get_energy(id).map_err(err)?),
+                v => return Err(format!("cannot watch {}", v)),
+            };
+            println!("{}  {}", id, reading);
+            if !self.wait(interval) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Polls devices and prints changes. Server doesn't push events,
+    /// so changes are detected by comparison with the previous poll.
+    fn subscribe(&mut self, interval: Duration) -> Result<(), String> {
+        self.interrupted.store(false, Ordering::Relaxed);
+        let mut known: HashMap<String, DeviceInfo> = self
+            .devices
+            .iter()
+            .map(|d| (d.id.clone(), d.clone()))
+            .collect();
+        println!("watching {} devices, press Ctrl-C to stop", known.len());
+        while self.wait(interval) {
+            self.refresh().map_err(err)?;
+            for d in &self.devices {
+                match known.get(&d.id) {
+                    None => println!("{}  added {}", d.id, d.name),
+                    Some(old) if old.power != d.power => println!("{}  power {:?}", d.id, d.power),
+                    Some(old) if old.name != d.name => println!("{}  renamed {}", d.id, d.name),
+                    _ => {}
+                }
+            }
+            for id in known.keys() {
+                if !self.devices.iter().any(|d| &d.id == id) {
+                    println!("{}  removed", id);
+                }
+            }
+            known = self
+                .devices
+                .iter()
+                .map(|d| (d.id.clone(), d.clone()))
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Executes command line, returns false on quit
+    fn execute(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let Some(cmd) = words.next() else {
+            return Ok(true);
+        };
+        match cmd {
+            "list" => {
+                self.refresh().map_err(err)?;
+                self.print_devices();
+            }
+            "on" => self
+                .client
+                .power_on(&self.resolve(words.next())?)
+                .map_err(err)?,
+            "off" => self
+                .client
+                .power_off(&self.resolve(words.next())?)
+                .map_err(err)?,
+            "status" => {
+                let id = self.resolve(words.next())?;
+                println!("{}", self.client.get_status(&id).map_err(err)?);
+            }
+            "consumption" => {
+                let id = self.resolve(words.next())?;
+                println!("{} W", self.client.get_consumption(&id).map_err(err)?);
+            }
+            "energy" => {
+                let id = self.resolve(words.next())?;
+                println!("{} kWh", self.client.get_energy(&id).map_err(err)?);
+            }
+            "reset-energy" => self
+                .client
+                .reset_energy(&self.resolve(words.next())?)
+                .map_err(err)?,
+            "watch" => {
+                let value = words.next().unwrap_or_default().to_string();
+                let id = self.resolve(words.next())?;
+                let interval = parse_interval(words.next())?;
+                self.watch(&value, &id, interval)?;
+            }
+            "subscribe" => {
+                let interval = parse_interval(words.next())?;
+                self.subscribe(interval)?;
+            }
+            "auth" => {
+                let token = words.next().ok_or("token is required")?;
+                self.client.authenticate(token).map_err(err)?;
+            }
+            "create" => {
+                let name = words.collect::<Vec<_>>().join(" ");
+                println!(
+                    "{}",
+                    self.client.create_device("socket", &name).map_err(err)?
+                );
+                self.refresh().map_err(err)?;
+            }
+            "remove" => {
+                self.client
+                    .remove_device(&self.resolve(words.next())?)
+                    .map_err(err)?;
+                self.refresh().map_err(err)?;
+            }
+            "rename" => {
+                let id = self.resolve(words.next())?;
+                let name = words.collect::<Vec<_>>().join(" ");
+                self.client.rename_device(&id, &name).map_err(err)?;
+                self.refresh().map_err(err)?;
+            }
+            "commands" => {
+                let id = match words.next() {
+                    Some(v) => Some(self.resolve(Some(v))?),
+                    None => None,
+                };
+                for c in self.client.get_catalog(id.as_deref()).map_err(err)? {
+                    let args: Vec<String> = c
+                        .args
+                        .iter()
+                        .map(|a| format!("<{}: {}>", a.name, a.kind))
+                        .collect();
+                    println!(
+                        "  {:>3} {} {} -> {}",
+                        c.id,
+                        c.name,
+                        args.join(" "),
+                        c.result
+                    );
+                    println!("      {}", c.description);
+                }
+            }
+            "help" => COMMANDS
+                .iter()
+                .for_each(|c| println!("  {:<44} {}", c.usage, c.about)),
+            "quit" | "exit" => return Ok(false),
+            v => return Err(format!("unknown command {}, type help", v)),
+        }
+        Ok(true)
+    }
+}
+
+fn err(v: ClientError) -> String {
+    v.to_string()
+}
+
+fn parse_interval(v: Option<&str>) -> Result<Duration, String> {
+    match v {
+        None => Ok(DEFAULT_INTERVAL),
+        Some(v) => v
+            .parse::<f64>()
+            .ok()
+            .filter(|secs| *secs > 0.0)
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(|| format!("invalid interval {}", v)),
+    }
+}
+
+fn default_history() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(HISTORY_FILE)
+}
+
 fn main() {
-    println!("Running client {}", libclient::add(3, 2));
+    let mut addr = String::from(DEFAULT_ADDR);
+    let mut token: Option<String> = None;
+    let mut history = default_history();
+    let mut discovery = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--discover" => discovery = true,
+            "--token" => token = Some(args.next().unwrap_or_else(|| usage())),
+            "--history" => history = args.next().unwrap_or_else(|| usage()).into(),
+            "-h" | "--help" => usage(),
+            _ => addr = arg,
+        }
+    }
+    if discovery {
+        let servers = discover(broadcast_target(), DISCOVERY_TIMEOUT).unwrap_or_else(|v| {
+            println!("Discovery failed: {}", v);
+            std::process::exit(2)
+        });
+        servers
+            .iter()
+            .for_each(|s| println!("Found {} v{} at {}", s.name, s.version, s.addr));
+        match servers.first() {
+            Some(s) => addr = s.addr.to_string(),
+            None => {
+                println!("No servers found");
+                std::process::exit(2)
+            }
+        }
+    }
+
+    let client = match IotClient::connect(addr.clone()) {
+        Ok(v) => v,
+        Err(v) => {
+            println!("Cannot connect to {}: {}", addr, v);
+            std::process::exit(2)
+        }
+    };
+    // Ctrl-C is read by the editor while it waits for input,
+    // the signal is received only during watch and subscribe
+    let interrupted = Arc::new(AtomicBool::new(false));
+    if let Err(v) = signal_hook::flag::register(SIGINT, interrupted.clone()) {
+        panic!("cannot handle signals {}", v);
+    }
+    let mut shell = Shell {
+        client,
+        devices: vec![],
+        interrupted,
+    };
+    if let Some(token) = token {
+        if let Err(v) = shell.client.authenticate(&token) {
+            println!("Cannot authenticate: {}", v);
+        }
+    }
+    println!("Connected to {}, type help for commands", addr);
+    if let Err(v) = shell.refresh() {
+        println!("error: {}", v);
+    }
+    shell.print_devices();
+
+    let mut editor: Editor<ShellHelper, DefaultHistory> = match Editor::new() {
+        Ok(v) => v,
+        Err(v) => panic!("cannot create editor {}", v),
+    };
+    editor.set_helper(Some(ShellHelper { devices: vec![] }));
+    let _ = editor.load_history(&history);
+    loop {
+        if let Some(helper) = editor.helper_mut() {
+            helper.devices = shell.devices.clone();
+        }
+        let line = match editor.readline("iot> ") {
+            Ok(v) => v,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(v) => {
+                println!("error: {}", v);
+                break;
+            }
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        match shell.execute(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(v) => println!("error: {}", v),
+        }
+    }
+    if let Err(v) = editor.save_history(&history) {
+        println!("Cannot save history {:?}: {}", history, v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libserver::{auth::Role, iotserver::IotServerBuilder, ACSocket, PowerState};
+    use rustyline::history::DefaultHistory;
+
+    use super::*;
+
+    fn info(id: &str, name: &str) -> DeviceInfo {
+        DeviceInfo {
+            id: id.to_string(),
+            name: name.to_string(),
+            kind: String::from("socket"),
+            power: PowerState::OFF,
+        }
+    }
+
+    fn shell(sockets: Vec<ACSocket>) -> Shell {
+        let mut builder = IotServerBuilder::new();
+        builder
+            .set_addr(String::from("127.0.0.1:0"))
+            .set_anonymous_role(Role::Operator);
+        for s in sockets {
+            builder.add_device(s);
+        }
+        let server = builder.build().unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        Shell {
+            client: IotClient::connect(addr.to_string()).unwrap(),
+            devices: vec![],
+            interrupted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval(None), Ok(DEFAULT_INTERVAL));
+        assert_eq!(parse_interval(Some("2.5")), Ok(Duration::from_millis(2500)));
+        for v in ["0", "-1", "abc", "", "NaN", "inf", "1e30"] {
+            assert!(parse_interval(Some(v)).is_err(), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_completion() {
+        let helper = ShellHelper {
+            devices: vec![info("cq1abc", "kettle"), info("cq2def", "lamp")],
+        };
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let complete = |line: &str| {
+            let (start, pairs) = helper.complete(line, line.len(), &ctx).unwrap();
+            let values: Vec<String> = pairs.into_iter().map(|p| p.display).collect();
+            (start, values)
+        };
+        assert_eq!(
+            complete("re"),
+            (
+                0,
+                vec!["remove".into(), "rename".into(), "reset-energy".into()]
+            )
+        );
+        assert_eq!(
+            complete("on cq"),
+            (3, vec!["cq1abc".into(), "cq2def".into()])
+        );
+        assert_eq!(complete("off cq1"), (4, vec!["cq1abc".into()]));
+        assert_eq!(complete("status k"), (7, vec!["kettle".into()]));
+        assert_eq!(complete("watch e"), (6, vec!["energy".into()]));
+        assert_eq!(complete("watch energy l"), (13, vec!["lamp".into()]));
+        assert_eq!(complete("auth cq"), (5, vec![]));
+        assert_eq!(complete("on cq1abc x"), (10, vec![]));
+        assert_eq!(complete("unknown "), (8, vec![]));
+
+        let pairs = candidates("a", ["b", "ab", "", "aa", "ab"].into_iter());
+        assert_eq!(
+            pairs.iter().map(|p| p.display.as_str()).collect::<Vec<_>>(),
+            vec!["aa", "ab"]
+        );
+        assert_eq!(pairs[0].replacement, "aa ");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut shell = shell(vec![]);
+        shell.devices = vec![
+            info("cq1abc", "kettle"),
+            info("cq2def", "lamp"),
+            info("cq3ghi", "lamp"),
+        ];
+        assert_eq!(shell.resolve(Some("cq2def")), Ok(String::from("cq2def")));
+        assert_eq!(shell.resolve(Some("kettle")), Ok(String::from("cq1abc")));
+        assert!(shell.resolve(Some("lamp")).is_err());
+        assert!(shell.resolve(None).is_err());
+        // unknown keys are sent as is, server reports the unknown device
+        assert_eq!(shell.resolve(Some("other")), Ok(String::from("other")));
+    }
+
+    #[test]
+    fn test_execute() {
+        let socket = ACSocket::with_name(String::from("kettle"));
+        let id = socket.get_id().to_string();
+        let mut shell = shell(vec![socket]);
+        assert_eq!(shell.execute(""), Ok(true));
+        assert_eq!(shell.execute("list"), Ok(true));
+        assert_eq!(shell.devices, vec![info(&id, "kettle")]);
+
+        assert_eq!(shell.execute("on kettle"), Ok(true));
+        assert_eq!(shell.execute("list"), Ok(true));
+        assert_eq!(shell.devices[0].power, PowerState::ON);
+        assert_eq!(shell.execute(&format!("off {}", id)), Ok(true));
+        assert_eq!(shell.execute("status kettle"), Ok(true));
+
+        assert!(shell
+            .execute("jump")
+            .unwrap_err()
+            .contains("unknown command"));
+        assert_eq!(shell.execute("on"), Err(String::from("device is required")));
+        assert!(shell.execute("on other").is_err());
+        assert_eq!(
+            shell.execute("auth"),
+            Err(String::from("token is required"))
+        );
+        assert_eq!(
+            shell.execute("subscribe 0"),
+            Err(String::from("invalid interval 0"))
+        );
+        assert_eq!(
+            shell.execute("watch energy kettle x"),
+            Err(String::from("invalid interval x"))
+        );
+        assert!(shell.execute("watch voltage kettle").is_err());
+        assert_eq!(shell.execute("help"), Ok(true));
+        assert_eq!(shell.execute("quit"), Ok(false));
+        assert_eq!(shell.execute("  exit  "), Ok(false));
+    }
 }
@@ -0,0 +1,87 @@
+use std::{sync::Arc, thread};
+
+use libclient::IotClient;
+use libprotocol::{log_error, log_info};
+use libserver::{iotserver::IotServerBuilder, ACSocket};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:0";
+
+fn usage() -> ! {
+    println!("Usage: emulator [address] [--socket <name>]... [--admin-token <token>]");
+    println!(
+        "                [--register <hub address>] [--hub-token <token>] [--advertise <address>]"
+    );
+    println!();
+    println!("Runs simulated sockets, one socket if none is given. Emulator registers itself");
+    println!("on the hub with the advertised address, which is the listening one by default.");
+    std::process::exit(1)
+}
+
+fn main() {
+    let mut addr = String::from(DEFAULT_ADDR);
+    let mut names: Vec<String> = vec![];
+    let mut admin_tokens: Vec<String> = vec![];
+    let mut hub: Option<String> = None;
+    let mut hub_token: Option<String> = None;
+    let mut advertise: Option<String> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => names.push(args.next().unwrap_or_else(|| usage())),
+            "--admin-token" => admin_tokens.push(args.next().unwrap_or_else(|| usage())),
+            "--register" => hub = Some(args.next().unwrap_or_else(|| usage())),
+            "--hub-token" => hub_token = Some(args.next().unwrap_or_else(|| usage())),
+            "--advertise" => advertise = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => addr = arg,
+        }
+    }
+    if names.is_empty() {
+        names.push(String::from("socket"));
+    }
+
+    let mut builder = IotServerBuilder::new();
+    builder.set_addr(addr);
+    admin_tokens.into_iter().for_each(|t| {
+        builder.add_admin_token(t);
+    });
+    for name in names {
+        let socket = ACSocket::with_name(name);
+        log_info!("Socket {} {}", socket.get_id(), socket.get_name());
+        builder.add_device(socket);
+    }
+    let server = match builder.build() {
+        Ok(v) => Arc::new(v),
+        Err(v) => panic!("cannot start emulator {}", v),
+    };
+    let local = match server.local_addr() {
+        Ok(v) => v.to_string(),
+        Err(v) => panic!("cannot get emulator address {}", v),
+    };
+    println!("Emulator is listening on {}", local);
+
+    // hub connects back to the emulator, so the server must be running
+    let runner = {
+        let server = server.clone();
+        thread::spawn(move || server.run())
+    };
+    if let Some(hub) = hub {
+        let advertise = advertise.unwrap_or(local);
+        let result = IotClient::connect(hub.clone()).and_then(|mut c| {
+            if let Some(token) = hub_token {
+                c.authenticate(&token)?;
+            }
+            c.register_emulator(&advertise)
+        });
+        match result {
+            Ok(n) => log_info!(
+                "Registered on hub {} as {} with {} devices",
+                hub,
+                advertise,
+                n
+            ),
+            Err(v) => log_error!("Cannot register on hub {}: {}", hub, v),
+        }
+    }
+    let _ = runner.join();
+}
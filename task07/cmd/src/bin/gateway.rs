@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use libgateway::Gateway;
+use libprotocol::{log_error, log_info};
+use libserver::{iotserver::IotServerBuilder, metrics};
+
+fn usage() -> ! {
+    println!(
+        "Usage: gateway <address> <upstream address>... [--metrics <address>] [--upstream-token <token>]"
+    );
+    println!("                [--admin-token <token>]");
+    std::process::exit(1)
+}
+
+fn main() {
+    let mut addr: Option<String> = None;
+    let mut upstreams: Vec<String> = vec![];
+    let mut metrics_addr: Option<String> = None;
+    let mut token: Option<String> = None;
+    let mut admin_tokens: Vec<String> = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--metrics" => metrics_addr = Some(args.next().unwrap_or_else(|| usage())),
+            "--upstream-token" => token = Some(args.next().unwrap_or_else(|| usage())),
+            "--admin-token" => admin_tokens.push(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ if addr.is_none() => addr = Some(arg),
+            _ => upstreams.push(arg),
+        }
+    }
+    let addr = addr.unwrap_or_else(|| usage());
+    if upstreams.is_empty() {
+        usage();
+    }
+
+    let gateway = Arc::new(Gateway::with_token(upstreams, token));
+    match gateway.list_devices() {
+        Ok(v) => v.iter().for_each(|id| log_info!("Serving device {}", id)),
+        Err(v) => log_error!("{}", v),
+    }
+    let mut builder = IotServerBuilder::new();
+    builder.set_addr(addr).set_backend(gateway);
+    admin_tokens.into_iter().for_each(|t| {
+        builder.add_admin_token(t);
+    });
+    let server = match builder.build() {
+        Ok(v) => v,
+        Err(v) => panic!("cannot start gateway {}", v),
+    };
+    if let Some(v) = metrics_addr {
+        if let Err(v) = metrics::start_metrics_endpoint(v, server.metrics()) {
+            panic!("cannot start metrics endpoint {}", v);
+        }
+    }
+    server.run();
+}
@@ -0,0 +1,520 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use libclient::{error::ClientError, IotClient};
+use libprotocol::commands::Commands;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:8088";
+const DEFAULT_MIX: &str = "status=4,consumption=4,on=1,off=1,list=1";
+/// Maximal weight of the command in the mix
+const MAX_WEIGHT: usize = 1000;
+/// Maximal duration, report period and request interval in seconds
+const MAX_SECONDS: f64 = 30.0 * 24.0 * 3600.0;
+/// Latency histogram buckets: exact below 2^SUB_BITS microseconds, then
+/// 2^(SUB_BITS - 1) buckets for each power of two
+const SUB_BITS: u32 = 6;
+const HALF: u64 = 1 << (SUB_BITS - 1);
+const BUCKETS: usize = ((64 - SUB_BITS + 2) as u64 * HALF) as usize;
+
+fn usage() -> ! {
+    println!("Usage: loadtest [address] [--connections <n>] [--rate <requests per second>]");
+    println!(
+        "                [--duration <seconds>] [--mix <command=weight,...>] [--report <seconds>]"
+    );
+    println!();
+    println!("Commands of the mix: on, off, status, consumption, energy, list, describe");
+    println!(
+        "Default mix: {}, rate 0 means as fast as possible",
+        DEFAULT_MIX
+    );
+    std::process::exit(1)
+}
+
+fn parse<T: std::str::FromStr>(v: Option<String>) -> T {
+    v.and_then(|v| v.parse().ok()).unwrap_or_else(|| usage())
+}
+
+/// Parses positive number of seconds not greater than `MAX_SECONDS`
+fn parse_secs(v: &str) -> Option<Duration> {
+    v.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v > 0.0 && *v <= MAX_SECONDS)
+        .map(Duration::from_secs_f64)
+}
+
+/// Interval between requests of the connection, `None` when rate is unlimited
+/// or invalid: not finite, negative or so small that interval is too long
+fn request_interval(connections: usize, rate: f64) -> Option<Option<Duration>> {
+    match rate {
+        0.0 => Some(None),
+        v if v.is_finite() && v > 0.0 => {
+            let secs = connections as f64 / v;
+            (secs <= MAX_SECONDS).then(|| Some(Duration::from_secs_f64(secs)))
+        }
+        _ => None,
+    }
+}
+
+fn parse_command(name: &str) -> Option<Commands> {
+    match name {
+        "on" => Some(Commands::PowerOn),
+        "off" => Some(Commands::PowerOff),
+        "status" => Some(Commands::GetStatus),
+        "consumption" => Some(Commands::GetConsumption),
+        "energy" => Some(Commands::GetEnergy),
+        "list" => Some(Commands::ListDevices),
+        "describe" => Some(Commands::DescribeDevice),
+        _ => None,
+    }
+}
+
+/// Parses mix into the sequence where each command is repeated by its weight
+fn parse_mix(mix: &str) -> Result<Vec<Commands>, String> {
+    let mut sequence = vec![];
+    for item in mix.split(',') {
+        let (name, weight) = item.split_once('=').unwrap_or((item, "1"));
+        let cmd = parse_command(name.trim()).ok_or(format!("unknown command {}", name))?;
+        let weight: usize = weight
+            .trim()
+            .parse()
+            .ok()
+            .filter(|v| *v <= MAX_WEIGHT)
+            .ok_or(format!("invalid weight of {}", name))?;
+        sequence.extend(std::iter::repeat_n(cmd, weight));
+    }
+    match sequence.is_empty() {
+        true => Err(String::from("mix is empty")),
+        false => Ok(sequence),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Options {
+    addr: String,
+    connections: usize,
+    /// Total requests per second, 0 is unlimited
+    rate: f64,
+    /// Interval between requests of the connection derived from the rate
+    interval: Option<Duration>,
+    duration: Duration,
+    mix: Vec<Commands>,
+    report: Option<Duration>,
+}
+
+/// Log-linear histogram of latencies in microseconds with relative error
+/// below 2^(1 - SUB_BITS)
+#[derive(Debug)]
+struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            total: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn bucket(v: u64) -> usize {
+        match v < 2 * HALF {
+            true => v as usize,
+            false => {
+                let shift = 63 - v.leading_zeros() - (SUB_BITS - 1);
+                (shift as u64 * HALF + (v >> shift)) as usize
+            }
+        }
+    }
+
+    /// The highest value which falls into the bucket
+    fn highest(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        match bucket < 2 * HALF {
+            true => bucket,
+            false => {
+                let shift = bucket / HALF - 1;
+                ((bucket - shift * HALF) << shift) + ((1 << shift) - 1)
+            }
+        }
+    }
+
+    fn record(&mut self, v: Duration) {
+        let us = v.as_micros().min(u64::MAX as u128) as u64;
+        self.counts[Self::bucket(us)] += 1;
+        self.total += 1;
+        self.min = self.min.min(us);
+        self.max = self.max.max(us);
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        self.counts
+            .iter_mut()
+            .zip(&other.counts)
+            .for_each(|(a, b)| *a += b);
+        self.total += other.total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn len(&self) -> u64 {
+        self.total
+    }
+
+    /// Nearest rank percentile, exact for min and max
+    fn percentile(&self, p: f64) -> Duration {
+        if self.total == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((self.total as f64 * p.clamp(0.0, 100.0) / 100.0).ceil() as u64).max(1);
+        let mut seen = 0;
+        let us = self
+            .counts
+            .iter()
+            .position(|v| {
+                seen += v;
+                seen >= rank
+            })
+            .map_or(self.max, Self::highest);
+        Duration::from_micros(us.clamp(self.min, self.max))
+    }
+}
+
+/// Results of the single connection
+#[derive(Debug, Default)]
+struct Stats {
+    latencies: Histogram,
+    commands: BTreeMap<&'static str, u64>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    fn error(&mut self, kind: String) {
+        *self.errors.entry(kind).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: Stats) {
+        self.latencies.merge(&other.latencies);
+        other
+            .commands
+            .into_iter()
+            .for_each(|(k, v)| *self.commands.entry(k).or_default() += v);
+        other
+            .errors
+            .into_iter()
+            .for_each(|(k, v)| *self.errors.entry(k).or_default() += v);
+    }
+}
+
+fn error_kind(v: &ClientError) -> String {
+    match v {
+        ClientError::Server(status, _) => format!("{:?}", status),
+        ClientError::Connect(_) => String::from("connect"),
+        ClientError::Send(_) | ClientError::Recv(_) => String::from("io"),
+        ClientError::UnexpectedResponse => String::from("unexpected response"),
+    }
+}
+
+fn execute(client: &mut IotClient, cmd: Commands, id: &str) -> Result<(), ClientError> {
+    match cmd {
+        Commands::PowerOn => client.power_on(id),
+        Commands::PowerOff => client.power_off(id),
+        Commands::GetStatus => client.get_status(id).map(|_| ()),
+        Commands::GetConsumption => client.get_consumption(id).map(|_| ()),
+        Commands::GetEnergy => client.get_energy(id).map(|_| ()),
+        Commands::DescribeDevice => client.describe_device(id).map(|_| ()),
+        _ => client.list_devices().map(|_| ()),
+    }
+}
+
+/// Sends commands of the mix to devices of the server in turn until the deadline.
+/// Broken connection is re-established before the next request.
+fn worker(index: usize, options: &Options, deadline: Instant, sent: &AtomicU64) -> Stats {
+    let mut stats = Stats::default();
+    let interval = options.interval;
+    let mut client: Option<IotClient> = None;
+    let mut devices: Vec<String> = vec![];
+    let mut next = Instant::now();
+    let mut i = index;
+    while Instant::now() < deadline {
+        if let Some(interval) = interval {
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+            next += interval;
+        }
+        if client.is_none() {
+            match IotClient::connect(options.addr.clone()) {
+                Ok(mut c) => {
+                    devices = c.list_devices().unwrap_or_default();
+                    client = Some(c);
+                }
+                Err(v) => {
+                    stats.error(error_kind(&v));
+                    // avoid busy loop when server is down
+                    thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+            }
+        }
+        let cmd = match devices.is_empty() {
+            true => Commands::ListDevices,
+            false => options.mix[i % options.mix.len()],
+        };
+        let id = devices
+            .get(i % devices.len().max(1))
+            .cloned()
+            .unwrap_or_default();
+        i += 1;
+
+        let started = Instant::now();
+        let result = execute(client.as_mut().unwrap(), cmd, &id);
+        stats.latencies.record(started.elapsed());
+        *stats.commands.entry(cmd.name()).or_default() += 1;
+        sent.fetch_add(1, Ordering::Relaxed);
+        if let Err(v) = result {
+            if !matches!(v, ClientError::Server(..)) {
+                client = None;
+            }
+            stats.error(error_kind(&v));
+        }
+    }
+    stats
+}
+
+fn ms(v: Duration) -> f64 {
+    v.as_secs_f64() * 1000.0
+}
+
+fn print_report(options: &Options, elapsed: Duration, stats: Stats) -> u64 {
+    let total = stats.latencies.len();
+    let errors: u64 = stats.errors.values().sum();
+    println!();
+    println!(
+        "Connections {}, target rate {}, duration {:.1} s",
+        options.connections,
+        match options.rate > 0.0 {
+            true => format!("{}/s", options.rate),
+            false => String::from("unlimited"),
+        },
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "Requests {}, throughput {:.1}/s, errors {}",
+        total,
+        total as f64 / elapsed.as_secs_f64(),
+        errors
+    );
+    println!(
+        "Latency ms: min {:.3}, p50 {:.3}, p90 {:.3}, p99 {:.3}, p99.9 {:.3}, max {:.3}",
+        ms(stats.latencies.percentile(0.0)),
+        ms(stats.latencies.percentile(50.0)),
+        ms(stats.latencies.percentile(90.0)),
+        ms(stats.latencies.percentile(99.0)),
+        ms(stats.latencies.percentile(99.9)),
+        ms(stats.latencies.percentile(100.0)),
+    );
+    println!("Commands:");
+    stats
+        .commands
+        .iter()
+        .for_each(|(k, v)| println!("  {:<16} {}", k, v));
+    if errors > 0 {
+        println!("Errors:");
+        stats
+            .errors
+            .iter()
+            .for_each(|(k, v)| println!("  {:<16} {}", k, v));
+    }
+    errors
+}
+
+fn main() {
+    let mut options = Options {
+        addr: String::from(DEFAULT_ADDR),
+        connections: 8,
+        rate: 0.0,
+        interval: None,
+        duration: Duration::from_secs(10),
+        mix: parse_mix(DEFAULT_MIX).unwrap(),
+        report: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--connections" => options.connections = parse(args.next()),
+            "--rate" => options.rate = parse(args.next()),
+            "--duration" => {
+                options.duration =
+                    parse_secs(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|| usage())
+            }
+            "--mix" => {
+                options.mix =
+                    parse_mix(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|v| {
+                        println!("Invalid mix: {}", v);
+                        usage()
+                    })
+            }
+            "--report" => {
+                options.report = Some(
+                    parse_secs(&args.next().unwrap_or_else(|| usage())).unwrap_or_else(|| usage()),
+                )
+            }
+            "-h" | "--help" => usage(),
+            _ => options.addr = arg,
+        }
+    }
+    if options.connections == 0 {
+        usage();
+    }
+    options.interval =
+        request_interval(options.connections, options.rate).unwrap_or_else(|| usage());
+
+    println!(
+        "Testing {} with {} connections for {:.1} s",
+        options.addr,
+        options.connections,
+        options.duration.as_secs_f64()
+    );
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let sent = AtomicU64::new(0);
+    let stats = thread::scope(|s| {
+        let workers: Vec<_> = (0..options.connections)
+            .map(|i| {
+                let (options, sent) = (&options, &sent);
+                s.spawn(move || worker(i, options, deadline, sent))
+            })
+            .collect();
+        // progress of the long running soak test
+        if let Some(report) = options.report {
+            let mut last = 0;
+            while Instant::now() + report < deadline {
+                thread::sleep(report);
+                let total = sent.load(Ordering::Relaxed);
+                println!(
+                    "{:>8.1} s  {} requests, {:.1}/s",
+                    started.elapsed().as_secs_f64(),
+                    total,
+                    (total - last) as f64 / report.as_secs_f64()
+                );
+                last = total;
+            }
+        }
+        let mut stats = Stats::default();
+        workers
+            .into_iter()
+            .for_each(|w| stats.merge(w.join().unwrap()));
+        stats
+    });
+    let errors = print_report(&options, started.elapsed(), stats);
+    if errors > 0 {
+        std::process::exit(3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix() {
+        assert_eq!(
+            parse_mix("on=2, off").unwrap(),
+            vec![Commands::PowerOn, Commands::PowerOn, Commands::PowerOff]
+        );
+        assert_eq!(
+            parse_mix("status=0,list=1").unwrap(),
+            vec![Commands::ListDevices]
+        );
+        assert_eq!(parse_mix(DEFAULT_MIX).unwrap().len(), 11);
+        for mix in [
+            "",
+            "on=0",
+            "on=0,off=0",
+            "jump=1",
+            "on=",
+            "on=-1",
+            "on=x",
+            "on=1,,off=1",
+            "on=1=2",
+            "on=1000000",
+        ] {
+            assert!(parse_mix(mix).is_err(), "{}", mix);
+        }
+    }
+
+    #[test]
+    fn test_percentile() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), Duration::ZERO);
+        assert_eq!(histogram.percentile(100.0), Duration::ZERO);
+        histogram.record(Duration::from_millis(7));
+        assert_eq!(histogram.percentile(0.0), Duration::from_millis(7));
+        assert_eq!(histogram.percentile(99.9), Duration::from_millis(7));
+
+        // exact below 2^SUB_BITS microseconds
+        let mut small = Histogram::default();
+        (1..=50).for_each(|v| small.record(Duration::from_micros(v)));
+        assert_eq!(small.percentile(0.0), Duration::from_micros(1));
+        assert_eq!(small.percentile(50.0), Duration::from_micros(25));
+        assert_eq!(small.percentile(98.0), Duration::from_micros(49));
+        assert_eq!(small.percentile(150.0), Duration::from_micros(50));
+
+        let mut large = Histogram::default();
+        (1..=100).for_each(|v| large.record(Duration::from_millis(v)));
+        let mut merged = Histogram::default();
+        merged.merge(&small);
+        merged.merge(&large);
+        assert_eq!(merged.len(), 150);
+        assert_eq!(merged.percentile(0.0), Duration::from_micros(1));
+        assert_eq!(merged.percentile(100.0), Duration::from_millis(100));
+        for (p, expected) in [(10.0, 0.015), (50.0, 25.0), (90.0, 85.0), (99.0, 99.0)] {
+            let v = ms(merged.percentile(p));
+            assert!((v - expected).abs() <= expected / 32.0, "{} {}", p, v);
+        }
+        large.record(Duration::MAX);
+        assert_eq!(large.percentile(100.0).as_micros(), u64::MAX as u128);
+    }
+
+    #[test]
+    fn test_buckets() {
+        for v in (0..200).chain([1000, 4095, 4096, 123_456_789, u64::MAX - 1, u64::MAX]) {
+            let bucket = Histogram::bucket(v);
+            assert!(bucket < BUCKETS, "{}", v);
+            assert!(Histogram::highest(bucket) >= v, "{}", v);
+            assert!(
+                Histogram::highest(bucket) - v <= v >> (SUB_BITS - 1),
+                "{}",
+                v
+            );
+            if bucket > 0 {
+                assert!(Histogram::highest(bucket - 1) < v, "{}", v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(parse_secs("1.5"), Some(Duration::from_millis(1500)));
+        for v in ["0", "-1", "NaN", "inf", "1e300", "x", ""] {
+            assert_eq!(parse_secs(v), None, "{}", v);
+        }
+        assert_eq!(request_interval(8, 0.0), Some(None));
+        assert_eq!(request_interval(8, 4.0), Some(Some(Duration::from_secs(2))));
+        for rate in [-1.0, f64::NAN, f64::INFINITY, 1e-300] {
+            assert_eq!(request_interval(8, rate), None, "{}", rate);
+        }
+    }
+}
@@ -1 +1,242 @@
-fn main() {}
+use std::{path::PathBuf, sync::Arc, thread, time::Duration};
+
+use libprotocol::{log, log_error, log_info};
+#[cfg(unix)]
+use libserver::systemd;
+use libserver::{
+    config::{ConfigError, ServerConfig},
+    discovery::{self, Announcement},
+    hub::Hub,
+    iotserver::{IotServer, IotServerBuilder},
+    metrics, ACSocket,
+};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:8088";
+
+fn usage() -> ! {
+    println!("Usage: server --config <path> [--admin-token <token>]... [--state-file <path>]");
+    println!("       server [address] [--metrics <address>] [--admin-token <token>] [--state-file <path>] [--drain-timeout <secs>]");
+    println!("              [--discovery <udp address>] [--name <name>] [--audit-log <path>]");
+    println!("       server [address] --hub [--emulator <address>]... [--emulator-token <token>] [--metrics <address>] [--admin-token <token>]");
+    println!();
+    println!("--admin-token and --state-file are applied over the configuration file, other options can't be combined with it.");
+    println!("Listening sockets passed by systemd socket activation replace configured addresses.");
+    println!("SIGTERM stops the server after commands in progress, SIGHUP reloads configuration.");
+    println!("--discovery answers LAN broadcast probes, e.g. on 0.0.0.0:8089.");
+    std::process::exit(1)
+}
+
+#[cfg(unix)]
+fn notify(state: &str) {
+    if let Err(v) = systemd::notify(state) {
+        log_error!("Cannot notify service manager {}", v);
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
+
+/// Configuration file with command line options applied over it
+struct ConfigSource {
+    path: PathBuf,
+    admin_tokens: Vec<String>,
+    state_file: Option<PathBuf>,
+}
+
+impl ConfigSource {
+    fn load(&self) -> Result<ServerConfig, ConfigError> {
+        let mut config = ServerConfig::load(&self.path)?;
+        config.override_with(&self.admin_tokens, self.state_file.as_deref())?;
+        Ok(config)
+    }
+}
+
+/// Reloads configuration. Only settings are applied to the running
+/// server, changes of listeners, devices, metrics, storage and hub require restart.
+fn reload_config(source: &ConfigSource, started: &ServerConfig, server: &IotServer) {
+    notify("RELOADING=1");
+    match source.load() {
+        Ok(config) => {
+            if !config.is_reloadable_from(started) {
+                log_error!("listeners, devices, metrics, storage and hub changes require restart, they are ignored");
+            }
+            log::set_level(config.logging.level.into());
+            server.reload(config.settings());
+            log_info!("Configuration {:?} is reloaded", source.path);
+        }
+        Err(v) => log_error!("Cannot reload configuration, keep the current one: {}", v),
+    }
+    notify("READY=1");
+}
+
+/// SIGHUP reloads configuration, SIGTERM and SIGINT stop the server
+fn handle_signals(config: Option<(ConfigSource, ServerConfig)>, server: Arc<IotServer>) {
+    let mut signals = match Signals::new([SIGHUP, SIGTERM, SIGINT]) {
+        Ok(v) => v,
+        Err(v) => panic!("cannot handle signals {}", v),
+    };
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match (signal, &config) {
+                (SIGHUP, Some((source, started))) => reload_config(source, started, &server),
+                (SIGHUP, None) => log_info!("No configuration file to reload"),
+                _ => {
+                    log_info!(
+                        "Stopping server, {} clients are connected",
+                        server.active_connections()
+                    );
+                    notify("STOPPING=1");
+                    server.shutdown();
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn main() {
+    let mut addr = String::from(DEFAULT_ADDR);
+    let mut config_path: Option<PathBuf> = None;
+    let mut metrics_addr: Option<String> = None;
+    let mut admin_tokens: Vec<String> = vec![];
+    let mut state_file: Option<PathBuf> = None;
+    let mut hub = false;
+    let mut emulators: Vec<String> = vec![];
+    let mut emulator_token: Option<String> = None;
+    let mut drain_timeout: Option<Duration> = None;
+    let mut discovery_addr: Option<String> = None;
+    let mut audit_log: Option<PathBuf> = None;
+    let mut name = String::from(discovery::DEFAULT_NAME);
+    // options which are configured by the file when it is given
+    let mut standalone: Vec<String> = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if !matches!(
+            arg.as_str(),
+            "--config" | "--admin-token" | "--state-file" | "-h" | "--help"
+        ) {
+            standalone.push(arg.clone());
+        }
+        match arg.as_str() {
+            "--config" => config_path = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--metrics" => metrics_addr = Some(args.next().unwrap_or_else(|| usage())),
+            "--admin-token" => admin_tokens.push(args.next().unwrap_or_else(|| usage())),
+            "--state-file" => state_file = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--hub" => hub = true,
+            "--emulator" => emulators.push(args.next().unwrap_or_else(|| usage())),
+            "--discovery" => discovery_addr = Some(args.next().unwrap_or_else(|| usage())),
+            "--name" => name = args.next().unwrap_or_else(|| usage()),
+            "--audit-log" => audit_log = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--emulator-token" => emulator_token = Some(args.next().unwrap_or_else(|| usage())),
+            "--drain-timeout" => {
+                drain_timeout = match args.next().map(|v| v.parse()) {
+                    Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+                    _ => usage(),
+                }
+            }
+            "-h" | "--help" => usage(),
+            _ => addr = arg,
+        }
+    }
+    if config_path.is_some() && !standalone.is_empty() {
+        println!("{} can't be combined with --config", standalone.join(" "));
+        usage();
+    }
+
+    // must be taken before any thread is spawned
+    #[cfg(unix)]
+    let listeners = match systemd::listen_fds() {
+        Ok(v) => v,
+        Err(v) => panic!("cannot take sockets passed by systemd {}", v),
+    };
+    #[cfg(not(unix))]
+    let listeners = vec![];
+
+    let source = config_path.map(|path| ConfigSource {
+        path,
+        admin_tokens: admin_tokens.clone(),
+        state_file: state_file.clone(),
+    });
+    let config = source.as_ref().map(|source| match source.load() {
+        Ok(v) => v,
+        Err(v) => {
+            println!("Invalid configuration {:?}: {}", source.path, v);
+            std::process::exit(2)
+        }
+    });
+    let mut builder = match &config {
+        Some(config) => {
+            log::set_level(config.logging.level.into());
+            metrics_addr = config.metrics.as_ref().map(|m| m.address.clone());
+            if let Some(d) = &config.discovery {
+                discovery_addr = Some(d.address.clone());
+                name = d.name.clone();
+            }
+            config.to_builder()
+        }
+        None => {
+            let mut builder = IotServerBuilder::new();
+            builder.set_addr(addr);
+            admin_tokens.into_iter().for_each(|t| {
+                builder.add_admin_token(t);
+            });
+            if let Some(v) = drain_timeout {
+                builder.set_drain_timeout(v);
+            }
+            if let Some(v) = audit_log {
+                builder.set_audit_log(v);
+            }
+            if hub {
+                builder.set_backend(Arc::new(Hub::with_token(emulators, emulator_token)));
+            } else {
+                if let Some(v) = state_file {
+                    builder.set_state_file(v);
+                }
+                // initial devices, they are replaced by saved ones if state file exists
+                for _ in 0..3 {
+                    builder.add_device(ACSocket::new());
+                }
+            }
+            builder
+        }
+    };
+    if !listeners.is_empty() {
+        log_info!("Serving {} sockets passed by systemd", listeners.len());
+        builder.set_listeners(listeners);
+    }
+    let server = match builder.build() {
+        Ok(v) => Arc::new(v),
+        Err(v) => panic!("cannot start server {}", v),
+    };
+    if let Some(v) = metrics_addr {
+        if let Err(v) = metrics::start_metrics_endpoint(v, server.metrics()) {
+            panic!("cannot start metrics endpoint {}", v);
+        }
+    }
+    if let Some(v) = discovery_addr {
+        // the first listener is announced
+        let port = match server.local_addr() {
+            Ok(v) => v.port(),
+            Err(v) => panic!("cannot get server address {}", v),
+        };
+        if let Err(v) = discovery::start_discovery_responder(v, Announcement::new(name, port)) {
+            panic!("cannot start discovery responder {}", v);
+        }
+    }
+    handle_signals(source.zip(config), server.clone());
+    notify("READY=1");
+    server.run();
+
+    let timeout = server.settings().drain_timeout;
+    match server.wait_idle(timeout) {
+        true => log_info!("Server is stopped"),
+        false => log_error!(
+            "Server is stopped, {} clients are dropped",
+            server.active_connections()
+        ),
+    }
+}
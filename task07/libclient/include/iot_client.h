@@ -0,0 +1,90 @@
+/* C API of the IoT client, generated by cbindgen from src/ffi.rs */
+
+#ifndef IOT_CLIENT_H
+#define IOT_CLIENT_H
+
+#include <stddef.h>
+
+// Result code of the C API, server statuses keep their protocol values
+typedef enum IotStatus {
+  IOT_STATUS_OK = 0,
+  IOT_STATUS_UNKNOWN_COMMAND = 1,
+  IOT_STATUS_BAD_REQUEST = 2,
+  IOT_STATUS_UNKNOWN_DEVICE = 3,
+  IOT_STATUS_UPSTREAM_UNAVAILABLE = 4,
+  IOT_STATUS_DENIED = 5,
+  IOT_STATUS_UNKNOWN_ROOM = 6,
+  // Null pointer or string which isn't valid UTF-8
+  IOT_STATUS_INVALID_ARGUMENT = 100,
+  IOT_STATUS_CONNECTION_ERROR = 101,
+  // Connection is broken while sending request or reading response
+  IOT_STATUS_IO_ERROR = 102,
+  IOT_STATUS_UNEXPECTED_RESPONSE = 103,
+} IotStatus;
+
+// Client of the IoT server
+typedef struct IotClient IotClient;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Returns message of the last error of the calling thread or null if there was no error.
+// The string is owned by the library and valid until the next call on the thread.
+const char *iot_last_error(void);
+
+// Connects to the server at `addr`, e.g. "127.0.0.1:8088", and stores handle in `client`.
+//
+// # Safety
+// `addr` must be NUL-terminated string, `client` must be valid pointer.
+enum IotStatus iot_client_connect(const char *addr, struct IotClient **client);
+
+// Closes connection and releases the handle, null is ignored.
+//
+// # Safety
+// `client` must be created by `iot_client_connect` and not released before.
+void iot_client_free(struct IotClient *client);
+
+// Stores ids of the devices in `ids` and their number in `count`.
+// The list is released by `iot_string_list_free`.
+//
+// # Safety
+// `client` must be valid handle, `ids` and `count` must be valid pointers.
+enum IotStatus iot_client_list_devices(struct IotClient *client, char ***ids, size_t *count);
+
+// Switches the device on.
+//
+// # Safety
+// `client` must be valid handle, `id` must be NUL-terminated string.
+enum IotStatus iot_client_power_on(struct IotClient *client, const char *id);
+
+// Switches the device off.
+//
+// # Safety
+// `client` must be valid handle, `id` must be NUL-terminated string.
+enum IotStatus iot_client_power_off(struct IotClient *client, const char *id);
+
+// Stores status of the device in `status`, the string is released by `iot_string_free`.
+//
+// # Safety
+// `client` must be valid handle, `id` must be NUL-terminated string,
+// `status` must be valid pointer.
+enum IotStatus iot_client_get_status(struct IotClient *client, const char *id, char **status);
+
+// Releases string returned by the library, null is ignored.
+//
+// # Safety
+// `value` must be returned by the library and not released before.
+void iot_string_free(char *value);
+
+// Releases list of strings returned by `iot_client_list_devices`, null is ignored.
+//
+// # Safety
+// `values` and `count` must be returned by the library and not released before.
+void iot_string_list_free(char **values, size_t count);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* IOT_CLIENT_H */
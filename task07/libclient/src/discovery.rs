@@ -0,0 +1,120 @@
+//! Discovery of IoT servers answering UDP probes, see `libprotocol::discovery`
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use libprotocol::discovery::{Announcement, DISCOVERY_PORT, PROBE};
+
+/// Server found by the discovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    pub name: String,
+    pub version: u32,
+    /// Address of the protocol listener: IP of the responder and announced port
+    pub addr: SocketAddr,
+}
+
+/// Broadcast address of the local network
+pub fn broadcast_target() -> SocketAddr {
+    SocketAddr::from(([255, 255, 255, 255], DISCOVERY_PORT))
+}
+
+/// Sends probe to the target, which is usually [`broadcast_target`], and collects
+/// answers received within the timeout. Every server is reported once.
+pub fn discover(
+    target: impl ToSocketAddrs,
+    timeout: Duration,
+) -> io::Result<Vec<DiscoveredServer>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+    for addr in target.to_socket_addrs()? {
+        socket.send_to(PROBE, addr)?;
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut servers: Vec<DiscoveredServer> = vec![];
+    let mut buf = [0; 512];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let (n, from) = match socket.recv_from(&mut buf) {
+            Ok(v) => v,
+            Err(v)
+                if matches!(
+                    v.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(v) => return Err(v),
+        };
+        // other datagrams sent to the port are ignored
+        let Some(announcement) = Announcement::decode(&buf[..n]) else {
+            continue;
+        };
+        let server = DiscoveredServer {
+            name: announcement.name,
+            version: announcement.version,
+            addr: SocketAddr::new(from.ip(), announcement.port),
+        };
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use libprotocol::PROTOCOL_VERSION;
+    use libserver::{discovery::start_discovery_responder, iotserver::IotServerBuilder};
+
+    use super::*;
+    use crate::IotClient;
+
+    #[test]
+    fn test_discover() {
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .build()
+            .unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.run());
+        let responder = start_discovery_responder(
+            String::from("127.0.0.1:0"),
+            Announcement::new(String::from("kitchen"), port),
+        )
+        .unwrap();
+
+        // probe is sent twice, the server is reported once
+        let servers = discover(
+            [responder, responder].as_slice(),
+            Duration::from_millis(300),
+        )
+        .unwrap();
+        assert_eq!(
+            servers,
+            vec![DiscoveredServer {
+                name: String::from("kitchen"),
+                version: PROTOCOL_VERSION,
+                addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            }]
+        );
+        let mut client = IotClient::connect(servers[0].addr.to_string()).unwrap();
+        assert!(client.list_devices().unwrap().is_empty());
+
+        // nobody answers
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let servers = discover(silent.local_addr().unwrap(), Duration::from_millis(100)).unwrap();
+        assert!(servers.is_empty());
+    }
+}
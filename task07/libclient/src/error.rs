@@ -0,0 +1,29 @@
+use libprotocol::commands::Status;
+use libprotocol::error::{ConnectError, RecvError, SendError};
+use thiserror::Error;
+
+/// Error of IoT client operations
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("connection error: {0}")]
+    Connect(#[from] ConnectError),
+    #[error("send error: {0}")]
+    Send(#[from] SendError),
+    #[error("receive error: {0}")]
+    Recv(#[from] RecvError),
+    /// Server executed command with error status
+    #[error("server error {0:?}: {1}")]
+    Server(Status, String),
+    #[error("unexpected response")]
+    UnexpectedResponse,
+}
+
+impl ClientError {
+    /// Returns status of the server error
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            ClientError::Server(s, _) => Some(*s),
+            _ => None,
+        }
+    }
+}
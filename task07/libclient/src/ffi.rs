@@ -0,0 +1,326 @@
+//! C API of the client, header is `include/iot_client.h`.
+//!
+//! Client is an opaque handle created by `iot_client_connect` and released by
+//! `iot_client_free`. Functions return `IOT_STATUS_OK` or error code, message of the
+//! last error of the thread is returned by `iot_last_error`. Strings returned through
+//! out parameters are owned by the caller and released by `iot_string_free` and
+//! `iot_string_list_free`.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    ptr,
+};
+
+use libprotocol::commands::Status;
+
+use crate::{error::ClientError, IotClient};
+
+/// Result code of the C API, server statuses keep their protocol values
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IotStatus {
+    Ok = 0,
+    UnknownCommand = 1,
+    BadRequest = 2,
+    UnknownDevice = 3,
+    UpstreamUnavailable = 4,
+    Denied = 5,
+    UnknownRoom = 6,
+    /// Null pointer or string which isn't valid UTF-8
+    InvalidArgument = 100,
+    ConnectionError = 101,
+    /// Connection is broken while sending request or reading response
+    IoError = 102,
+    UnexpectedResponse = 103,
+}
+
+impl From<Status> for IotStatus {
+    fn from(value: Status) -> Self {
+        match value {
+            Status::Ok => IotStatus::Ok,
+            Status::UnknownCommand => IotStatus::UnknownCommand,
+            Status::BadRequest => IotStatus::BadRequest,
+            Status::UnknownDevice => IotStatus::UnknownDevice,
+            Status::UpstreamUnavailable => IotStatus::UpstreamUnavailable,
+            Status::Denied => IotStatus::Denied,
+            Status::UnknownRoom => IotStatus::UnknownRoom,
+        }
+    }
+}
+
+impl From<&ClientError> for IotStatus {
+    fn from(value: &ClientError) -> Self {
+        match value {
+            ClientError::Connect(_) => IotStatus::ConnectionError,
+            ClientError::Send(_) | ClientError::Recv(_) => IotStatus::IoError,
+            ClientError::Server(s, _) => (*s).into(),
+            ClientError::UnexpectedResponse => IotStatus::UnexpectedResponse,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(status: IotStatus, msg: String) -> IotStatus {
+    // messages don't contain NUL, but the server ones come from the network
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+    status
+}
+
+fn to_status<T>(result: Result<T, ClientError>, out: impl FnOnce(T) -> IotStatus) -> IotStatus {
+    match result {
+        Ok(v) => out(v),
+        Err(v) => set_error((&v).into(), v.to_string()),
+    }
+}
+
+unsafe fn arg_str<'a>(value: *const c_char, name: &str) -> Result<&'a str, IotStatus> {
+    if value.is_null() {
+        return Err(set_error(
+            IotStatus::InvalidArgument,
+            format!("{} is null", name),
+        ));
+    }
+    CStr::from_ptr(value).to_str().map_err(|_| {
+        set_error(
+            IotStatus::InvalidArgument,
+            format!("{} is not valid UTF-8", name),
+        )
+    })
+}
+
+unsafe fn arg_client<'a>(client: *mut IotClient) -> Result<&'a mut IotClient, IotStatus> {
+    client
+        .as_mut()
+        .ok_or_else(|| set_error(IotStatus::InvalidArgument, String::from("client is null")))
+}
+
+fn to_c_string(value: String) -> Result<*mut c_char, IotStatus> {
+    CString::new(value).map(CString::into_raw).map_err(|_| {
+        set_error(
+            IotStatus::UnexpectedResponse,
+            String::from("response contains NUL character"),
+        )
+    })
+}
+
+macro_rules! try_arg {
+    ($e:expr) => {
+        match $e {
+            Ok(v) => v,
+            Err(v) => return v,
+        }
+    };
+}
+
+/// Returns message of the last error of the calling thread or null if there was no error.
+/// The string is owned by the library and valid until the next call on the thread.
+#[no_mangle]
+pub extern "C" fn iot_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |v| v.as_ptr()))
+}
+
+/// Connects to the server at `addr`, e.g. "127.0.0.1:8088", and stores handle in `client`.
+///
+/// # Safety
+/// `addr` must be NUL-terminated string, `client` must be valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn iot_client_connect(
+    addr: *const c_char,
+    client: *mut *mut IotClient,
+) -> IotStatus {
+    let addr = try_arg!(arg_str(addr, "addr"));
+    if client.is_null() {
+        return set_error(IotStatus::InvalidArgument, String::from("client is null"));
+    }
+    to_status(IotClient::connect(addr.to_string()), |c| {
+        *client = Box::into_raw(Box::new(c));
+        IotStatus::Ok
+    })
+}
+
+/// Closes connection and releases the handle, null is ignored.
+///
+/// # Safety
+/// `client` must be created by `iot_client_connect` and not released before.
+#[no_mangle]
+pub unsafe extern "C" fn iot_client_free(client: *mut IotClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Stores ids of the devices in `ids` and their number in `count`.
+/// The list is released by `iot_string_list_free`.
+///
+/// # Safety
+/// `client` must be valid handle, `ids` and `count` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn iot_client_list_devices(
+    client: *mut IotClient,
+    ids: *mut *mut *mut c_char,
+    count: *mut usize,
+) -> IotStatus {
+    let client = try_arg!(arg_client(client));
+    if ids.is_null() || count.is_null() {
+        return set_error(
+            IotStatus::InvalidArgument,
+            String::from("ids or count is null"),
+        );
+    }
+    to_status(client.list_devices(), |devices| {
+        let mut list = Vec::with_capacity(devices.len());
+        for id in devices {
+            match to_c_string(id) {
+                Ok(v) => list.push(v),
+                Err(v) => {
+                    list.into_iter().for_each(|s| drop(CString::from_raw(s)));
+                    return v;
+                }
+            }
+        }
+        *count = list.len();
+        *ids = Box::into_raw(list.into_boxed_slice()) as *mut *mut c_char;
+        IotStatus::Ok
+    })
+}
+
+/// Switches the device on.
+///
+/// # Safety
+/// `client` must be valid handle, `id` must be NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn iot_client_power_on(
+    client: *mut IotClient,
+    id: *const c_char,
+) -> IotStatus {
+    let client = try_arg!(arg_client(client));
+    let id = try_arg!(arg_str(id, "id"));
+    to_status(client.power_on(id), |_| IotStatus::Ok)
+}
+
+/// Switches the device off.
+///
+/// # Safety
+/// `client` must be valid handle, `id` must be NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn iot_client_power_off(
+    client: *mut IotClient,
+    id: *const c_char,
+) -> IotStatus {
+    let client = try_arg!(arg_client(client));
+    let id = try_arg!(arg_str(id, "id"));
+    to_status(client.power_off(id), |_| IotStatus::Ok)
+}
+
+/// Stores status of the device in `status`, the string is released by `iot_string_free`.
+///
+/// # Safety
+/// `client` must be valid handle, `id` must be NUL-terminated string,
+/// `status` must be valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn iot_client_get_status(
+    client: *mut IotClient,
+    id: *const c_char,
+    status: *mut *mut c_char,
+) -> IotStatus {
+    let client = try_arg!(arg_client(client));
+    let id = try_arg!(arg_str(id, "id"));
+    if status.is_null() {
+        return set_error(IotStatus::InvalidArgument, String::from("status is null"));
+    }
+    to_status(client.get_status(id), |v| {
+        *status = try_arg!(to_c_string(v));
+        IotStatus::Ok
+    })
+}
+
+/// Releases string returned by the library, null is ignored.
+///
+/// # Safety
+/// `value` must be returned by the library and not released before.
+#[no_mangle]
+pub unsafe extern "C" fn iot_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// Releases list of strings returned by `iot_client_list_devices`, null is ignored.
+///
+/// # Safety
+/// `values` and `count` must be returned by the library and not released before.
+#[no_mangle]
+pub unsafe extern "C" fn iot_string_list_free(values: *mut *mut c_char, count: usize) {
+    if values.is_null() {
+        return;
+    }
+    let list = Box::from_raw(ptr::slice_from_raw_parts_mut(values, count));
+    list.iter().for_each(|s| iot_string_free(*s));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use libserver::{auth::Role, iotserver::IotServerBuilder, ACSocket};
+
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        let socket = ACSocket::new();
+        let id = CString::new(socket.get_id().to_string()).unwrap();
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .set_anonymous_role(Role::Operator)
+            .add_device(socket)
+            .build()
+            .unwrap();
+        let addr = CString::new(server.local_addr().unwrap().to_string()).unwrap();
+        thread::spawn(move || server.run());
+
+        unsafe {
+            let mut client = ptr::null_mut();
+            assert_eq!(
+                iot_client_connect(addr.as_ptr(), &mut client),
+                IotStatus::Ok
+            );
+
+            let mut ids = ptr::null_mut();
+            let mut count = 0;
+            assert_eq!(
+                iot_client_list_devices(client, &mut ids, &mut count),
+                IotStatus::Ok
+            );
+            assert_eq!(count, 1);
+            assert_eq!(CStr::from_ptr(*ids), id.as_c_str());
+            iot_string_list_free(ids, count);
+
+            assert_eq!(iot_client_power_on(client, id.as_ptr()), IotStatus::Ok);
+            let mut status = ptr::null_mut();
+            assert_eq!(
+                iot_client_get_status(client, id.as_ptr(), &mut status),
+                IotStatus::Ok
+            );
+            assert!(CStr::from_ptr(status).to_str().unwrap().contains("ON"));
+            iot_string_free(status);
+
+            let unknown = CString::new("unknown").unwrap();
+            assert_eq!(
+                iot_client_power_off(client, unknown.as_ptr()),
+                IotStatus::UnknownDevice
+            );
+            assert!(!iot_last_error().is_null());
+            assert_eq!(
+                iot_client_power_off(client, ptr::null()),
+                IotStatus::InvalidArgument
+            );
+            iot_client_free(client);
+        }
+    }
+}
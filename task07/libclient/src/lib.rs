@@ -1,9 +1,251 @@
+use std::time::Duration;
+
+use error::ClientError;
+use libprotocol::{
+    catalog::{read_catalog, CommandInfo},
+    client::TcpClient,
+    commands::{Commands, DeviceInfo, PowerState, Status},
+    Packet,
+};
+
+pub mod discovery;
+pub mod error;
+pub mod ffi;
+
+pub use discovery::{broadcast_target, discover, DiscoveredServer};
+
+/// Client of the IoT server
+pub struct IotClient {
+    client: TcpClient,
+    addr: String,
+    /// Limit of connecting and of every read and write, None means infinite waiting
+    timeout: Option<Duration>,
+    /// Token of the opened session
+    session: Option<String>,
+}
+
+fn tcp_client(addr: &str, timeout: Option<Duration>) -> Result<TcpClient, ClientError> {
+    let client = match timeout {
+        Some(t) => TcpClient::connect_timeout(addr.to_string(), t)?,
+        None => TcpClient::connect(addr.to_string())?,
+    };
+    Ok(client)
+}
+
+impl IotClient {
+    pub fn connect(addr: String) -> Result<IotClient, ClientError> {
+        Self::open(addr, None)
+    }
+
+    /// Connects within the timeout, which also limits every request,
+    /// e.g. so an unresponsive server doesn't block the caller forever
+    pub fn connect_timeout(addr: String, timeout: Duration) -> Result<IotClient, ClientError> {
+        Self::open(addr, Some(timeout))
+    }
+
+    fn open(addr: String, timeout: Option<Duration>) -> Result<IotClient, ClientError> {
+        Ok(Self {
+            client: tcp_client(&addr, timeout)?,
+            addr,
+            timeout,
+            session: None,
+        })
+    }
+
+    /// Opens new session, or resumes one with the token, and returns its token
+    pub fn open_session(&mut self, token: Option<&str>) -> Result<String, ClientError> {
+        self.request(&[
+            Packet::Byte(Commands::OpenSession as u8),
+            Packet::Str(token.unwrap_or_default().to_string()),
+        ])?;
+        let token = self.recv_str()?;
+        self.session = Some(token.clone());
+        Ok(token)
+    }
+
+    /// Token of the opened session
+    pub fn session_token(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    /// Connects to the server again, e.g. after network failure,
+    /// and resumes the opened session
+    pub fn reconnect(&mut self) -> Result<(), ClientError> {
+        self.client = tcp_client(&self.addr, self.timeout)?;
+        if let Some(token) = self.session.clone() {
+            self.open_session(Some(&token))?;
+        }
+        Ok(())
+    }
+
+    /// Selects device used by device commands with empty id, None clears selection
+    pub fn select_device(&mut self, id: Option<&str>) -> Result<(), ClientError> {
+        self.device_request(Commands::SelectDevice, id.unwrap_or_default())
+    }
+
+    /// Sets session preference, e.g. `power_unit` to `kW`
+    pub fn set_preference(&mut self, key: &str, value: &str) -> Result<(), ClientError> {
+        self.request(&[
+            Packet::Byte(Commands::SetPreference as u8),
+            Packet::Str(key.to_string()),
+            Packet::Str(value.to_string()),
+        ])
+    }
+
+    /// Returns ids of the devices served by the server
+    pub fn list_devices(&mut self) -> Result<Vec<String>, ClientError> {
+        self.request(&[Packet::Byte(Commands::ListDevices as u8)])?;
+        let count = self.recv_i32()?;
+        (0..count).map(|_| self.recv_str()).collect()
+    }
+
+    pub fn power_on(&mut self, id: &str) -> Result<(), ClientError> {
+        self.device_request(Commands::PowerOn, id)
+    }
+
+    pub fn power_off(&mut self, id: &str) -> Result<(), ClientError> {
+        self.device_request(Commands::PowerOff, id)
+    }
+
+    pub fn get_status(&mut self, id: &str) -> Result<String, ClientError> {
+        self.device_request(Commands::GetStatus, id)?;
+        self.recv_str()
+    }
+
+    /// Returns current power of the device in watts
+    pub fn get_consumption(&mut self, id: &str) -> Result<f32, ClientError> {
+        self.device_request(Commands::GetConsumption, id)?;
+        self.recv_f32()
+    }
+
+    /// Returns energy in kWh consumed by the device since the last reset
+    pub fn get_energy(&mut self, id: &str) -> Result<f32, ClientError> {
+        self.device_request(Commands::GetEnergy, id)?;
+        self.recv_f32()
+    }
+
+    pub fn reset_energy(&mut self, id: &str) -> Result<(), ClientError> {
+        self.device_request(Commands::ResetEnergy, id)
+    }
+
+    /// Authenticates client, its role is defined by the token
+    pub fn authenticate(&mut self, token: &str) -> Result<(), ClientError> {
+        self.request(&[
+            Packet::Byte(Commands::Authenticate as u8),
+            Packet::Str(token.to_string()),
+        ])
+    }
+
+    /// Creates device of the given type and returns its id
+    pub fn create_device(&mut self, kind: &str, name: &str) -> Result<String, ClientError> {
+        self.request(&[
+            Packet::Byte(Commands::CreateDevice as u8),
+            Packet::Str(kind.to_string()),
+            Packet::Str(name.to_string()),
+        ])?;
+        self.recv_str()
+    }
+
+    pub fn remove_device(&mut self, id: &str) -> Result<(), ClientError> {
+        self.device_request(Commands::RemoveDevice, id)
+    }
+
+    pub fn rename_device(&mut self, id: &str, name: &str) -> Result<(), ClientError> {
+        self.request(&[
+            Packet::Byte(Commands::RenameDevice as u8),
+            Packet::Str(id.to_string()),
+            Packet::Str(name.to_string()),
+        ])
+    }
+
+    pub fn describe_device(&mut self, id: &str) -> Result<DeviceInfo, ClientError> {
+        self.device_request(Commands::DescribeDevice, id)?;
+        Ok(DeviceInfo {
+            id: self.recv_str()?,
+            name: self.recv_str()?,
+            kind: self.recv_str()?,
+            power: match self.client.recv_response()? {
+                Packet::Byte(v) => {
+                    PowerState::try_from(v).map_err(|_| ClientError::UnexpectedResponse)?
+                }
+                _ => return Err(ClientError::UnexpectedResponse),
+            },
+        })
+    }
+
+    /// Returns commands supported by the server, or by the device if id is given
+    pub fn get_catalog(&mut self, id: Option<&str>) -> Result<Vec<CommandInfo>, ClientError> {
+        self.device_request(Commands::GetCatalog, id.unwrap_or_default())?;
+        read_catalog(
+            || self.client.recv_response().map_err(ClientError::from),
+            || ClientError::UnexpectedResponse,
+        )
+    }
+
+    /// Registers emulator on the hub, returns number of its devices
+    pub fn register_emulator(&mut self, addr: &str) -> Result<usize, ClientError> {
+        self.request(&[
+            Packet::Byte(Commands::RegisterEmulator as u8),
+            Packet::Str(addr.to_string()),
+        ])?;
+        Ok(self.recv_i32()? as usize)
+    }
+
+    fn device_request(&mut self, cmd: Commands, id: &str) -> Result<(), ClientError> {
+        self.request(&[Packet::Byte(cmd as u8), Packet::Str(id.to_string())])
+    }
+
+    /// Sends request and reads status of the response
+    fn request(&mut self, request: &[Packet]) -> Result<(), ClientError> {
+        self.client.send_request(request)?;
+        let status = match self.client.recv_response()? {
+            Packet::Byte(v) => Status::try_from(v).map_err(|_| ClientError::UnexpectedResponse)?,
+            _ => return Err(ClientError::UnexpectedResponse),
+        };
+        match status {
+            Status::Ok => Ok(()),
+            s => Err(ClientError::Server(s, self.recv_str()?)),
+        }
+    }
+
+    fn recv_i32(&mut self) -> Result<i32, ClientError> {
+        match self.client.recv_response()? {
+            Packet::Int32(v) => Ok(v),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    fn recv_f32(&mut self) -> Result<f32, ClientError> {
+        match self.client.recv_response()? {
+            Packet::Float32(v) => Ok(v),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    fn recv_str(&mut self) -> Result<String, ClientError> {
+        match self.client.recv_response()? {
+            Packet::Str(v) => Ok(v),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+}
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{thread, time::Duration};
+
+    use libserver::{
+        audit::read_audit_log,
+        auth::{ClientCredential, Role},
+        energy::LoadModel,
+        iotserver::IotServerBuilder,
+        ACSocket,
+    };
+
     use super::*;
 
     #[test]
@@ -11,4 +253,257 @@ mod tests {
         let result = add(2, 2);
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn test_client() {
+        let mut socket = ACSocket::new();
+        socket.set_load_model(LoadModel::Constant { watts: 3_600_000.0 });
+        let id = socket.get_id().to_string();
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .set_anonymous_role(Role::Operator)
+            .add_device(socket)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = IotClient::connect(addr.to_string()).unwrap();
+        assert_eq!(client.list_devices().unwrap(), vec![id.clone()]);
+        assert_eq!(client.get_consumption(&id).unwrap(), 0.0);
+        client.power_on(&id).unwrap();
+        assert!(client.get_status(&id).unwrap().contains("ON"));
+        assert_eq!(client.get_consumption(&id).unwrap(), 3_600_000.0);
+        // 1 kWh per second
+        thread::sleep(Duration::from_millis(100));
+        client.power_off(&id).unwrap();
+        assert_eq!(client.get_consumption(&id).unwrap(), 0.0);
+        let energy = client.get_energy(&id).unwrap();
+        assert!(energy >= 0.1);
+        assert_eq!(client.get_energy(&id).unwrap(), energy);
+        client.reset_energy(&id).unwrap();
+        assert_eq!(client.get_energy(&id).unwrap(), 0.0);
+        assert!(matches!(
+            client.power_on("unknown").unwrap_err().status(),
+            Some(Status::UnknownDevice)
+        ));
+    }
+
+    #[test]
+    fn test_session() {
+        let mut socket = ACSocket::with_name(String::from("kettle"));
+        socket.set_load_model(LoadModel::Constant { watts: 1500.0 });
+        let id = socket.get_id().to_string();
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .add_admin_token(String::from("secret"))
+            .add_device(socket)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = IotClient::connect(addr.to_string()).unwrap();
+        assert_eq!(
+            client.power_on("").unwrap_err().status(),
+            Some(Status::UnknownDevice)
+        );
+        assert_eq!(
+            client.select_device(Some("unknown")).unwrap_err().status(),
+            Some(Status::UnknownDevice)
+        );
+        client.authenticate("secret").unwrap();
+        let token = client.open_session(None).unwrap();
+        client.select_device(Some(&id)).unwrap();
+        client.set_preference("power_unit", "kW").unwrap();
+        assert_eq!(
+            client
+                .set_preference("power_unit", "hp")
+                .unwrap_err()
+                .status(),
+            Some(Status::BadRequest)
+        );
+        client.power_on("").unwrap();
+        assert_eq!(client.get_consumption("").unwrap(), 1.5);
+
+        // context is restored after reconnection, privileges too
+        client.reconnect().unwrap();
+        assert_eq!(client.session_token(), Some(token.as_str()));
+        assert_eq!(client.get_consumption("").unwrap(), 1.5);
+        client.rename_device("", "lamp").unwrap();
+        client.set_preference("encoding", "json").unwrap();
+        let status: serde_json::Value =
+            serde_json::from_str(&client.get_status("").unwrap()).unwrap();
+        assert_eq!(status["name"], "lamp");
+        assert_eq!(status["power"], "ON");
+        assert_eq!(status["consumption"], 1.5);
+
+        // other connection resumes the session by the token
+        let mut other = IotClient::connect(addr.to_string()).unwrap();
+        assert_eq!(
+            other.open_session(Some("unknown")).unwrap_err().status(),
+            Some(Status::BadRequest)
+        );
+        other.open_session(Some(&token)).unwrap();
+        other.select_device(None).unwrap();
+        assert_eq!(
+            client.get_energy("").unwrap_err().status(),
+            Some(Status::UnknownDevice)
+        );
+        assert_eq!(client.get_consumption(&id).unwrap(), 1.5);
+    }
+
+    #[test]
+    fn test_roles() {
+        let socket = ACSocket::new();
+        let id = socket.get_id().to_string();
+        let path = std::env::temp_dir().join(format!("audit-{}.log", xid::new()));
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .add_admin_token(String::from("secret"))
+            .add_client(ClientCredential {
+                name: String::from("panel"),
+                token: String::from("switch"),
+                role: Role::Operator,
+            })
+            .set_anonymous_role(Role::Monitor)
+            .set_audit_log(path.clone())
+            .add_device(socket)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = IotClient::connect(addr.to_string()).unwrap();
+        assert!(client.get_status(&id).unwrap().contains("OFF"));
+        assert_eq!(
+            client.power_on(&id).unwrap_err().status(),
+            Some(Status::Denied)
+        );
+        client.authenticate("switch").unwrap();
+        client.power_on(&id).unwrap();
+        assert_eq!(
+            client.rename_device(&id, "lamp").unwrap_err().status(),
+            Some(Status::Denied)
+        );
+        client.authenticate("secret").unwrap();
+        client.rename_device(&id, "lamp").unwrap();
+
+        let records = read_audit_log(&path).unwrap();
+        let summary: Vec<(&str, &str, &str, &str)> = records
+            .iter()
+            .map(|r| {
+                (
+                    r.client.as_str(),
+                    r.role.as_str(),
+                    r.command.as_str(),
+                    r.result.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("anonymous", "monitor", "get_status", "Ok"),
+                ("anonymous", "monitor", "power_on", "Denied"),
+                ("panel", "operator", "authenticate", "Ok"),
+                ("panel", "operator", "power_on", "Ok"),
+                ("panel", "operator", "rename_device", "Denied"),
+                ("admin", "admin", "authenticate", "Ok"),
+                ("admin", "admin", "rename_device", "Ok"),
+            ]
+        );
+        assert_eq!(records[1].device, id);
+        assert_eq!(records[2].device, "");
+        assert!(records
+            .windows(2)
+            .all(|w| w[0].timestamp_ms <= w[1].timestamp_ms));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_catalog() {
+        let socket = ACSocket::new();
+        let id = socket.get_id().to_string();
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .add_device(socket)
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = IotClient::connect(addr.to_string()).unwrap();
+        let catalog = client.get_catalog(None).unwrap();
+        let names: Vec<&str> = catalog.iter().map(|c| c.name.as_str()).collect();
+        assert!(names.contains(&"list_devices"));
+        assert!(names.contains(&"authenticate"));
+        assert!(names.contains(&"get_catalog"));
+        assert!(!names.contains(&"register_emulator"));
+
+        let catalog = client.get_catalog(Some(&id)).unwrap();
+        let energy = catalog.iter().find(|c| c.name == "get_energy").unwrap();
+        assert_eq!(energy.id, Commands::GetEnergy as u8);
+        assert_eq!(energy.result, "f32");
+        assert!(!catalog.iter().any(|c| c.name == "list_devices"));
+        assert_eq!(
+            client.get_catalog(Some("unknown")).unwrap_err().status(),
+            Some(Status::UnknownDevice)
+        );
+        // connection is still usable after the catalog
+        assert_eq!(client.list_devices().unwrap(), vec![id]);
+    }
+
+    #[test]
+    fn test_device_management() {
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .add_admin_token(String::from("secret"))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut client = IotClient::connect(addr.to_string()).unwrap();
+        assert_eq!(
+            client
+                .create_device("socket", "kettle")
+                .unwrap_err()
+                .status(),
+            Some(Status::Denied)
+        );
+        assert_eq!(
+            client.authenticate("wrong").unwrap_err().status(),
+            Some(Status::Denied)
+        );
+        client.authenticate("secret").unwrap();
+        assert_eq!(
+            client
+                .create_device("heater", "kettle")
+                .unwrap_err()
+                .status(),
+            Some(Status::BadRequest)
+        );
+
+        let id = client.create_device("socket", "kettle").unwrap();
+        assert_eq!(client.list_devices().unwrap(), vec![id.clone()]);
+        client.power_on(&id).unwrap();
+        client.rename_device(&id, "lamp").unwrap();
+        let info = client.describe_device(&id).unwrap();
+        assert_eq!(info.id, id);
+        assert_eq!(info.name, "lamp");
+        assert_eq!(info.kind, "socket");
+        assert_eq!(info.power, PowerState::ON);
+
+        // unprivileged clients may still describe devices
+        let mut monitor = IotClient::connect(addr.to_string()).unwrap();
+        assert_eq!(monitor.describe_device(&id).unwrap().name, "lamp");
+        assert_eq!(
+            monitor.remove_device(&id).unwrap_err().status(),
+            Some(Status::Denied)
+        );
+
+        client.remove_device(&id).unwrap();
+        assert!(client.list_devices().unwrap().is_empty());
+    }
 }
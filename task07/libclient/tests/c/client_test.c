@@ -0,0 +1,53 @@
+/* Exercises the C API against the server given by address: client_test <address> */
+#include <stdio.h>
+#include <string.h>
+
+#include "iot_client.h"
+
+#define CHECK(expr, expected)                                                  \
+    do {                                                                       \
+        IotStatus status_ = (expr);                                            \
+        if (status_ != (expected)) {                                           \
+            const char *error_ = iot_last_error();                             \
+            fprintf(stderr, "%s:%d: %s returned %d: %s\n", __FILE__, __LINE__, \
+                    #expr, (int)status_, error_ ? error_ : "");                \
+            return 1;                                                          \
+        }                                                                      \
+    } while (0)
+
+int main(int argc, char **argv) {
+    IotClient *client = NULL;
+    char **ids = NULL;
+    size_t count = 0;
+    char *status = NULL;
+
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <address>\n", argv[0]);
+        return 2;
+    }
+    CHECK(iot_client_connect("127.0.0.1:1", &client), IOT_STATUS_CONNECTION_ERROR);
+    CHECK(iot_client_connect(argv[1], &client), IOT_STATUS_OK);
+
+    CHECK(iot_client_list_devices(client, &ids, &count), IOT_STATUS_OK);
+    if (count != 1) {
+        fprintf(stderr, "expected 1 device, got %zu\n", count);
+        return 1;
+    }
+
+    CHECK(iot_client_power_on(client, ids[0]), IOT_STATUS_OK);
+    CHECK(iot_client_get_status(client, ids[0], &status), IOT_STATUS_OK);
+    if (strstr(status, "ON") == NULL) {
+        fprintf(stderr, "unexpected status %s\n", status);
+        return 1;
+    }
+    printf("%s: %s\n", ids[0], status);
+    iot_string_free(status);
+
+    CHECK(iot_client_power_off(client, ids[0]), IOT_STATUS_OK);
+    CHECK(iot_client_power_off(client, "unknown"), IOT_STATUS_UNKNOWN_DEVICE);
+    CHECK(iot_client_power_off(client, NULL), IOT_STATUS_INVALID_ARGUMENT);
+
+    iot_string_list_free(ids, count);
+    iot_client_free(client);
+    return 0;
+}
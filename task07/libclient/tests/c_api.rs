@@ -0,0 +1,68 @@
+//! Checks that the header is up to date and runs the C test program against a local server.
+
+use std::{env, path::PathBuf, process::Command, thread};
+
+use libserver::{auth::Role, iotserver::IotServerBuilder, ACSocket};
+
+const HEADER: &str = "include/iot_client.h";
+
+fn crate_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn test_header() {
+    let config = cbindgen::Config::from_root_or_default(crate_dir());
+    let mut generated = vec![];
+    cbindgen::generate_with_config(crate_dir(), config)
+        .unwrap()
+        .write(&mut generated);
+    let committed = std::fs::read(crate_dir().join(HEADER)).unwrap();
+    assert!(
+        generated == committed,
+        "{} is outdated, regenerate it with `cbindgen -o {}`",
+        HEADER,
+        HEADER
+    );
+}
+
+#[test]
+fn test_c_client() {
+    // the test binary is in target/<profile>/deps, the library is next to it
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let lib_dir = deps.parent().unwrap().to_path_buf();
+    let program = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("client_test");
+    let status = Command::new(env::var("CC").unwrap_or(String::from("cc")))
+        .arg(crate_dir().join("tests/c/client_test.c"))
+        .arg("-I")
+        .arg(crate_dir().join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-llibclient")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let server = IotServerBuilder::new()
+        .set_addr(String::from("127.0.0.1:0"))
+        .set_anonymous_role(Role::Operator)
+        .add_device(ACSocket::new())
+        .build()
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+
+    let output = Command::new(&program)
+        .arg(addr.to_string())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("ON"));
+}
@@ -0,0 +1,405 @@
+//! Gateway merges devices of several IoT servers and routes commands to their owners
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+    thread,
+    time::Duration,
+};
+
+use libclient::{error::ClientError, IotClient};
+use libprotocol::{log_error, Packet};
+use libserver::{
+    backend::{arg, CommandError, CommandResult, DeviceBackend},
+    catalog::{encode_catalog, SOCKET_COMMANDS},
+    Commands, Status,
+};
+use thiserror::Error;
+
+/// Default limit of connecting to the upstream and of waiting for its response
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Gateway error
+#[derive(Debug, Error)]
+pub enum GatewayError {
+    /// Upstream server can't be reached
+    #[error("upstream {0} is unavailable: {1}")]
+    Unavailable(String, ClientError),
+    /// Upstream server executed command with error
+    #[error("upstream {0} error: {1}")]
+    Upstream(String, ClientError),
+    /// None of upstream servers owns the device
+    #[error("device {0} is not found")]
+    UnknownDevice(String),
+}
+
+impl From<GatewayError> for CommandError {
+    fn from(value: GatewayError) -> Self {
+        match value {
+            GatewayError::Unavailable(..) => {
+                CommandError::new(Status::UpstreamUnavailable, value.to_string())
+            }
+            GatewayError::Upstream(_, ClientError::Server(status, msg)) => {
+                CommandError::new(status, msg)
+            }
+            GatewayError::Upstream(..) => CommandError::new(Status::BadRequest, value.to_string()),
+            GatewayError::UnknownDevice(_) => {
+                CommandError::new(Status::UnknownDevice, value.to_string())
+            }
+        }
+    }
+}
+
+/// Connection to upstream IoT server. Connection is established lazily and
+/// re-established on next call after IO failure.
+struct Upstream {
+    addr: String,
+    token: Option<String>,
+    timeout: Duration,
+    client: Mutex<Option<IotClient>>,
+}
+
+impl Upstream {
+    fn new(addr: String, token: Option<String>) -> Self {
+        Self {
+            addr,
+            token,
+            timeout: DEFAULT_TIMEOUT,
+            client: Mutex::new(None),
+        }
+    }
+
+    fn connect(&self) -> Result<IotClient, ClientError> {
+        let mut client = IotClient::connect_timeout(self.addr.clone(), self.timeout)?;
+        if let Some(token) = &self.token {
+            client.authenticate(token)?;
+        }
+        Ok(client)
+    }
+
+    fn call<T, F>(&self, f: F) -> Result<T, GatewayError>
+    where
+        F: FnOnce(&mut IotClient) -> Result<T, ClientError>,
+    {
+        let mut guard = self.client.lock().unwrap();
+        if guard.is_none() {
+            let client = self
+                .connect()
+                .map_err(|v| GatewayError::Unavailable(self.addr.clone(), v))?;
+            *guard = Some(client);
+        }
+        match f(guard.as_mut().unwrap()) {
+            Ok(v) => Ok(v),
+            Err(v @ ClientError::Server(..)) => Err(GatewayError::Upstream(self.addr.clone(), v)),
+            Err(v) => {
+                // connection is broken, reconnect next time
+                *guard = None;
+                Err(GatewayError::Unavailable(self.addr.clone(), v))
+            }
+        }
+    }
+}
+
+/// Gateway backend: presents devices of all upstreams as its own
+pub struct Gateway {
+    upstreams: Vec<Upstream>,
+    /// Device id to the index of owning upstream
+    routes: RwLock<HashMap<String, usize>>,
+}
+
+impl Gateway {
+    pub fn new(addrs: Vec<String>) -> Self {
+        Self::with_token(addrs, None)
+    }
+
+    /// Creates gateway which authenticates on upstreams with the token,
+    /// so management commands are forwarded as privileged
+    pub fn with_token(addrs: Vec<String>, token: Option<String>) -> Self {
+        Self {
+            upstreams: addrs
+                .into_iter()
+                .map(|addr| Upstream::new(addr, token.clone()))
+                .collect(),
+            routes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Sets limit of connecting to upstreams and of waiting for their responses
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.upstreams.iter_mut().for_each(|u| u.timeout = timeout);
+        self
+    }
+
+    /// Collects devices of all available upstreams and refreshes routing table.
+    /// Routes to devices of unavailable upstreams are kept, so commands to them
+    /// fail as unavailable. Fails only if none of the upstreams is available.
+    pub fn list_devices(&self) -> Result<Vec<String>, GatewayError> {
+        // upstreams are listed in parallel, so the slow one delays listing by its timeout only
+        let listings: Vec<Result<Vec<String>, GatewayError>> = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .upstreams
+                .iter()
+                .map(|u| s.spawn(move || u.call(|c| c.list_devices())))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let mut devices = vec![];
+        let mut listed = HashMap::new();
+        let mut last_error = None;
+        let mut available = vec![false; self.upstreams.len()];
+        for (i, listing) in listings.into_iter().enumerate() {
+            match listing {
+                Ok(ids) => {
+                    available[i] = true;
+                    ids.into_iter().for_each(|id| {
+                        if !listed.contains_key(&id) {
+                            listed.insert(id.clone(), i);
+                            devices.push(id);
+                        }
+                    })
+                }
+                Err(v) => {
+                    log_error!("cannot list devices {}", v);
+                    last_error = Some(v);
+                }
+            }
+        }
+        let mut routes = self.routes.write().unwrap();
+        routes.retain(|id, i| !available[*i] && !listed.contains_key(id));
+        routes.extend(listed);
+        match last_error {
+            Some(v) if devices.is_empty() => Err(v),
+            _ => Ok(devices),
+        }
+    }
+
+    /// Finds upstream which owns the device
+    fn route(&self, id: &str) -> Result<&Upstream, GatewayError> {
+        if let Some(i) = self.routes.read().unwrap().get(id) {
+            return Ok(&self.upstreams[*i]);
+        }
+        // device may be added after the last listing
+        let _ = self.list_devices();
+        match self.routes.read().unwrap().get(id) {
+            Some(i) => Ok(&self.upstreams[*i]),
+            None => Err(GatewayError::UnknownDevice(id.to_string())),
+        }
+    }
+}
+
+impl DeviceBackend for Gateway {
+    fn execute_cmd(&self, cmd: Commands, args: &[String]) -> CommandResult {
+        let id = arg(args, 0);
+        let response = match cmd {
+            Commands::ListDevices => {
+                let devices = self.list_devices()?;
+                let mut response = vec![Packet::Int32(devices.len() as i32)];
+                response.extend(devices.into_iter().map(Packet::Str));
+                return Ok(response);
+            }
+            Commands::CreateDevice
+            | Commands::Authenticate
+            | Commands::RegisterEmulator
+            | Commands::OpenSession
+            | Commands::SelectDevice
+            | Commands::SetPreference => {
+                return Err(CommandError::new(
+                    Status::UnknownCommand,
+                    format!("{} is not supported by gateway", cmd.name()),
+                ))
+            }
+            Commands::PowerOn => self.route(id)?.call(|c| c.power_on(id)).map(|_| vec![]),
+            Commands::PowerOff => self.route(id)?.call(|c| c.power_off(id)).map(|_| vec![]),
+            Commands::GetStatus => self
+                .route(id)?
+                .call(|c| c.get_status(id))
+                .map(|v| vec![Packet::Str(v)]),
+            Commands::GetConsumption => self
+                .route(id)?
+                .call(|c| c.get_consumption(id))
+                .map(|v| vec![Packet::Float32(v)]),
+            Commands::GetEnergy => self
+                .route(id)?
+                .call(|c| c.get_energy(id))
+                .map(|v| vec![Packet::Float32(v)]),
+            Commands::ResetEnergy => self.route(id)?.call(|c| c.reset_energy(id)).map(|_| vec![]),
+            Commands::RemoveDevice => self
+                .route(id)?
+                .call(|c| c.remove_device(id))
+                .map(|_| vec![]),
+            Commands::RenameDevice => self
+                .route(id)?
+                .call(|c| c.rename_device(id, arg(args, 1)))
+                .map(|_| vec![]),
+            Commands::DescribeDevice => self
+                .route(id)?
+                .call(|c| c.describe_device(id))
+                .map(|v| v.to_packets()),
+            Commands::GetCatalog => self
+                .route(id)?
+                .call(|c| c.get_catalog(Some(id)))
+                .map(|v| encode_catalog(&v)),
+        };
+        Ok(response?)
+    }
+
+    fn commands(&self) -> Vec<Commands> {
+        let mut commands = vec![Commands::ListDevices];
+        commands.extend(SOCKET_COMMANDS);
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc, thread, time::Duration};
+
+    use libprotocol::server::TcpServer;
+    use libserver::{auth::Role, iotserver::IotServerBuilder, ACSocket};
+
+    use super::*;
+
+    fn start_server(builder: &mut IotServerBuilder) -> SocketAddr {
+        let server = builder
+            .set_addr(String::from("127.0.0.1:0"))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    /// Upstream which serves the only list request and goes offline
+    fn start_flaky_server(id: String) -> SocketAddr {
+        let server = TcpServer::bind(String::from("127.0.0.1:0")).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut conn = server.incoming().next().unwrap().unwrap();
+            assert_eq!(
+                conn.recv_request().unwrap(),
+                Packet::Byte(Commands::ListDevices as u8)
+            );
+            conn.send_response_vec(&[
+                Packet::Byte(Status::Ok as u8),
+                Packet::Int32(1),
+                Packet::Str(id),
+            ])
+            .unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn test_gateway() {
+        let s1 = ACSocket::new();
+        let s2 = ACSocket::new();
+        let (id1, id2) = (s1.get_id().to_string(), s2.get_id().to_string());
+        let token = String::from("secret");
+        let up1 = start_server(
+            IotServerBuilder::new()
+                .add_admin_token(token.clone())
+                .add_device(s1),
+        );
+        let up2 = start_server(
+            IotServerBuilder::new()
+                .add_admin_token(token.clone())
+                .add_device(s2),
+        );
+        let gateway = Arc::new(Gateway::with_token(
+            vec![up1.to_string(), up2.to_string()],
+            Some(token),
+        ));
+        let addr = start_server(
+            IotServerBuilder::new()
+                .set_anonymous_role(Role::Operator)
+                .set_backend(gateway),
+        );
+
+        let mut client = IotClient::connect(addr.to_string()).unwrap();
+        let devices = client.list_devices().unwrap();
+        assert_eq!(devices, vec![id1.clone(), id2.clone()]);
+        client.power_on(&id2).unwrap();
+        assert!(client.get_status(&id2).unwrap().contains("ON"));
+        assert!(client.get_status(&id1).unwrap().contains("OFF"));
+        assert_eq!(client.get_consumption(&id2).unwrap(), 60.0);
+        client.reset_energy(&id1).unwrap();
+        assert_eq!(client.get_energy(&id1).unwrap(), 0.0);
+        assert_eq!(
+            client.power_on("missing").unwrap_err().status(),
+            Some(Status::UnknownDevice)
+        );
+    }
+
+    #[test]
+    fn test_unavailable_upstream() {
+        let s1 = ACSocket::new();
+        let id1 = s1.get_id().to_string();
+        let up1 = start_server(IotServerBuilder::new().add_device(s1));
+        let flaky = start_flaky_server(String::from("flaky"));
+        let gateway = Gateway::new(vec![up1.to_string(), flaky.to_string()]);
+
+        assert_eq!(
+            gateway.list_devices().unwrap(),
+            vec![id1, String::from("flaky")]
+        );
+        let err = gateway
+            .execute_cmd(Commands::PowerOn, &[String::from("flaky")])
+            .unwrap_err();
+        assert_eq!(err.status, Status::UpstreamUnavailable);
+        // listing still returns devices of available upstreams
+        assert_eq!(gateway.list_devices().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_upstream_restart() {
+        let s1 = ACSocket::new();
+        let s2 = ACSocket::new();
+        let (id1, id2) = (s1.get_id().to_string(), s2.get_id().to_string());
+        let up1 = start_server(IotServerBuilder::new().add_device(s1));
+        let start = |addr: String| {
+            let server = Arc::new(
+                IotServerBuilder::new()
+                    .set_addr(addr)
+                    .set_anonymous_role(Role::Operator)
+                    .add_device(s2.clone())
+                    .build()
+                    .unwrap(),
+            );
+            let handle = server.clone();
+            (server, thread::spawn(move || handle.run()))
+        };
+        let (up2, running) = start(String::from("127.0.0.1:0"));
+        let addr = up2.local_addr().unwrap().to_string();
+        // nothing answers on the hung upstream, it accepts connections only
+        let hung = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut gateway = Gateway::new(vec![
+            up1.to_string(),
+            addr.clone(),
+            hung.local_addr().unwrap().to_string(),
+        ]);
+        gateway.set_timeout(Duration::from_millis(200));
+
+        let started = std::time::Instant::now();
+        assert_eq!(
+            gateway.list_devices().unwrap(),
+            vec![id1.clone(), id2.clone()]
+        );
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let args = [id2.clone()];
+        up2.shutdown();
+        running.join().unwrap();
+        drop(up2);
+        for _ in 0..3 {
+            let err = gateway.execute_cmd(Commands::PowerOn, &args).unwrap_err();
+            assert_eq!(err.status, Status::UpstreamUnavailable);
+            assert_eq!(gateway.list_devices().unwrap(), vec![id1.clone()]);
+        }
+
+        let (up2, running) = start(addr);
+        gateway.execute_cmd(Commands::PowerOn, &args).unwrap();
+        assert_eq!(gateway.list_devices().unwrap(), vec![id1, id2]);
+        up2.shutdown();
+        running.join().unwrap();
+    }
+}
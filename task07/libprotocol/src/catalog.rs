@@ -0,0 +1,153 @@
+//! Catalog of commands supported by the server or by the device.
+//!
+//! Catalog is sent as the number of commands followed by the commands:
+//! `Byte id, Str name, Str description, Int32 args count, (Str name, Str type)..., Str result`
+
+use crate::{commands::Commands, Packet};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgInfo {
+    pub name: String,
+    pub kind: String,
+}
+
+/// Description of the command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub id: u8,
+    pub name: String,
+    pub description: String,
+    pub args: Vec<ArgInfo>,
+    pub result: String,
+}
+
+impl From<Commands> for CommandInfo {
+    fn from(value: Commands) -> Self {
+        Self {
+            id: value as u8,
+            name: value.name().to_string(),
+            description: value.description().to_string(),
+            args: value
+                .args()
+                .iter()
+                .map(|(name, kind)| ArgInfo {
+                    name: name.to_string(),
+                    kind: kind.to_string(),
+                })
+                .collect(),
+            result: value.result().to_string(),
+        }
+    }
+}
+
+impl CommandInfo {
+    pub fn to_packets(&self) -> Vec<Packet> {
+        let mut packets = vec![
+            Packet::Byte(self.id),
+            Packet::Str(self.name.clone()),
+            Packet::Str(self.description.clone()),
+            Packet::Int32(self.args.len() as i32),
+        ];
+        self.args.iter().for_each(|a| {
+            packets.push(Packet::Str(a.name.clone()));
+            packets.push(Packet::Str(a.kind.clone()));
+        });
+        packets.push(Packet::Str(self.result.clone()));
+        packets
+    }
+}
+
+/// Encodes catalog of the commands ordered by id
+pub fn catalog_packets(commands: &[Commands]) -> Vec<Packet> {
+    let mut commands = commands.to_vec();
+    commands.sort_by_key(|c| *c as u8);
+    commands.dedup();
+    encode_catalog(
+        &commands
+            .into_iter()
+            .map(CommandInfo::from)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Encodes catalog as is, e.g. one received from other server
+pub fn encode_catalog(catalog: &[CommandInfo]) -> Vec<Packet> {
+    let mut packets = vec![Packet::Int32(catalog.len() as i32)];
+    catalog.iter().for_each(|c| packets.extend(c.to_packets()));
+    packets
+}
+
+fn read_str<E>(
+    next: &mut impl FnMut() -> Result<Packet, E>,
+    invalid: &impl Fn() -> E,
+) -> Result<String, E> {
+    match next()? {
+        Packet::Str(v) => Ok(v),
+        _ => Err(invalid()),
+    }
+}
+
+fn read_count<E>(
+    next: &mut impl FnMut() -> Result<Packet, E>,
+    invalid: &impl Fn() -> E,
+) -> Result<i32, E> {
+    match next()? {
+        Packet::Int32(v) if v >= 0 => Ok(v),
+        _ => Err(invalid()),
+    }
+}
+
+/// Decodes catalog from the packets returned by `next`, `invalid` creates error
+/// of the unexpected packet
+pub fn read_catalog<E>(
+    mut next: impl FnMut() -> Result<Packet, E>,
+    invalid: impl Fn() -> E,
+) -> Result<Vec<CommandInfo>, E> {
+    let count = read_count(&mut next, &invalid)?;
+    let mut catalog = vec![];
+    for _ in 0..count {
+        let id = match next()? {
+            Packet::Byte(v) => v,
+            _ => return Err(invalid()),
+        };
+        let name = read_str(&mut next, &invalid)?;
+        let description = read_str(&mut next, &invalid)?;
+        let mut args = vec![];
+        for _ in 0..read_count(&mut next, &invalid)? {
+            args.push(ArgInfo {
+                name: read_str(&mut next, &invalid)?,
+                kind: read_str(&mut next, &invalid)?,
+            });
+        }
+        catalog.push(CommandInfo {
+            id,
+            name,
+            description,
+            args,
+            result: read_str(&mut next, &invalid)?,
+        });
+    }
+    Ok(catalog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog() {
+        let packets = catalog_packets(&[Commands::RenameDevice, Commands::PowerOn]);
+        let mut iter = packets.into_iter();
+        let catalog = read_catalog(|| iter.next().ok_or(()), || ()).unwrap();
+        assert_eq!(iter.next(), None);
+        assert_eq!(
+            catalog.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(),
+            vec!["power_on", "rename_device"]
+        );
+        assert_eq!(catalog[1], CommandInfo::from(Commands::RenameDevice));
+        assert_eq!(catalog[1].args[1].name, "name");
+
+        let mut truncated = catalog_packets(&[Commands::PowerOn]).into_iter().take(3);
+        assert!(read_catalog(|| truncated.next().ok_or(()), || ()).is_err());
+    }
+}
@@ -1,7 +1,9 @@
-use std::net::TcpStream;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
 
 use crate::{
-    error::{self, CmdError, ConnectError},
+    error::{self, CmdError, ConnectError, RecvError, SendError},
     Packet,
 };
 
@@ -11,7 +13,31 @@ pub struct TcpClient {
 
 impl TcpClient {
     pub fn connect(addr: String) -> Result<TcpClient, error::ConnectError> {
-        let conn = TcpStream::connect(addr)?;
+        Self::open(TcpStream::connect(addr)?)
+    }
+
+    /// Connects within the timeout, which also limits the handshake and every
+    /// read and write of the client, so an unresponsive server fails the call
+    pub fn connect_timeout(addr: String, timeout: Duration) -> Result<TcpClient, ConnectError> {
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(conn) => {
+                    conn.set_read_timeout(Some(timeout))?;
+                    conn.set_write_timeout(Some(timeout))?;
+                    return Self::open(conn);
+                }
+                Err(v) => last_error = Some(v),
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address resolved"))
+            .into())
+    }
+
+    fn open(conn: TcpStream) -> Result<TcpClient, ConnectError> {
+        // packets are written separately, don't wait to coalesce them
+        conn.set_nodelay(true)?;
         Self::try_handshake(conn)
     }
 
@@ -48,6 +74,16 @@ impl TcpClient {
 
     pub fn send_cmd(&mut self, cmd: u8) -> Result<Packet, CmdError> {
         crate::write_packet(&mut self.stream, cmd.into())?;
-        crate::read_packet(&mut self.stream).map_err(|v| CmdError::Recv(v))
+        crate::read_packet(&mut self.stream).map_err(CmdError::Recv)
+    }
+
+    pub fn send_request(&mut self, request: &[Packet]) -> Result<(), SendError> {
+        request
+            .iter()
+            .try_for_each(|packet| crate::write_packet(&mut self.stream, packet.clone()))
+    }
+
+    pub fn recv_response(&mut self) -> Result<Packet, RecvError> {
+        crate::read_packet(&mut self.stream)
     }
 }
@@ -0,0 +1,263 @@
+//! Commands, statuses and payloads of the IoT server protocol
+
+use crate::Packet;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    OFF,
+    ON,
+}
+
+impl TryFrom<u8> for PowerState {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            v if v == PowerState::OFF as u8 => Ok(PowerState::OFF),
+            v if v == PowerState::ON as u8 => Ok(PowerState::ON),
+            v => Err(v),
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Commands {
+    PowerOn = 1,
+    PowerOff = 2,
+    GetStatus = 3,
+    GetConsumption = 4,
+    ListDevices = 5,
+    CreateDevice = 6,
+    RemoveDevice = 7,
+    RenameDevice = 8,
+    DescribeDevice = 9,
+    Authenticate = 10,
+    GetEnergy = 11,
+    ResetEnergy = 12,
+    RegisterEmulator = 13,
+    GetCatalog = 14,
+    OpenSession = 15,
+    SelectDevice = 16,
+    SetPreference = 17,
+}
+
+impl Commands {
+    pub const ALL: [Commands; 17] = [
+        Commands::PowerOn,
+        Commands::PowerOff,
+        Commands::GetStatus,
+        Commands::GetConsumption,
+        Commands::ListDevices,
+        Commands::CreateDevice,
+        Commands::RemoveDevice,
+        Commands::RenameDevice,
+        Commands::DescribeDevice,
+        Commands::Authenticate,
+        Commands::GetEnergy,
+        Commands::ResetEnergy,
+        Commands::RegisterEmulator,
+        Commands::GetCatalog,
+        Commands::OpenSession,
+        Commands::SelectDevice,
+        Commands::SetPreference,
+    ];
+
+    /// Name of the command, used in logs and metric labels
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::PowerOn => "power_on",
+            Commands::PowerOff => "power_off",
+            Commands::GetStatus => "get_status",
+            Commands::GetConsumption => "get_consumption",
+            Commands::ListDevices => "list_devices",
+            Commands::CreateDevice => "create_device",
+            Commands::RemoveDevice => "remove_device",
+            Commands::RenameDevice => "rename_device",
+            Commands::DescribeDevice => "describe_device",
+            Commands::Authenticate => "authenticate",
+            Commands::GetEnergy => "get_energy",
+            Commands::ResetEnergy => "reset_energy",
+            Commands::RegisterEmulator => "register_emulator",
+            Commands::GetCatalog => "get_catalog",
+            Commands::OpenSession => "open_session",
+            Commands::SelectDevice => "select_device",
+            Commands::SetPreference => "set_preference",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Commands::PowerOn => "switch device on",
+            Commands::PowerOff => "switch device off",
+            Commands::GetStatus => "get device status",
+            Commands::GetConsumption => "get current power of the device, W",
+            Commands::ListDevices => "list ids of the devices",
+            Commands::CreateDevice => "create device of the type",
+            Commands::RemoveDevice => "remove device",
+            Commands::RenameDevice => "rename device",
+            Commands::DescribeDevice => "get device id, name, type and power state",
+            Commands::Authenticate => "authenticate as privileged client",
+            Commands::GetEnergy => "get energy consumed since the last reset, kWh",
+            Commands::ResetEnergy => "reset energy meter of the device",
+            Commands::RegisterEmulator => "register emulator on the hub",
+            Commands::GetCatalog => "get commands of the server, or of the device if id is given",
+            Commands::OpenSession => "open new session or resume the one with the token",
+            Commands::SelectDevice => {
+                "select device used by device commands with empty id, empty id clears selection"
+            }
+            Commands::SetPreference => {
+                "set session preference: power_unit, energy_unit or encoding"
+            }
+        }
+    }
+
+    /// Names and types of string arguments which follow the command in the request.
+    /// Device commands take device id as the first argument, empty id refers
+    /// to the device selected in the session.
+    pub fn args(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Commands::ListDevices => &[],
+            Commands::CreateDevice => &[("type", "str"), ("name", "str")],
+            Commands::RenameDevice => &[("id", "device_id"), ("name", "str")],
+            Commands::Authenticate => &[("token", "str")],
+            Commands::RegisterEmulator => &[("address", "str")],
+            Commands::GetCatalog => &[("id", "device_id or empty")],
+            Commands::OpenSession => &[("token", "str or empty")],
+            Commands::SelectDevice => &[("id", "device_id or empty")],
+            Commands::SetPreference => &[("key", "str"), ("value", "str")],
+            _ => &[("id", "device_id")],
+        }
+    }
+
+    /// Type of the response payload
+    pub fn result(&self) -> &'static str {
+        match self {
+            Commands::GetStatus => "str",
+            Commands::GetConsumption | Commands::GetEnergy => "f32",
+            Commands::ListDevices => "device_list",
+            Commands::CreateDevice => "device_id",
+            Commands::DescribeDevice => "device_info",
+            Commands::RegisterEmulator => "i32",
+            Commands::GetCatalog => "catalog",
+            Commands::OpenSession => "str",
+            _ => "none",
+        }
+    }
+
+    /// Number of string arguments which follow the command in the request
+    pub fn args_count(&self) -> usize {
+        self.args().len()
+    }
+
+    /// Checks if command takes device id as the first argument
+    pub fn is_device_command(&self) -> bool {
+        self.args().first().is_some_and(|(name, _)| *name == "id")
+            && !matches!(self, Commands::SelectDevice)
+    }
+
+    /// Checks if command changes state of the connection, not of the devices.
+    /// Such commands are handled by the server and never reach the backend.
+    pub fn is_connection_command(&self) -> bool {
+        matches!(
+            self,
+            Commands::Authenticate
+                | Commands::OpenSession
+                | Commands::SelectDevice
+                | Commands::SetPreference
+        )
+    }
+
+    /// Checks if command changes device inventory or state
+    pub fn is_modifying(&self) -> bool {
+        matches!(
+            self,
+            Commands::PowerOn
+                | Commands::PowerOff
+                | Commands::CreateDevice
+                | Commands::RemoveDevice
+                | Commands::RenameDevice
+                | Commands::ResetEnergy
+        )
+    }
+}
+
+impl TryFrom<u8> for Commands {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            v if v == Commands::PowerOn as u8 => Ok(Commands::PowerOn),
+            v if v == Commands::PowerOff as u8 => Ok(Commands::PowerOff),
+            v if v == Commands::GetStatus as u8 => Ok(Commands::GetStatus),
+            v if v == Commands::GetConsumption as u8 => Ok(Commands::GetConsumption),
+            v if v == Commands::ListDevices as u8 => Ok(Commands::ListDevices),
+            v if v == Commands::CreateDevice as u8 => Ok(Commands::CreateDevice),
+            v if v == Commands::RemoveDevice as u8 => Ok(Commands::RemoveDevice),
+            v if v == Commands::RenameDevice as u8 => Ok(Commands::RenameDevice),
+            v if v == Commands::DescribeDevice as u8 => Ok(Commands::DescribeDevice),
+            v if v == Commands::Authenticate as u8 => Ok(Commands::Authenticate),
+            v if v == Commands::GetEnergy as u8 => Ok(Commands::GetEnergy),
+            v if v == Commands::ResetEnergy as u8 => Ok(Commands::ResetEnergy),
+            v if v == Commands::RegisterEmulator as u8 => Ok(Commands::RegisterEmulator),
+            v if v == Commands::GetCatalog as u8 => Ok(Commands::GetCatalog),
+            v if v == Commands::OpenSession as u8 => Ok(Commands::OpenSession),
+            v if v == Commands::SelectDevice as u8 => Ok(Commands::SelectDevice),
+            v if v == Commands::SetPreference as u8 => Ok(Commands::SetPreference),
+            v => Err(v),
+        }
+    }
+}
+
+/// Status of the command. It is sent as the first packet of every response,
+/// error statuses are followed by the message string.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    UnknownCommand = 1,
+    BadRequest = 2,
+    UnknownDevice = 3,
+    UpstreamUnavailable = 4,
+    Denied = 5,
+    /// Room of the home server is not found
+    UnknownRoom = 6,
+}
+
+impl TryFrom<u8> for Status {
+    type Error = u8;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            v if v == Status::Ok as u8 => Ok(Status::Ok),
+            v if v == Status::UnknownCommand as u8 => Ok(Status::UnknownCommand),
+            v if v == Status::BadRequest as u8 => Ok(Status::BadRequest),
+            v if v == Status::UnknownDevice as u8 => Ok(Status::UnknownDevice),
+            v if v == Status::UpstreamUnavailable as u8 => Ok(Status::UpstreamUnavailable),
+            v if v == Status::Denied as u8 => Ok(Status::Denied),
+            v if v == Status::UnknownRoom as u8 => Ok(Status::UnknownRoom),
+            v => Err(v),
+        }
+    }
+}
+
+/// Type of the AC socket device
+pub const SOCKET_KIND: &str = "socket";
+
+/// Device description, returned by `Commands::DescribeDevice`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub kind: String,
+    pub power: PowerState,
+}
+
+impl DeviceInfo {
+    pub fn to_packets(&self) -> Vec<Packet> {
+        vec![
+            Packet::Str(self.id.clone()),
+            Packet::Str(self.name.clone()),
+            Packet::Str(self.kind.clone()),
+            Packet::Byte(self.power as u8),
+        ]
+    }
+}
@@ -0,0 +1,66 @@
+//! Datagrams of the discovery of IoT servers on the LAN.
+//!
+//! Client broadcasts probe datagram to the discovery port, every responder answers
+//! to the sender with the announcement: name of the server, protocol version
+//! and TCP port of the protocol listener.
+
+use serde::{Deserialize, Serialize};
+
+use crate::PROTOCOL_VERSION;
+
+/// UDP port of the responder
+pub const DISCOVERY_PORT: u16 = 8089;
+
+/// Content of the probe datagram
+pub const PROBE: &[u8] = b"IOT-DISCOVER";
+
+/// Prefix of the announcement datagram, followed by announcement in JSON
+const ANNOUNCE_PREFIX: &[u8] = b"IOT-SERVER ";
+
+/// Server description sent in reply to the probe
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Announcement {
+    pub name: String,
+    pub version: u32,
+    /// TCP port of the protocol listener
+    pub port: u16,
+}
+
+impl Announcement {
+    /// Announcement of the server with the current protocol version
+    pub fn new(name: String, port: u16) -> Self {
+        Self {
+            name,
+            version: PROTOCOL_VERSION,
+            port,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = ANNOUNCE_PREFIX.to_vec();
+        // serialization of the plain struct doesn't fail
+        data.extend(serde_json::to_vec(self).unwrap());
+        data
+    }
+
+    /// Returns None if datagram is not an announcement
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        serde_json::from_slice(data.strip_prefix(ANNOUNCE_PREFIX)?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement() {
+        let announcement = Announcement::new(String::from("kitchen"), 8088);
+        assert_eq!(
+            Announcement::decode(&announcement.encode()),
+            Some(announcement.clone())
+        );
+        assert_eq!(Announcement::decode(b"IOT-SERVER {}"), None);
+        assert_eq!(Announcement::decode(PROBE), None);
+    }
+}
@@ -1,9 +1,16 @@
 use std::io::{Read, Write};
 
+pub mod catalog;
 pub mod client;
+pub mod commands;
+pub mod discovery;
 pub mod error;
+pub mod log;
 pub mod server;
 
+/// Version of the protocol, announced by the discovery
+pub const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Packet {
     Byte(u8),
@@ -130,7 +137,7 @@ pub fn read_packet<Reader: Read>(mut reader: Reader) -> Result<Packet, error::Re
                         state = State::ReadStr;
                     }
                     _ => {
-                        println!("Unexpected format {}", prefix[0]);
+                        log_error!("Unexpected format {}", prefix[0]);
                         state = State::Fail;
                     }
                 };
@@ -1,6 +1,7 @@
 use std::{
     io,
-    net::{SocketAddr, TcpListener, TcpStream},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
 };
 
 use crate::{
@@ -8,6 +9,9 @@ use crate::{
     Packet,
 };
 
+/// Time to wait for the handshake of the accepted client
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub struct TcpServer {
     tcp: TcpListener,
@@ -24,13 +28,19 @@ impl TcpServer {
         Ok(Self { tcp })
     }
 
+    /// Serves already bound listener, e.g. one passed by the service manager
+    pub fn from_listener(tcp: TcpListener) -> TcpServer {
+        Self { tcp }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
     pub fn incoming(
         &self,
     ) -> impl Iterator<Item = Result<TcpConnection, error::ConnectError>> + '_ {
-        println!(
-            "INFO: Starting server on {:?}",
-            self.tcp.local_addr().unwrap()
-        );
+        crate::log_info!("Starting server on {:?}", self.tcp.local_addr().unwrap());
         self.tcp.incoming().map(|s| match s {
             Ok(s) => Self::try_handshake(s),
             Err(e) => Err(error::ConnectError::Io(e)),
@@ -38,10 +48,14 @@ impl TcpServer {
     }
 
     fn try_handshake(mut stream: TcpStream) -> Result<TcpConnection, error::ConnectError> {
-        println!(
-            "INFO: server is trying handshake with {:?}",
+        crate::log_debug!(
+            "server is trying handshake with {:?}",
             stream.peer_addr().unwrap()
         );
+        // packets are written separately, don't wait to coalesce them
+        stream.set_nodelay(true)?;
+        // handshake blocks accepting of other clients, silent client must not hold it
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
         let mut state = 0;
         loop {
             match state {
@@ -63,7 +77,10 @@ impl TcpServer {
                         )))
                     }
                 },
-                3 => return Ok(TcpConnection { stream }),
+                3 => {
+                    stream.set_read_timeout(None)?;
+                    return Ok(TcpConnection { stream });
+                }
                 _ => return Err(ConnectError::BadHandshake(String::from("invalid state"))),
             }
         }
@@ -85,7 +102,25 @@ impl TcpConnection {
         crate::read_packet(&mut self.stream)
     }
 
+    /// Sets timeout of waiting for the request, None means infinite waiting
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
         self.stream.peer_addr()
     }
+
+    /// Shuts down reading of the connection, so the blocked `recv_request` returns.
+    /// Responses still can be sent.
+    pub fn shutdown_read(&self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Read)
+    }
+
+    /// Creates independent handle of the connection
+    pub fn try_clone(&self) -> io::Result<TcpConnection> {
+        Ok(Self {
+            stream: self.stream.try_clone()?,
+        })
+    }
 }
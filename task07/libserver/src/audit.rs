@@ -0,0 +1,114 @@
+//! Append-only audit log of the commands.
+//!
+//! Every accepted or denied command is appended to the file as JSON line:
+//!
+//! ```json
+//! {"timestamp_ms":1718000000000,"client":"dashboard","peer":"127.0.0.1:53012","role":"monitor","device":"cpbfcv4q6r8t2e9p6rlg","command":"power_on","result":"Denied"}
+//! ```
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use libprotocol::log_error;
+use serde::{Deserialize, Serialize};
+
+/// Record of the executed or denied command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Milliseconds since Unix epoch
+    pub timestamp_ms: u64,
+    /// Name of the authenticated client, `anonymous` otherwise
+    pub client: String,
+    /// Address of the connection
+    pub peer: String,
+    pub role: String,
+    /// Device id, empty for commands not related to a device
+    pub device: String,
+    pub command: String,
+    /// Status of the response
+    pub result: String,
+}
+
+impl AuditRecord {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+}
+
+/// Audit log file, records are only appended to it
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Opens file for appending, creates it if it doesn't exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the record. Failure is logged, it doesn't fail the command.
+    pub fn append(&self, record: &AuditRecord) {
+        // serialization of the plain struct doesn't fail
+        let mut line = serde_json::to_vec(record).unwrap();
+        line.push(b'\n');
+        // the whole line is written at once, so concurrent records don't interleave
+        if let Err(v) = self.file.lock().unwrap().write_all(&line) {
+            log_error!("cannot write audit log {:?}: {:?}", self.path, v);
+        }
+    }
+}
+
+/// Reads records of the audit log
+pub fn read_audit_log(path: &Path) -> io::Result<Vec<AuditRecord>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(|l| serde_json::from_str(l).map_err(io::Error::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", xid::new()));
+        let record = AuditRecord {
+            timestamp_ms: AuditRecord::now(),
+            client: String::from("dashboard"),
+            peer: String::from("127.0.0.1:5000"),
+            role: String::from("monitor"),
+            device: String::new(),
+            command: String::from("list_devices"),
+            result: String::from("Ok"),
+        };
+        AuditLog::open(&path).unwrap().append(&record);
+        // records are appended to the existing file
+        let log = AuditLog::open(&path).unwrap();
+        log.append(&AuditRecord {
+            result: String::from("Denied"),
+            ..record.clone()
+        });
+        let records = read_audit_log(log.path()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], record);
+        assert_eq!(records[1].result, "Denied");
+        std::fs::remove_file(path).unwrap();
+    }
+}
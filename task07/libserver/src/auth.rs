@@ -0,0 +1,86 @@
+//! Roles of the clients and permissions of the commands
+
+use libprotocol::commands::Commands;
+use serde::Deserialize;
+
+/// Role of the client, every role has permissions of the lower ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Reads state of the devices
+    #[default]
+    Monitor,
+    /// Switches power and resets energy meters
+    Operator,
+    /// Manages devices
+    Admin,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Monitor => "monitor",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Permissions of the protocol commands
+pub trait Permissions {
+    /// Minimal role of the client allowed to execute the command
+    fn required_role(&self) -> Role;
+
+    /// Checks if command is allowed only for authenticated admins
+    fn is_privileged(&self) -> bool {
+        self.required_role() == Role::Admin
+    }
+}
+
+impl Permissions for Commands {
+    fn required_role(&self) -> Role {
+        match self {
+            Commands::PowerOn | Commands::PowerOff | Commands::ResetEnergy => Role::Operator,
+            Commands::CreateDevice
+            | Commands::RemoveDevice
+            | Commands::RenameDevice
+            | Commands::RegisterEmulator => Role::Admin,
+            _ => Role::Monitor,
+        }
+    }
+}
+
+/// Client which authenticates with the token
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientCredential {
+    pub name: String,
+    pub token: String,
+    pub role: Role,
+}
+
+/// Authenticated client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Name of the clients authenticated by admin tokens
+pub const ADMIN_NAME: &str = "admin";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles() {
+        assert!(Role::Admin > Role::Operator && Role::Operator > Role::Monitor);
+        assert_eq!(Commands::GetStatus.required_role(), Role::Monitor);
+        assert_eq!(Commands::Authenticate.required_role(), Role::Monitor);
+        assert_eq!(Commands::PowerOff.required_role(), Role::Operator);
+        for c in Commands::ALL {
+            assert_eq!(c.is_privileged(), c.required_role() == Role::Admin);
+        }
+    }
+}
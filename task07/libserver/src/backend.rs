@@ -0,0 +1,169 @@
+//! Backends execute protocol commands on the devices served by IoT server
+
+use std::sync::Mutex;
+
+use libprotocol::{log_error, Packet};
+use thiserror::Error;
+
+use crate::{
+    catalog::{catalog_packets, SOCKET_COMMANDS},
+    storage::DeviceStore,
+    ACSocket, Commands, PowerState, Status, SOCKET_KIND,
+};
+
+/// Error of the command execution. It is sent to the client as status and message.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{status:?}: {msg}")]
+pub struct CommandError {
+    pub status: Status,
+    pub msg: String,
+}
+
+impl CommandError {
+    pub fn new(status: Status, msg: String) -> Self {
+        Self { status, msg }
+    }
+}
+
+/// Result of the command: response payload which follows `Status::Ok`
+pub type CommandResult = Result<Vec<Packet>, CommandError>;
+
+/// DeviceBackend defines common interface for sources of devices of the server
+pub trait DeviceBackend: Send + Sync {
+    /// execute command with arguments (device commands take device id as the first argument)
+    fn execute_cmd(&self, cmd: Commands, args: &[String]) -> CommandResult;
+
+    /// Commands executed by the backend, they are listed in the catalog of the server
+    fn commands(&self) -> Vec<Commands>;
+}
+
+/// Returns argument of the request, server guarantees number of arguments declared by the command
+pub fn arg(args: &[String], i: usize) -> &str {
+    args.get(i).map(|v| v.as_str()).unwrap_or_default()
+}
+
+/// Backend with AC sockets simulated in the server process
+#[derive(Debug, Default)]
+pub struct LocalBackend {
+    devices: Mutex<Vec<ACSocket>>,
+    store: Option<DeviceStore>,
+}
+
+impl LocalBackend {
+    pub fn new(devices: Vec<ACSocket>) -> Self {
+        Self {
+            devices: Mutex::new(devices),
+            store: None,
+        }
+    }
+
+    /// Creates backend which saves devices to the store after each change
+    pub fn with_store(devices: Vec<ACSocket>, store: DeviceStore) -> Self {
+        Self {
+            devices: Mutex::new(devices),
+            store: Some(store),
+        }
+    }
+
+    fn save(&self, devices: &mut [ACSocket]) {
+        if let Some(store) = &self.store {
+            if let Err(v) = store.save(devices) {
+                log_error!("cannot save device state {}", v);
+            }
+        }
+    }
+}
+
+fn find_device<'a>(
+    devices: &'a mut [ACSocket],
+    id: &str,
+) -> Result<&'a mut ACSocket, CommandError> {
+    devices
+        .iter_mut()
+        .find(|d| d.get_id().to_string() == id)
+        .ok_or_else(|| CommandError::new(Status::UnknownDevice, format!("device {} not found", id)))
+}
+
+impl DeviceBackend for LocalBackend {
+    fn execute_cmd(&self, cmd: Commands, args: &[String]) -> CommandResult {
+        let mut devices = self.devices.lock().unwrap();
+        let result = match cmd {
+            Commands::ListDevices => {
+                let mut response = vec![Packet::Int32(devices.len() as i32)];
+                response.extend(devices.iter().map(|d| Packet::Str(d.get_id().to_string())));
+                Ok(response)
+            }
+            Commands::PowerOn => {
+                find_device(&mut devices, arg(args, 0))?.switch(PowerState::ON);
+                Ok(vec![])
+            }
+            Commands::PowerOff => {
+                find_device(&mut devices, arg(args, 0))?.switch(PowerState::OFF);
+                Ok(vec![])
+            }
+            Commands::GetStatus => Ok(vec![Packet::Str(
+                find_device(&mut devices, arg(args, 0))?.get_state(),
+            )]),
+            Commands::GetConsumption => Ok(vec![Packet::Float32(
+                find_device(&mut devices, arg(args, 0))?.get_consumption(),
+            )]),
+            Commands::GetEnergy => Ok(vec![Packet::Float32(
+                find_device(&mut devices, arg(args, 0))?.get_energy() as f32,
+            )]),
+            Commands::ResetEnergy => {
+                find_device(&mut devices, arg(args, 0))?.reset_energy();
+                Ok(vec![])
+            }
+            Commands::CreateDevice => match arg(args, 0) {
+                SOCKET_KIND => {
+                    let socket = ACSocket::with_name(arg(args, 1).to_string());
+                    let id = socket.get_id().to_string();
+                    devices.push(socket);
+                    Ok(vec![Packet::Str(id)])
+                }
+                kind => Err(CommandError::new(
+                    Status::BadRequest,
+                    format!("unsupported device type {}", kind),
+                )),
+            },
+            Commands::RemoveDevice => {
+                let id = find_device(&mut devices, arg(args, 0))?.get_id();
+                devices.retain(|d| d.get_id() != id);
+                Ok(vec![])
+            }
+            Commands::RenameDevice => {
+                find_device(&mut devices, arg(args, 0))?.set_name(arg(args, 1).to_string());
+                Ok(vec![])
+            }
+            Commands::DescribeDevice => Ok(find_device(&mut devices, arg(args, 0))?
+                .get_info()
+                .to_packets()),
+            Commands::GetCatalog => {
+                find_device(&mut devices, arg(args, 0))?;
+                Ok(catalog_packets(SOCKET_COMMANDS))
+            }
+            // connection level commands are handled by the server
+            Commands::Authenticate
+            | Commands::OpenSession
+            | Commands::SelectDevice
+            | Commands::SetPreference => Err(CommandError::new(
+                Status::BadRequest,
+                format!("{} is not a device command", cmd.name()),
+            )),
+            Commands::RegisterEmulator => Err(CommandError::new(
+                Status::UnknownCommand,
+                format!("{} is supported only by hub", cmd.name()),
+            )),
+        };
+        if result.is_ok() && cmd.is_modifying() {
+            self.save(&mut devices);
+        }
+        result
+    }
+
+    fn commands(&self) -> Vec<Commands> {
+        let mut commands = vec![Commands::ListDevices, Commands::CreateDevice];
+        commands.extend(SOCKET_COMMANDS);
+        commands
+    }
+}
@@ -0,0 +1,21 @@
+//! Catalog of commands supported by the server or by the device, see `libprotocol::catalog`
+
+use crate::Commands;
+
+pub use libprotocol::catalog::{
+    catalog_packets, encode_catalog, read_catalog, ArgInfo, CommandInfo,
+};
+
+/// Commands of the AC socket device
+pub const SOCKET_COMMANDS: &[Commands] = &[
+    Commands::PowerOn,
+    Commands::PowerOff,
+    Commands::GetStatus,
+    Commands::GetConsumption,
+    Commands::GetEnergy,
+    Commands::ResetEnergy,
+    Commands::DescribeDevice,
+    Commands::RenameDevice,
+    Commands::RemoveDevice,
+    Commands::GetCatalog,
+];
@@ -0,0 +1,628 @@
+//! Declarative TOML configuration of the IoT server
+//!
+//! ```toml
+//! [[listeners]]
+//! address = "127.0.0.1:8088"
+//!
+//! [[devices]]
+//! id = "cpbfcv4q6r8t2e9p6rlg"
+//! name = "kettle"
+//! type = "socket"
+//! load = { type = "scheduled", steps = [{ secs = 120, watts = 2000 }, { secs = 600, watts = 0 }] }
+//!
+//! [timeouts]
+//! idle_secs = 300
+//! drain_secs = 10
+//! session_secs = 300
+//!
+//! [limits]
+//! max_connections = 64
+//!
+//! [logging]
+//! level = "info"
+//!
+//! [auth]
+//! admin_tokens = ["secret"]
+//! anonymous_role = "monitor"
+//! clients = [{ name = "dashboard", token = "dashboard-secret", role = "monitor" }]
+//!
+//! [audit]
+//! file = "audit.log"
+//!
+//! [metrics]
+//! address = "127.0.0.1:9090"
+//!
+//! [storage]
+//! state_file = "devices.json"
+//!
+//! [discovery]
+//! address = "0.0.0.0:8089"
+//! name = "kitchen"
+//! ```
+//!
+//! Hub serves devices of emulators instead of own devices,
+//! so `devices` and `storage` are not allowed with it:
+//!
+//! ```toml
+//! [hub]
+//! emulators = ["127.0.0.1:8101", "127.0.0.1:8102"]
+//! token = "emulator-secret"
+//! ```
+
+use std::{
+    collections::HashSet, fs, io, net::SocketAddr, path::Path, path::PathBuf, str::FromStr,
+    sync::Arc, time::Duration,
+};
+
+use libprotocol::log;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    auth::{ClientCredential, Role},
+    discovery::{DEFAULT_NAME, DISCOVERY_PORT},
+    energy::LoadModel,
+    hub::Hub,
+    iotserver::{IotServerBuilder, ServerSettings},
+    ACSocket, PowerState, SOCKET_KIND,
+};
+
+/// Configuration error
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read {0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+    /// Value is well-formed but not valid, first field is the path to the value
+    #[error("{0}: {1}")]
+    Invalid(String, String),
+}
+
+fn invalid(field: String, msg: &str) -> ConfigError {
+    ConfigError::Invalid(field, msg.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub address: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type", default = "default_kind")]
+    pub kind: String,
+    #[serde(default)]
+    pub load: LoadModel,
+}
+
+fn default_kind() -> String {
+    String::from(SOCKET_KIND)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    /// Disconnect clients which send nothing during this number of seconds
+    pub idle_secs: Option<u64>,
+    /// Wait for commands in progress during this number of seconds on shutdown
+    pub drain_secs: Option<u64>,
+    /// Keep sessions of disconnected clients during this number of seconds
+    pub session_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_connections: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl From<LogLevel> for log::Level {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Debug => log::Level::Debug,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub level: LogLevel,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub admin_tokens: Vec<String>,
+    /// Role of the clients which aren't authenticated
+    #[serde(default)]
+    pub anonymous_role: Role,
+    #[serde(default)]
+    pub clients: Vec<ClientCredential>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub address: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    pub state_file: PathBuf,
+}
+
+/// UDP discovery responder, see [`crate::discovery`]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    #[serde(default = "default_discovery_address")]
+    pub address: String,
+    /// Name of the server in announcements
+    #[serde(default = "default_discovery_name")]
+    pub name: String,
+}
+
+fn default_discovery_address() -> String {
+    format!("0.0.0.0:{}", DISCOVERY_PORT)
+}
+
+fn default_discovery_name() -> String {
+    String::from(DEFAULT_NAME)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HubConfig {
+    /// Addresses of emulators, more emulators may register at runtime
+    #[serde(default)]
+    pub emulators: Vec<String>,
+    /// Token to authenticate on emulators
+    pub token: Option<String>,
+}
+
+/// Configuration of the server binary
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listeners: Vec<ListenerConfig>,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    pub metrics: Option<MetricsConfig>,
+    pub storage: Option<StorageConfig>,
+    pub hub: Option<HubConfig>,
+    pub discovery: Option<DiscoveryConfig>,
+    pub audit: Option<AuditConfig>,
+}
+
+impl FromStr for ServerConfig {
+    type Err = ConfigError;
+
+    /// Parses and validates configuration
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: ServerConfig = toml::from_str(s)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        fs::read_to_string(path)
+            .map_err(|v| ConfigError::Io(path.to_path_buf(), v))?
+            .parse()
+    }
+
+    /// Applies command line options over the configuration: admin tokens are
+    /// added to the configured ones, the state file replaces the configured one
+    pub fn override_with(
+        &mut self,
+        admin_tokens: &[String],
+        state_file: Option<&Path>,
+    ) -> Result<(), ConfigError> {
+        self.auth.admin_tokens.extend(admin_tokens.iter().cloned());
+        if let Some(path) = state_file {
+            self.storage = Some(StorageConfig {
+                state_file: path.to_path_buf(),
+            });
+        }
+        self.validate()
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.listeners.is_empty() {
+            return Err(invalid(
+                String::from("listeners"),
+                "at least one listener is required",
+            ));
+        }
+        for (i, l) in self.listeners.iter().enumerate() {
+            if SocketAddr::from_str(&l.address).is_err() {
+                return Err(ConfigError::Invalid(
+                    format!("listeners[{}].address", i),
+                    format!("invalid socket address '{}'", l.address),
+                ));
+            }
+        }
+
+        let mut ids = HashSet::new();
+        for (i, d) in self.devices.iter().enumerate() {
+            if xid::Id::from_str(&d.id).is_err() {
+                return Err(ConfigError::Invalid(
+                    format!("devices[{}].id", i),
+                    format!("invalid device id '{}'", d.id),
+                ));
+            }
+            if !ids.insert(d.id.as_str()) {
+                return Err(ConfigError::Invalid(
+                    format!("devices[{}].id", i),
+                    format!("duplicate device id '{}'", d.id),
+                ));
+            }
+            if d.kind != SOCKET_KIND {
+                return Err(ConfigError::Invalid(
+                    format!("devices[{}].type", i),
+                    format!("unsupported device type '{}'", d.kind),
+                ));
+            }
+            if let Err(v) = d.load.validate() {
+                return Err(ConfigError::Invalid(format!("devices[{}].load", i), v));
+            }
+        }
+
+        if self.timeouts.idle_secs == Some(0) {
+            return Err(invalid(
+                String::from("timeouts.idle_secs"),
+                "must be greater than 0",
+            ));
+        }
+        if self.timeouts.session_secs == Some(0) {
+            return Err(invalid(
+                String::from("timeouts.session_secs"),
+                "must be greater than 0",
+            ));
+        }
+        if self.limits.max_connections == Some(0) {
+            return Err(invalid(
+                String::from("limits.max_connections"),
+                "must be greater than 0",
+            ));
+        }
+        if self.auth.admin_tokens.iter().any(|t| t.is_empty()) {
+            return Err(invalid(
+                String::from("auth.admin_tokens"),
+                "empty token is not allowed",
+            ));
+        }
+        let mut tokens: HashSet<&str> = self.auth.admin_tokens.iter().map(|t| t.as_str()).collect();
+        for (i, c) in self.auth.clients.iter().enumerate() {
+            if c.name.is_empty() {
+                return Err(invalid(format!("auth.clients[{}].name", i), "empty name"));
+            }
+            if c.token.is_empty() {
+                return Err(invalid(
+                    format!("auth.clients[{}].token", i),
+                    "empty token is not allowed",
+                ));
+            }
+            if !tokens.insert(c.token.as_str()) {
+                return Err(invalid(
+                    format!("auth.clients[{}].token", i),
+                    "token is used by other client",
+                ));
+            }
+        }
+        if let Some(h) = &self.hub {
+            if !self.devices.is_empty() {
+                return Err(invalid(
+                    String::from("devices"),
+                    "devices are not allowed in hub mode",
+                ));
+            }
+            if self.storage.is_some() {
+                return Err(invalid(
+                    String::from("storage"),
+                    "storage is not allowed in hub mode",
+                ));
+            }
+            if let Some(i) = h.emulators.iter().position(|e| e.is_empty()) {
+                return Err(invalid(format!("hub.emulators[{}]", i), "empty address"));
+            }
+        }
+        if let Some(m) = &self.metrics {
+            if SocketAddr::from_str(&m.address).is_err() {
+                return Err(ConfigError::Invalid(
+                    String::from("metrics.address"),
+                    format!("invalid socket address '{}'", m.address),
+                ));
+            }
+        }
+        if let Some(d) = &self.discovery {
+            if SocketAddr::from_str(&d.address).is_err() {
+                return Err(ConfigError::Invalid(
+                    String::from("discovery.address"),
+                    format!("invalid socket address '{}'", d.address),
+                ));
+            }
+            if d.name.is_empty() {
+                return Err(invalid(String::from("discovery.name"), "empty name"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings which can be applied to running server
+    pub fn settings(&self) -> ServerSettings {
+        ServerSettings {
+            admin_tokens: self.auth.admin_tokens.clone(),
+            clients: self.auth.clients.clone(),
+            anonymous_role: self.auth.anonymous_role,
+            max_connections: self.limits.max_connections,
+            idle_timeout: self.timeouts.idle_secs.map(Duration::from_secs),
+            drain_timeout: self.timeouts.drain_secs.map(Duration::from_secs),
+            session_ttl: self.timeouts.session_secs.map(Duration::from_secs),
+        }
+    }
+
+    /// Checks if configuration differs from other one only by settings
+    /// which can be reloaded without restart. Emulators registered at runtime
+    /// are kept by the hub, so hub section requires restart too.
+    pub fn is_reloadable_from(&self, other: &ServerConfig) -> bool {
+        self.listeners == other.listeners
+            && self.devices == other.devices
+            && self.metrics == other.metrics
+            && self.storage == other.storage
+            && self.hub == other.hub
+            && self.discovery == other.discovery
+            && self.audit == other.audit
+    }
+
+    /// Creates server builder with listeners, devices and settings of the configuration
+    pub fn to_builder(&self) -> IotServerBuilder {
+        let mut builder = IotServerBuilder::new();
+        self.listeners.iter().for_each(|l| {
+            builder.add_addr(l.address.clone());
+        });
+        self.devices.iter().for_each(|d| {
+            // ids are checked by validation
+            let id = xid::Id::from_str(&d.id).unwrap();
+            let mut socket = ACSocket::restore(id, d.name.clone(), PowerState::OFF);
+            socket.set_load_model(d.load.clone());
+            builder.add_device(socket);
+        });
+        if let Some(s) = &self.storage {
+            builder.set_state_file(s.state_file.clone());
+        }
+        if let Some(a) = &self.audit {
+            builder.set_audit_log(a.file.clone());
+        }
+        if let Some(h) = &self.hub {
+            builder.set_backend(Arc::new(Hub::with_token(
+                h.emulators.clone(),
+                h.token.clone(),
+            )));
+        }
+        builder.set_settings(self.settings());
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[[listeners]]
+address = "127.0.0.1:0"
+
+[[devices]]
+id = "cpbfcv4q6r8t2e9p6rlg"
+name = "kettle"
+load = { type = "random", seed = 1, min_watts = 1500, max_watts = 2000, interval_secs = 10 }
+
+[timeouts]
+idle_secs = 30
+drain_secs = 5
+session_secs = 600
+
+[limits]
+max_connections = 4
+
+[logging]
+level = "debug"
+
+[auth]
+admin_tokens = ["secret"]
+anonymous_role = "monitor"
+clients = [{ name = "dashboard", token = "view", role = "monitor" }]
+"#;
+
+    fn error(s: &str) -> String {
+        ServerConfig::from_str(s).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = ServerConfig::from_str(CONFIG).unwrap();
+        assert_eq!(config.devices[0].kind, "socket");
+        assert!(matches!(
+            config.devices[0].load,
+            LoadModel::Random { seed: 1, .. }
+        ));
+        assert_eq!(config.logging.level, LogLevel::Debug);
+        assert_eq!(
+            config.settings(),
+            ServerSettings {
+                admin_tokens: vec![String::from("secret")],
+                clients: vec![ClientCredential {
+                    name: String::from("dashboard"),
+                    token: String::from("view"),
+                    role: Role::Monitor,
+                }],
+                anonymous_role: Role::Monitor,
+                max_connections: Some(4),
+                idle_timeout: Some(Duration::from_secs(30)),
+                drain_timeout: Some(Duration::from_secs(5)),
+                session_ttl: Some(Duration::from_secs(600)),
+            }
+        );
+        let server = config.to_builder().build().unwrap();
+        assert_eq!(server.settings(), config.settings());
+
+        let mut changed = config.clone();
+        changed.limits.max_connections = Some(8);
+        assert!(changed.is_reloadable_from(&config));
+        changed.devices.clear();
+        assert!(!changed.is_reloadable_from(&config));
+
+        let hub = ServerConfig::from_str(
+            "[[listeners]]\naddress = \"127.0.0.1:0\"\n[hub]\nemulators = [\"127.0.0.1:8101\"]",
+        )
+        .unwrap();
+        assert_eq!(hub.hub.as_ref().unwrap().emulators, vec!["127.0.0.1:8101"]);
+        // emulators are connected lazily
+        assert!(hub.to_builder().build().is_ok());
+
+        // write access always needs a token
+        for anonymous in [
+            CONFIG.replace("anonymous_role = \"monitor\"\n", ""),
+            String::from("[[listeners]]\naddress = \"127.0.0.1:0\""),
+        ] {
+            let config = ServerConfig::from_str(&anonymous).unwrap();
+            assert_eq!(config.settings().anonymous_role, Role::Monitor);
+        }
+        assert_eq!(ServerSettings::default().anonymous_role, Role::Monitor);
+
+        let discovery = ServerConfig::from_str(
+            "[[listeners]]\naddress = \"127.0.0.1:0\"\n[discovery]\nname = \"kitchen\"",
+        )
+        .unwrap()
+        .discovery
+        .unwrap();
+        assert_eq!(discovery.address, "0.0.0.0:8089");
+        assert_eq!(discovery.name, "kitchen");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error("listeners = []"),
+            "listeners: at least one listener is required"
+        );
+        assert_eq!(
+            error("[[listeners]]\naddress = \"localhost\""),
+            "listeners[0].address: invalid socket address 'localhost'"
+        );
+        assert_eq!(
+            error(&CONFIG.replace("cpbfcv4q6r8t2e9p6rlg", "abc")),
+            "devices[0].id: invalid device id 'abc'"
+        );
+        assert_eq!(
+            error(&format!(
+                "{}\n[[devices]]\nid = \"cpbfcv4q6r8t2e9p6rlg\"",
+                CONFIG
+            )),
+            "devices[1].id: duplicate device id 'cpbfcv4q6r8t2e9p6rlg'"
+        );
+        assert_eq!(
+            error(&CONFIG.replace("max_connections = 4", "max_connections = 0")),
+            "limits.max_connections: must be greater than 0"
+        );
+        assert_eq!(
+            error(&CONFIG.replace("min_watts = 1500", "min_watts = 2500")),
+            "devices[0].load: min_watts is greater than max_watts"
+        );
+        assert_eq!(
+            error(&format!("{}\n[discovery]\nname = \"\"", CONFIG)),
+            "discovery.name: empty name"
+        );
+        assert_eq!(
+            error(&CONFIG.replace("\"view\"", "\"secret\"")),
+            "auth.clients[0].token: token is used by other client"
+        );
+        assert!(error(&CONFIG.replace("\"monitor\" }", "\"root\" }"))
+            .contains("unknown variant `root`"));
+        // syntax and schema errors point to the line
+        let err = error(&CONFIG.replace("debug", "verbose"));
+        assert!(err.contains("line 19"));
+        assert!(err.contains("unknown variant `verbose`"));
+        assert_eq!(
+            error(&format!(
+                "{}\n[hub]\nemulators = [\"127.0.0.1:8101\"]",
+                CONFIG
+            )),
+            "devices: devices are not allowed in hub mode"
+        );
+        assert!(
+            error(&CONFIG.replace("[limits]", "[limits]\nmax_clients = 1"))
+                .contains("unknown field `max_clients`")
+        );
+    }
+
+    #[test]
+    fn test_override() {
+        let mut config: ServerConfig = CONFIG.parse().unwrap();
+        config
+            .override_with(&[String::from("cli")], Some(Path::new("state.json")))
+            .unwrap();
+        assert_eq!(config.auth.admin_tokens, vec!["secret", "cli"]);
+        assert_eq!(
+            config.storage.unwrap().state_file,
+            PathBuf::from("state.json")
+        );
+
+        let mut config: ServerConfig = CONFIG.parse().unwrap();
+        let err = config
+            .override_with(&[String::from("view")], None)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "auth.clients[0].token: token is used by other client"
+        );
+
+        let mut hub: ServerConfig = "[[listeners]]\naddress = \"127.0.0.1:0\"\n[hub]"
+            .parse()
+            .unwrap();
+        let err = hub
+            .override_with(&[], Some(Path::new("state.json")))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "storage: storage is not allowed in hub mode"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Discovery of IoT servers on the LAN.
+//!
+//! Client broadcasts probe datagram to the discovery port, every responder answers
+//! to the sender with the announcement: name of the server, protocol version
+//! and TCP port of the protocol listener.
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    thread,
+};
+
+use libprotocol::{log_debug, log_error, log_info};
+
+pub use libprotocol::discovery::{Announcement, DISCOVERY_PORT, PROBE};
+
+/// Default name of the server in announcements
+pub const DEFAULT_NAME: &str = "iot-server";
+
+/// Starts responder on the UDP address in the background thread, returns bound address
+pub fn start_discovery_responder(
+    addr: String,
+    announcement: Announcement,
+) -> io::Result<SocketAddr> {
+    let socket = UdpSocket::bind(addr)?;
+    let local = socket.local_addr()?;
+    log_info!(
+        "Answering discovery probes on {} as {}",
+        local,
+        announcement.name
+    );
+    let reply = announcement.encode();
+    thread::spawn(move || {
+        let mut buf = [0; 64];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((n, peer)) if &buf[..n] == PROBE => {
+                    log_debug!("Discovery probe from {}", peer);
+                    if let Err(v) = socket.send_to(&reply, peer) {
+                        log_error!("cannot answer discovery probe from {}: {:?}", peer, v);
+                    }
+                }
+                Ok((_, peer)) => log_debug!("Unknown datagram from {}", peer),
+                Err(v) => log_error!("discovery socket error {:?}", v),
+            }
+        }
+    });
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_responder() {
+        let announcement = Announcement::new(String::from("kitchen"), 8088);
+        let addr =
+            start_discovery_responder(String::from("127.0.0.1:0"), announcement.clone()).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // unknown datagrams are ignored
+        client.send_to(b"hello", addr).unwrap();
+        client.send_to(PROBE, addr).unwrap();
+        let mut buf = [0; 256];
+        let (n, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(from, addr);
+        assert_eq!(Announcement::decode(&buf[..n]), Some(announcement));
+    }
+}
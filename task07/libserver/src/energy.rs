@@ -0,0 +1,340 @@
+//! Load models and energy metering of the devices
+
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Step of the scheduled load profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoadStep {
+    /// Duration of the step
+    pub secs: u64,
+    pub watts: f32,
+}
+
+/// Power drawn by the switched on device. Power depends only on the time
+/// since the device was switched on, so it is reproducible.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum LoadModel {
+    /// Device always draws the same power
+    Constant { watts: f32 },
+    /// Device repeats the profile of steps
+    Scheduled { steps: Vec<LoadStep> },
+    /// Power changes every interval to the value of the pseudo random sequence,
+    /// the sequence is defined by the seed
+    Random {
+        seed: u64,
+        min_watts: f32,
+        max_watts: f32,
+        interval_secs: u64,
+    },
+}
+
+impl Default for LoadModel {
+    fn default() -> Self {
+        LoadModel::Constant { watts: 60.0 }
+    }
+}
+
+fn check_watts(watts: f32) -> Result<(), String> {
+    match watts.is_finite() && watts >= 0.0 {
+        true => Ok(()),
+        false => Err(format!("invalid power {}", watts)),
+    }
+}
+
+impl LoadModel {
+    /// Checks parameters of the model, returns description of the problem
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            LoadModel::Constant { watts } => check_watts(*watts),
+            LoadModel::Scheduled { steps } => {
+                if steps.is_empty() {
+                    return Err(String::from("schedule has no steps"));
+                }
+                for s in steps {
+                    if s.secs == 0 {
+                        return Err(String::from("step duration must be greater than 0"));
+                    }
+                    check_watts(s.watts)?;
+                }
+                Ok(())
+            }
+            LoadModel::Random {
+                min_watts,
+                max_watts,
+                interval_secs,
+                ..
+            } => {
+                check_watts(*min_watts)?;
+                check_watts(*max_watts)?;
+                if min_watts > max_watts {
+                    return Err(String::from("min_watts is greater than max_watts"));
+                }
+                if *interval_secs == 0 {
+                    return Err(String::from("interval must be greater than 0"));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns power in watts and the time when it changes next
+    fn segment(&self, elapsed: Duration) -> (f32, Option<Duration>) {
+        match self {
+            LoadModel::Constant { watts } => (*watts, None),
+            LoadModel::Scheduled { steps } => {
+                let period: u128 = steps.iter().map(|s| s.secs as u128 * 1_000_000_000).sum();
+                let offset = elapsed.as_nanos() % period;
+                let start = elapsed - Duration::from_nanos(offset as u64);
+                let mut end = 0;
+                for s in steps {
+                    end += s.secs as u128 * 1_000_000_000;
+                    if offset < end {
+                        return (s.watts, Some(start + Duration::from_nanos(end as u64)));
+                    }
+                }
+                unreachable!("offset is less than period")
+            }
+            LoadModel::Random {
+                seed,
+                min_watts,
+                max_watts,
+                interval_secs,
+            } => {
+                let index = elapsed.as_secs() / interval_secs;
+                let mut rng = StdRng::seed_from_u64(seed ^ index.wrapping_mul(0x9e3779b97f4a7c15));
+                (
+                    rng.gen_range(*min_watts..=*max_watts),
+                    Some(Duration::from_secs((index + 1) * interval_secs)),
+                )
+            }
+        }
+    }
+
+    /// Power in watts after the given time since switch on
+    pub fn power_at(&self, elapsed: Duration) -> f32 {
+        self.segment(elapsed).0
+    }
+
+    /// Energy in watt-seconds consumed between two moments since switch on
+    pub fn energy_between(&self, from: Duration, to: Duration) -> f64 {
+        let mut energy = 0.0;
+        let mut t = from;
+        while t < to {
+            let (watts, end) = self.segment(t);
+            let end = end.map_or(to, |v| v.min(to));
+            energy += watts as f64 * (end - t).as_secs_f64();
+            t = end;
+        }
+        energy
+    }
+}
+
+const JOULES_PER_KWH: f64 = 3_600_000.0;
+
+/// Cumulative energy meter of the device
+#[derive(Debug, Clone)]
+pub struct EnergyMeter {
+    model: LoadModel,
+    /// Energy in watt-seconds counted before `counted_from`
+    stored: f64,
+    /// Time when device was switched on
+    on_since: Option<Instant>,
+    /// Start of the part of the current run which isn't counted yet, it is
+    /// moved by readings and resets of the meter
+    counted_from: Option<Instant>,
+}
+
+impl Default for EnergyMeter {
+    fn default() -> Self {
+        Self::new(LoadModel::default())
+    }
+}
+
+impl EnergyMeter {
+    pub fn new(model: LoadModel) -> Self {
+        Self {
+            model,
+            stored: 0.0,
+            on_since: None,
+            counted_from: None,
+        }
+    }
+
+    pub fn get_model(&self) -> &LoadModel {
+        &self.model
+    }
+
+    /// Replaces the load model, the current run is restarted with the new model
+    pub fn set_model(&mut self, model: LoadModel, now: Instant) {
+        let on = self.on_since.is_some();
+        self.switch_off(now);
+        self.model = model;
+        if on {
+            self.switch_on(now);
+        }
+    }
+
+    pub fn switch_on(&mut self, now: Instant) {
+        if self.on_since.is_none() {
+            self.on_since = Some(now);
+            self.counted_from = Some(now);
+        }
+    }
+
+    pub fn switch_off(&mut self, now: Instant) {
+        self.checkpoint(now);
+        self.on_since = None;
+        self.counted_from = None;
+    }
+
+    /// Power in watts, zero when device is off
+    pub fn power(&self, now: Instant) -> f32 {
+        match self.on_since {
+            Some(since) => self.model.power_at(now.saturating_duration_since(since)),
+            None => 0.0,
+        }
+    }
+
+    fn current_run(&self, now: Instant) -> f64 {
+        match (self.on_since, self.counted_from) {
+            (Some(since), Some(from)) => self.model.energy_between(
+                from.saturating_duration_since(since),
+                now.saturating_duration_since(since),
+            ),
+            _ => 0.0,
+        }
+    }
+
+    /// Folds energy of the current run up to now into the stored energy,
+    /// so the next reading integrates the load model from now only
+    fn checkpoint(&mut self, now: Instant) {
+        self.stored += self.current_run(now);
+        if let Some(from) = self.counted_from {
+            self.counted_from = Some(from.max(now));
+        }
+    }
+
+    /// Energy in kWh consumed since the last reset
+    pub fn energy_kwh(&mut self, now: Instant) -> f64 {
+        self.checkpoint(now);
+        self.stored / JOULES_PER_KWH
+    }
+
+    pub fn reset(&mut self, now: Instant) {
+        self.stored = 0.0;
+        if self.on_since.is_some() {
+            self.counted_from = Some(now);
+        }
+    }
+
+    /// Sets energy counted before, used to restore saved meter
+    pub fn set_energy_kwh(&mut self, kwh: f64) {
+        self.stored = kwh * JOULES_PER_KWH;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(v: u64) -> Duration {
+        Duration::from_secs(v)
+    }
+
+    #[test]
+    fn test_models() {
+        let constant = LoadModel::Constant { watts: 100.0 };
+        assert_eq!(constant.power_at(secs(1000)), 100.0);
+        assert_eq!(constant.energy_between(secs(0), secs(36)), 3600.0);
+
+        let scheduled = LoadModel::Scheduled {
+            steps: vec![
+                LoadStep {
+                    secs: 10,
+                    watts: 1000.0,
+                },
+                LoadStep {
+                    secs: 20,
+                    watts: 10.0,
+                },
+            ],
+        };
+        assert_eq!(scheduled.power_at(secs(5)), 1000.0);
+        assert_eq!(scheduled.power_at(secs(10)), 10.0);
+        assert_eq!(scheduled.power_at(secs(35)), 1000.0);
+        // two periods and the half of the first step
+        assert_eq!(
+            scheduled.energy_between(secs(0), secs(65)),
+            2.0 * 10200.0 + 5000.0
+        );
+
+        let random = LoadModel::Random {
+            seed: 7,
+            min_watts: 10.0,
+            max_watts: 20.0,
+            interval_secs: 5,
+        };
+        let powers: Vec<f32> = (0..10).map(|i| random.power_at(secs(i * 5))).collect();
+        assert!(powers.iter().all(|v| (10.0..=20.0).contains(v)));
+        assert_eq!(random.power_at(secs(11)), powers[2]);
+        assert_eq!(
+            powers,
+            (0..10)
+                .map(|i| random.power_at(secs(i * 5)))
+                .collect::<Vec<_>>()
+        );
+        assert!(random.validate().is_ok());
+        assert!(LoadModel::Scheduled { steps: vec![] }.validate().is_err());
+        assert!(LoadModel::Constant { watts: -1.0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_meter() {
+        let t0 = Instant::now();
+        let mut meter = EnergyMeter::new(LoadModel::Constant { watts: 1000.0 });
+        assert_eq!(meter.power(t0), 0.0);
+        meter.switch_on(t0);
+        assert_eq!(meter.power(t0), 1000.0);
+        assert_eq!(meter.energy_kwh(t0 + secs(900)), 0.25);
+        meter.switch_off(t0 + secs(1800));
+        assert_eq!(meter.power(t0 + secs(3600)), 0.0);
+        assert_eq!(meter.energy_kwh(t0 + secs(3600)), 0.5);
+
+        meter.switch_on(t0 + secs(3600));
+        meter.reset(t0 + secs(5400));
+        assert_eq!(meter.energy_kwh(t0 + secs(7200)), 0.5);
+        meter.set_energy_kwh(2.0);
+        assert_eq!(meter.energy_kwh(t0 + secs(5400)), 2.0);
+    }
+
+    #[test]
+    fn test_meter_checkpoints() {
+        let model = LoadModel::Random {
+            seed: 3,
+            min_watts: 10.0,
+            max_watts: 20.0,
+            interval_secs: 7,
+        };
+        let t0 = Instant::now();
+        let mut meter = EnergyMeter::new(model.clone());
+        meter.switch_on(t0);
+        let mut last = 0.0;
+        for i in 1..=100 {
+            let kwh = meter.energy_kwh(t0 + secs(i * 5));
+            assert!(kwh > last);
+            last = kwh;
+        }
+        // readings integrate from the previous one only
+        assert_eq!(meter.counted_from, Some(t0 + secs(500)));
+        let expected = model.energy_between(secs(0), secs(500)) / JOULES_PER_KWH;
+        assert!((last - expected).abs() < 1e-9);
+        // reading of the past doesn't move the checkpoint back
+        assert_eq!(meter.energy_kwh(t0 + secs(400)), last);
+        assert_eq!(meter.counted_from, Some(t0 + secs(500)));
+    }
+}
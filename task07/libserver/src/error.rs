@@ -0,0 +1,17 @@
+use std::{io, path::PathBuf};
+
+use libprotocol::error::BindError;
+use thiserror::Error;
+
+use crate::storage::StorageError;
+
+/// Error of the server start
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("cannot bind: {0}")]
+    Bind(#[from] BindError),
+    #[error("cannot restore devices: {0}")]
+    Storage(#[from] StorageError),
+    #[error("cannot open audit log {0:?}: {1}")]
+    Audit(PathBuf, io::Error),
+}
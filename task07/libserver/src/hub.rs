@@ -0,0 +1,440 @@
+//! Hub backend: connects out to device emulators and serves their devices.
+//! Unlike the gateway, the hub keeps the inventory of registered emulators,
+//! so devices of the offline emulator are still listed.
+
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::Duration,
+};
+
+use libprotocol::{client::TcpClient, error::RecvError, log_error, log_info, Packet};
+
+use crate::{
+    backend::{arg, CommandError, CommandResult, DeviceBackend},
+    catalog::{encode_catalog, read_catalog, SOCKET_COMMANDS},
+    Commands, Status,
+};
+
+/// Default limit of connecting to the emulator and of waiting for its response
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn unavailable(addr: &str, msg: String) -> CommandError {
+    CommandError::new(
+        Status::UpstreamUnavailable,
+        format!("emulator {} is unavailable: {}", addr, msg),
+    )
+}
+
+/// Reads response of the command, payload is relayed as is
+fn read_response(client: &mut TcpClient, cmd: Commands) -> Result<CommandResult, RecvError> {
+    let status = match client.recv_response()? {
+        Packet::Byte(v) => Status::try_from(v).map_err(|_| RecvError::InvalidFormat)?,
+        _ => return Err(RecvError::InvalidFormat),
+    };
+    if status != Status::Ok {
+        return match client.recv_response()? {
+            Packet::Str(msg) => Ok(Err(CommandError::new(status, msg))),
+            _ => Err(RecvError::InvalidFormat),
+        };
+    }
+    if cmd == Commands::GetCatalog {
+        let catalog = read_catalog(|| client.recv_response(), || RecvError::InvalidFormat)?;
+        return Ok(Ok(encode_catalog(&catalog)));
+    }
+    let mut payload = vec![];
+    let count = match cmd {
+        Commands::ListDevices => match client.recv_response()? {
+            Packet::Int32(n) if n >= 0 => {
+                payload.push(Packet::Int32(n));
+                n as usize
+            }
+            _ => return Err(RecvError::InvalidFormat),
+        },
+        Commands::DescribeDevice => 4,
+        Commands::GetStatus
+        | Commands::GetConsumption
+        | Commands::GetEnergy
+        | Commands::CreateDevice => 1,
+        _ => 0,
+    };
+    for _ in 0..count {
+        payload.push(client.recv_response()?);
+    }
+    Ok(Ok(payload))
+}
+
+/// Connection to the emulator process. Connection is established lazily and
+/// re-established on next call after IO failure.
+struct Emulator {
+    addr: String,
+    timeout: Duration,
+    client: Mutex<Option<TcpClient>>,
+    /// Devices reported by the last successful listing
+    devices: RwLock<Vec<String>>,
+}
+
+impl Emulator {
+    fn new(addr: String, timeout: Duration) -> Self {
+        Self {
+            addr,
+            timeout,
+            client: Mutex::new(None),
+            devices: RwLock::new(vec![]),
+        }
+    }
+
+    fn connect(&self, token: Option<&str>) -> Result<TcpClient, CommandError> {
+        let mut client = TcpClient::connect_timeout(self.addr.clone(), self.timeout)
+            .map_err(|v| unavailable(&self.addr, v.to_string()))?;
+        if let Some(token) = token {
+            let result = client
+                .send_request(&[
+                    Packet::Byte(Commands::Authenticate as u8),
+                    Packet::Str(token.to_string()),
+                ])
+                .map_err(|v| unavailable(&self.addr, v.to_string()))
+                .and_then(|_| {
+                    read_response(&mut client, Commands::Authenticate)
+                        .map_err(|v| unavailable(&self.addr, v.to_string()))
+                })?;
+            if let Err(v) = result {
+                log_error!("Cannot authenticate on emulator {}: {}", self.addr, v);
+            }
+        }
+        Ok(client)
+    }
+
+    fn call(&self, token: Option<&str>, cmd: Commands, args: &[String]) -> CommandResult {
+        let mut guard = self.client.lock().unwrap();
+        if guard.is_none() {
+            *guard = Some(self.connect(token)?);
+        }
+        let client = guard.as_mut().unwrap();
+        let mut request = vec![Packet::Byte(cmd as u8)];
+        request.extend(args.iter().map(|v| Packet::Str(v.clone())));
+        let response = client
+            .send_request(&request)
+            .map_err(|v| v.to_string())
+            .and_then(|_| read_response(client, cmd).map_err(|v| v.to_string()));
+        match response {
+            Ok(v) => v,
+            Err(v) => {
+                // connection is broken, reconnect next time
+                *guard = None;
+                Err(unavailable(&self.addr, v))
+            }
+        }
+    }
+
+    /// Refreshes the list of devices
+    fn list_devices(&self, token: Option<&str>) -> Result<Vec<String>, CommandError> {
+        let ids = self
+            .call(token, Commands::ListDevices, &[])?
+            .into_iter()
+            .skip(1)
+            .filter_map(|p| match p {
+                Packet::Str(v) => Some(v),
+                _ => None,
+            })
+            .collect::<Vec<String>>();
+        *self.devices.write().unwrap() = ids.clone();
+        Ok(ids)
+    }
+
+    fn owns(&self, id: &str) -> bool {
+        self.devices.read().unwrap().iter().any(|v| v == id)
+    }
+}
+
+/// Hub backend: presents devices of registered emulators as its own
+pub struct Hub {
+    token: Option<String>,
+    timeout: Duration,
+    emulators: RwLock<Vec<Arc<Emulator>>>,
+}
+
+impl Hub {
+    pub fn new(addrs: Vec<String>) -> Self {
+        Self::with_token(addrs, None)
+    }
+
+    /// Creates hub which authenticates on emulators with the token,
+    /// so management commands are forwarded as privileged
+    pub fn with_token(addrs: Vec<String>, token: Option<String>) -> Self {
+        Self {
+            token,
+            timeout: DEFAULT_TIMEOUT,
+            emulators: RwLock::new(
+                addrs
+                    .into_iter()
+                    .map(|addr| Arc::new(Emulator::new(addr, DEFAULT_TIMEOUT)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Sets limit of connecting to emulators and of waiting for their responses.
+    /// Connections to the emulators registered before are dropped.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self.emulators
+            .get_mut()
+            .unwrap()
+            .iter_mut()
+            .for_each(|e| *e = Arc::new(Emulator::new(e.addr.clone(), timeout)));
+        self
+    }
+
+    /// Registers emulator, it must be available to be registered.
+    /// Registration of the known emulator refreshes its devices.
+    pub fn register(&self, addr: String) -> Result<Vec<String>, CommandError> {
+        let known = self
+            .emulators
+            .read()
+            .unwrap()
+            .iter()
+            .find(|e| e.addr == addr)
+            .cloned();
+        if let Some(emulator) = known {
+            return emulator.list_devices(self.token.as_deref());
+        }
+        let emulator = Arc::new(Emulator::new(addr.clone(), self.timeout));
+        let ids = emulator.list_devices(self.token.as_deref())?;
+        let mut emulators = self.emulators.write().unwrap();
+        // emulator may be registered concurrently
+        if !emulators.iter().any(|e| e.addr == addr) {
+            emulators.push(emulator);
+        }
+        log_info!("Emulator {} is registered with {} devices", addr, ids.len());
+        Ok(ids)
+    }
+
+    /// Addresses of registered emulators
+    pub fn emulators(&self) -> Vec<String> {
+        self.emulators
+            .read()
+            .unwrap()
+            .iter()
+            .map(|e| e.addr.clone())
+            .collect()
+    }
+
+    /// Lists devices of all emulators, the last known devices are listed for
+    /// unavailable emulators
+    pub fn list_devices(&self) -> Vec<String> {
+        let emulators = self.emulators.read().unwrap().clone();
+        // emulators are listed in parallel, so the slow one delays listing by its timeout only
+        let listings: Vec<Vec<String>> = thread::scope(|s| {
+            let handles: Vec<_> = emulators
+                .iter()
+                .map(|e| {
+                    s.spawn(move || match e.list_devices(self.token.as_deref()) {
+                        Ok(v) => v,
+                        Err(v) => {
+                            log_error!("cannot list devices {}", v);
+                            e.devices.read().unwrap().clone()
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let mut devices: Vec<String> = vec![];
+        for ids in listings {
+            ids.into_iter().for_each(|id| {
+                if !devices.contains(&id) {
+                    devices.push(id);
+                }
+            });
+        }
+        devices
+    }
+
+    /// Finds emulator which owns the device
+    fn route(&self, id: &str) -> Result<Arc<Emulator>, CommandError> {
+        let find = || {
+            self.emulators
+                .read()
+                .unwrap()
+                .iter()
+                .find(|e| e.owns(id))
+                .cloned()
+        };
+        if let Some(e) = find() {
+            return Ok(e);
+        }
+        // device may be added after the last listing
+        self.list_devices();
+        find().ok_or_else(|| {
+            CommandError::new(Status::UnknownDevice, format!("device {} not found", id))
+        })
+    }
+}
+
+impl DeviceBackend for Hub {
+    fn execute_cmd(&self, cmd: Commands, args: &[String]) -> CommandResult {
+        match cmd {
+            Commands::ListDevices => {
+                let devices = self.list_devices();
+                let mut response = vec![Packet::Int32(devices.len() as i32)];
+                response.extend(devices.into_iter().map(Packet::Str));
+                Ok(response)
+            }
+            Commands::RegisterEmulator => self
+                .register(arg(args, 0).to_string())
+                .map(|ids| vec![Packet::Int32(ids.len() as i32)]),
+            c if c == Commands::CreateDevice || c.is_connection_command() => {
+                Err(CommandError::new(
+                    Status::UnknownCommand,
+                    format!("{} is not supported by hub", cmd.name()),
+                ))
+            }
+            c => self
+                .route(arg(args, 0))?
+                .call(self.token.as_deref(), c, args),
+        }
+    }
+
+    fn commands(&self) -> Vec<Commands> {
+        let mut commands = vec![Commands::ListDevices, Commands::RegisterEmulator];
+        commands.extend(SOCKET_COMMANDS);
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        time::Instant,
+    };
+
+    use libprotocol::server::TcpServer;
+
+    use super::*;
+    use crate::{catalog::catalog_packets, iotserver::IotServerBuilder, ACSocket, PowerState};
+
+    fn start_emulator(devices: &[ACSocket]) -> SocketAddr {
+        let mut builder = IotServerBuilder::new();
+        builder
+            .set_addr(String::from("127.0.0.1:0"))
+            .add_admin_token(String::from("secret"));
+        devices.iter().for_each(|d| {
+            builder.add_device(d.clone());
+        });
+        let server = builder.build().unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    /// Emulator which serves the only list request and goes offline
+    fn start_flaky_emulator(id: String) -> SocketAddr {
+        let server = TcpServer::bind(String::from("127.0.0.1:0")).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut conn = server.incoming().next().unwrap().unwrap();
+            assert_eq!(
+                conn.recv_request().unwrap(),
+                Packet::Byte(Commands::ListDevices as u8)
+            );
+            conn.send_response_vec(&[
+                Packet::Byte(Status::Ok as u8),
+                Packet::Int32(1),
+                Packet::Str(id),
+            ])
+            .unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn test_hub() {
+        let s1 = ACSocket::new();
+        let s2 = ACSocket::new();
+        let (id1, id2) = (s1.get_id().to_string(), s2.get_id().to_string());
+        let e1 = start_emulator(&[s1]);
+        let e2 = start_emulator(&[s2]);
+        let hub = Hub::with_token(vec![e1.to_string()], Some(String::from("secret")));
+        assert_eq!(hub.list_devices(), vec![id1.clone()]);
+
+        let args = [id2.clone()];
+        assert_eq!(
+            hub.execute_cmd(Commands::PowerOn, &args)
+                .unwrap_err()
+                .status,
+            Status::UnknownDevice
+        );
+        assert_eq!(
+            hub.execute_cmd(Commands::RegisterEmulator, &[e2.to_string()])
+                .unwrap(),
+            vec![Packet::Int32(1)]
+        );
+        assert_eq!(hub.list_devices(), vec![id1, id2.clone()]);
+        hub.execute_cmd(Commands::PowerOn, &args).unwrap();
+        let info = hub.execute_cmd(Commands::DescribeDevice, &args).unwrap();
+        assert_eq!(info[0], Packet::Str(id2));
+        assert_eq!(info[3], Packet::Byte(PowerState::ON as u8));
+        assert!(matches!(
+            hub.execute_cmd(Commands::GetConsumption, &args).unwrap()[..],
+            [Packet::Float32(_)]
+        ));
+        assert_eq!(
+            hub.execute_cmd(Commands::GetCatalog, &args).unwrap(),
+            catalog_packets(SOCKET_COMMANDS)
+        );
+    }
+
+    #[test]
+    fn test_unavailable_emulator() {
+        let hub = Hub::new(vec![]);
+        // nothing listens on the address of the closed listener
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = hub
+            .execute_cmd(Commands::RegisterEmulator, &[addr.to_string()])
+            .unwrap_err();
+        assert_eq!(err.status, Status::UpstreamUnavailable);
+        assert!(hub.emulators().is_empty());
+
+        let flaky = start_flaky_emulator(String::from("flaky"));
+        assert_eq!(
+            hub.register(flaky.to_string()).unwrap(),
+            vec![String::from("flaky")]
+        );
+        let err = hub
+            .execute_cmd(Commands::PowerOn, &[String::from("flaky")])
+            .unwrap_err();
+        assert_eq!(err.status, Status::UpstreamUnavailable);
+        // devices of the offline emulator are still listed
+        assert_eq!(hub.list_devices(), vec![String::from("flaky")]);
+    }
+
+    #[test]
+    fn test_hung_emulator() {
+        let socket = ACSocket::new();
+        let id = socket.get_id().to_string();
+        let good = start_emulator(&[socket]);
+        // connection is accepted by the backlog, but requests are never answered
+        let hung = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut hub = Hub::new(vec![
+            hung.local_addr().unwrap().to_string(),
+            good.to_string(),
+        ]);
+        hub.set_timeout(Duration::from_millis(200));
+
+        let started = Instant::now();
+        assert_eq!(hub.list_devices(), vec![id.clone()]);
+        let args = [id];
+        hub.execute_cmd(Commands::GetStatus, &args).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let err = hub
+            .execute_cmd(Commands::GetStatus, &[String::from("unknown")])
+            .unwrap_err();
+        assert_eq!(err.status, Status::UnknownDevice);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -1,81 +1,992 @@
-use std::{sync::Mutex, thread};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use libprotocol::{error::ConnectError, server::TcpConnection};
+use libprotocol::{
+    error::{BindError, CmdError, ConnectError, RecvError},
+    log_debug, log_error, log_info,
+    server::{TcpConnection, TcpServer},
+    Packet,
+};
 
-use crate::{ACSocket, Commands};
+use crate::{
+    audit::{AuditLog, AuditRecord},
+    auth::{ClientCredential, Identity, Permissions, Role, ADMIN_NAME},
+    backend::{CommandError, DeviceBackend, LocalBackend},
+    catalog::catalog_packets,
+    error::ServerError,
+    metrics::Metrics,
+    session::{Encoding, Session, SessionStore, DEFAULT_SESSION_TTL},
+    storage::DeviceStore,
+    ACSocket, Commands, PowerState, Status,
+};
 
-/// Run IoT server on specified address and with specified devices
-pub fn run_iot_server(addr: String, devs: &Vec<ACSocket>) {
-    match libprotocol::server::TcpServer::bind(addr) {
-        Err(v) => panic!("cannot start server {}", v),
-        Ok(server) => server.incoming().for_each(|item| match item {
+/// Settings of the server which may be changed while it is running
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerSettings {
+    /// Tokens of admins which may manage devices
+    pub admin_tokens: Vec<String>,
+    /// Clients with their own names and roles
+    pub clients: Vec<ClientCredential>,
+    /// Role of the clients which aren't authenticated
+    pub anonymous_role: Role,
+    /// Maximum number of simultaneously served clients, unlimited if None
+    pub max_connections: Option<usize>,
+    /// Client is disconnected if it sends nothing during this period
+    pub idle_timeout: Option<Duration>,
+    /// Time to wait for commands in progress on shutdown, unlimited if None
+    pub drain_timeout: Option<Duration>,
+    /// Sessions of disconnected clients are kept during this period,
+    /// `DEFAULT_SESSION_TTL` if None
+    pub session_ttl: Option<Duration>,
+}
+
+/// State shared between connections of the server
+struct ServerState {
+    backend: Arc<dyn DeviceBackend>,
+    metrics: Arc<Metrics>,
+    settings: RwLock<ServerSettings>,
+    /// Number of currently served connections
+    active: AtomicUsize,
+    /// Server doesn't accept new connections, served ones are being closed
+    stopping: AtomicBool,
+    /// Handles of served connections to close them on shutdown
+    connections: Mutex<HashMap<u64, TcpConnection>>,
+    next_id: AtomicU64,
+    sessions: SessionStore,
+    audit: Option<AuditLog>,
+}
+
+/// Decrements number of active connections when connection is closed
+struct ActiveGuard(Arc<ServerState>, u64);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.connections.lock().unwrap().remove(&self.1);
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// IoT server which serves devices of the backend
+pub struct IotServer {
+    servers: Vec<TcpServer>,
+    state: Arc<ServerState>,
+}
+
+/// Defines builder pattern for IotServer
+#[derive(Default)]
+pub struct IotServerBuilder {
+    addrs: Vec<String>,
+    listeners: Vec<TcpListener>,
+    devices: Vec<ACSocket>,
+    backend: Option<Arc<dyn DeviceBackend>>,
+    metrics: Option<Arc<Metrics>>,
+    settings: ServerSettings,
+    state_file: Option<PathBuf>,
+    audit_file: Option<PathBuf>,
+}
+
+impl IotServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listen on the only address
+    pub fn set_addr(&mut self, addr: String) -> &mut Self {
+        self.addrs = vec![addr];
+        self
+    }
+
+    /// Listen on one more address
+    pub fn add_addr(&mut self, addr: String) -> &mut Self {
+        self.addrs.push(addr);
+        self
+    }
+
+    /// Serve already bound listeners instead of binding addresses,
+    /// e.g. ones passed by systemd socket activation
+    pub fn set_listeners(&mut self, listeners: Vec<TcpListener>) -> &mut Self {
+        self.listeners = listeners;
+        self
+    }
+
+    pub fn add_device(&mut self, d: ACSocket) -> &mut Self {
+        self.devices.push(d);
+        self
+    }
+
+    /// Serve devices of the backend instead of local sockets added with `add_device`
+    pub fn set_backend(&mut self, backend: Arc<dyn DeviceBackend>) -> &mut Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Persist local devices to the file. If the file exists, its devices
+    /// replace devices added with `add_device`.
+    pub fn set_state_file(&mut self, path: PathBuf) -> &mut Self {
+        self.state_file = Some(path);
+        self
+    }
+
+    /// Clients authenticated with this token may create, remove and rename devices
+    pub fn add_admin_token(&mut self, token: String) -> &mut Self {
+        self.settings.admin_tokens.push(token);
+        self
+    }
+
+    /// Client authenticated with the token gets its name and role
+    pub fn add_client(&mut self, client: ClientCredential) -> &mut Self {
+        self.settings.clients.push(client);
+        self
+    }
+
+    pub fn set_anonymous_role(&mut self, role: Role) -> &mut Self {
+        self.settings.anonymous_role = role;
+        self
+    }
+
+    /// Append record of every accepted or denied command to the file
+    pub fn set_audit_log(&mut self, path: PathBuf) -> &mut Self {
+        self.audit_file = Some(path);
+        self
+    }
+
+    pub fn set_max_connections(&mut self, max: usize) -> &mut Self {
+        self.settings.max_connections = Some(max);
+        self
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.settings.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn set_drain_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.settings.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Replace all runtime settings
+    pub fn set_settings(&mut self, settings: ServerSettings) -> &mut Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Use external metrics registry instead of creating a new one
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn build_backend(&self) -> Result<Arc<dyn DeviceBackend>, ServerError> {
+        if let Some(v) = &self.backend {
+            return Ok(v.clone());
+        }
+        match &self.state_file {
+            None => Ok(Arc::new(LocalBackend::new(self.devices.clone()))),
+            Some(path) => {
+                let store = DeviceStore::new(path.clone());
+                let devices = match store.exists() {
+                    true => store.load()?,
+                    false => {
+                        let mut devices = self.devices.clone();
+                        store.save(&mut devices)?;
+                        devices
+                    }
+                };
+                Ok(Arc::new(LocalBackend::with_store(devices, store)))
+            }
+        }
+    }
+
+    /// Restores devices and binds server to configured addresses
+    pub fn build(&self) -> Result<IotServer, ServerError> {
+        let backend = self.build_backend()?;
+        let audit = match &self.audit_file {
+            Some(path) => {
+                Some(AuditLog::open(path).map_err(|v| ServerError::Audit(path.clone(), v))?)
+            }
+            None => None,
+        };
+        let servers = match self.listeners.is_empty() {
+            true => self
+                .addrs
+                .iter()
+                .map(|addr| TcpServer::bind(addr.clone()))
+                .collect::<Result<Vec<TcpServer>, _>>()?,
+            false => self
+                .listeners
+                .iter()
+                .map(|l| Ok(TcpServer::from_listener(l.try_clone()?)))
+                .collect::<Result<Vec<TcpServer>, BindError>>()?,
+        };
+        Ok(IotServer {
+            servers,
+            state: Arc::new(ServerState {
+                backend,
+                metrics: self.metrics.clone().unwrap_or_default(),
+                settings: RwLock::new(self.settings.clone()),
+                active: AtomicUsize::new(0),
+                stopping: AtomicBool::new(false),
+                connections: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+                sessions: SessionStore::new(),
+                audit,
+            }),
+        })
+    }
+}
+
+impl IotServer {
+    /// Address of the first listener
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self.servers.first() {
+            Some(v) => v.local_addr(),
+            None => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.state.metrics.clone()
+    }
+
+    pub fn settings(&self) -> ServerSettings {
+        self.state.settings.read().unwrap().clone()
+    }
+
+    /// Applies new settings, they affect new commands and connections
+    pub fn reload(&self, settings: ServerSettings) {
+        *self.state.settings.write().unwrap() = settings;
+    }
+
+    /// Number of currently served connections
+    pub fn active_connections(&self) -> usize {
+        self.state.active.load(Ordering::Relaxed)
+    }
+
+    /// Stops accepting connections, so `run` returns. Served connections are
+    /// closed after the command in progress.
+    pub fn shutdown(&self) {
+        if self.state.stopping.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        // wake up listeners blocked in accept
+        for server in self.servers.iter() {
+            if let Ok(mut addr) = server.local_addr() {
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(match addr.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                if let Err(v) = TcpStream::connect(addr) {
+                    log_error!("Cannot stop listener {}: {}", addr, v);
+                }
+            }
+        }
+        for connection in self.state.connections.lock().unwrap().values() {
+            let _ = connection.shutdown_read();
+        }
+    }
+
+    /// Waits until all connections are closed, returns false on timeout
+    pub fn wait_idle(&self, timeout: Option<Duration>) -> bool {
+        let started = Instant::now();
+        while self.active_connections() > 0 {
+            if timeout.is_some_and(|t| started.elapsed() >= t) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+
+    /// Accepts connections on all listeners and serves each of them in separate thread.
+    /// Returns after `shutdown`.
+    pub fn run(&self) {
+        thread::scope(|scope| {
+            for server in self.servers.iter() {
+                scope.spawn(|| Self::serve(server, &self.state));
+            }
+        })
+    }
+
+    fn serve(server: &TcpServer, state: &Arc<ServerState>) {
+        for item in server.incoming() {
+            if state.stopping.load(Ordering::Relaxed) {
+                break;
+            }
+            Self::accept(item, state);
+        }
+    }
+
+    fn accept(item: Result<TcpConnection, ConnectError>, state: &Arc<ServerState>) {
+        match item {
             Ok(connection) => {
-                let clone = devs.clone();
-                thread::spawn(move || handle_connection(connection, clone));
+                let settings = state.settings.read().unwrap().clone();
+                let active = state.active.fetch_add(1, Ordering::Relaxed);
+                let id = state.next_id.fetch_add(1, Ordering::Relaxed);
+                let guard = ActiveGuard(state.clone(), id);
+                if settings.max_connections.is_some_and(|max| active >= max) {
+                    state.metrics.connection_rejected();
+                    log_error!("Connection limit is reached, dropping client");
+                    return;
+                }
+                if let Err(v) = connection.set_read_timeout(settings.idle_timeout) {
+                    log_error!("Cannot set idle timeout {}", v);
+                }
+                match connection.try_clone() {
+                    Ok(v) => {
+                        state.connections.lock().unwrap().insert(id, v);
+                    }
+                    Err(v) => log_error!("Cannot register connection {}", v),
+                }
+                // shutdown may happen before the connection is registered
+                if state.stopping.load(Ordering::Relaxed) {
+                    let _ = connection.shutdown_read();
+                }
+                state.metrics.connection_accepted();
+                let state = state.clone();
+                thread::spawn(move || {
+                    let _guard = guard;
+                    match handle_connection(connection, state) {
+                        Err(CmdError::Recv(RecvError::Io(v)))
+                            if v.kind() == ErrorKind::UnexpectedEof =>
+                        {
+                            log_info!("Client disconnected");
+                        }
+                        Err(CmdError::Recv(RecvError::Io(v)))
+                            if matches!(v.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                        {
+                            log_debug!("Idle client disconnected");
+                        }
+                        Err(v) => log_error!("Connection closed with error {}", v),
+                        Ok(_) => (),
+                    }
+                });
+            }
+            Err(ConnectError::BadHandshake(v)) => {
+                state.metrics.handshake_failed();
+                log_error!("Invalid handshake {:?}", v);
             }
             Err(v) => {
-                println!("ERROR: Invalid connection {:?}", v);
+                state.metrics.connection_rejected();
+                log_error!("Invalid connection {:?}", v);
+            }
+        }
+    }
+}
+
+/// Run IoT server on specified address and with specified devices
+pub fn run_iot_server(addr: String, devs: &[ACSocket]) {
+    let mut builder = IotServerBuilder::new();
+    builder.set_addr(addr);
+    devs.iter().for_each(|d| {
+        builder.add_device(d.clone());
+    });
+    match builder.build() {
+        Err(v) => panic!("cannot start server {}", v),
+        Ok(server) => server.run(),
+    }
+}
+
+fn error_response(status: Status, msg: String) -> Vec<Packet> {
+    vec![Packet::Byte(status as u8), Packet::Str(msg)]
+}
+
+/// Session of the connection, anonymous until the client opens one
+struct ClientContext {
+    session: Arc<Mutex<Session>>,
+    token: Option<String>,
+    /// Address of the client for the audit log
+    peer: String,
+}
+
+impl ClientContext {
+    /// Detaches the session, so it expires unless the client reconnects
+    fn detach(&mut self, shared: &ServerState) {
+        if let Some(token) = self.token.take() {
+            shared.sessions.detach(&token);
+        }
+    }
+}
+
+/// Finds client by the token, admin tokens grant admin role
+fn authenticate(settings: &ServerSettings, token: &str) -> Option<Identity> {
+    if let Some(c) = settings.clients.iter().find(|c| c.token == token) {
+        return Some(Identity {
+            name: c.name.clone(),
+            role: c.role,
+        });
+    }
+    settings
+        .admin_tokens
+        .iter()
+        .any(|t| t == token)
+        .then(|| Identity {
+            name: String::from(ADMIN_NAME),
+            role: Role::Admin,
+        })
+}
+
+fn session_ttl(shared: &ServerState) -> Duration {
+    shared
+        .settings
+        .read()
+        .unwrap()
+        .session_ttl
+        .unwrap_or(DEFAULT_SESSION_TTL)
+}
+
+/// Executes commands which change context of the connection
+fn execute_session_cmd(
+    shared: &ServerState,
+    ctx: &mut ClientContext,
+    cmd: Commands,
+    args: &[String],
+) -> Result<Vec<Packet>, CommandError> {
+    match cmd {
+        Commands::Authenticate => {
+            let identity = authenticate(&shared.settings.read().unwrap(), &args[0]);
+            let authenticated = identity.is_some();
+            ctx.session.lock().unwrap().identity = identity;
+            match authenticated {
+                true => Ok(vec![]),
+                false => Err(CommandError::new(
+                    Status::Denied,
+                    String::from("invalid token"),
+                )),
             }
+        }
+        Commands::OpenSession => {
+            let ttl = session_ttl(shared);
+            let session = match args[0].as_str() {
+                // new session starts with the current context
+                "" => {
+                    let session = ctx.session.lock().unwrap().clone();
+                    Arc::new(Mutex::new(session))
+                }
+                token => shared.sessions.resume(token, ttl).ok_or_else(|| {
+                    CommandError::new(
+                        Status::BadRequest,
+                        String::from("unknown or expired session"),
+                    )
+                })?,
+            };
+            ctx.detach(shared);
+            let token = match args[0].as_str() {
+                "" => shared.sessions.open(session.clone(), ttl),
+                token => token.to_string(),
+            };
+            ctx.session = session;
+            ctx.token = Some(token.clone());
+            Ok(vec![Packet::Str(token)])
+        }
+        Commands::SelectDevice => {
+            let device = match args[0].as_str() {
+                "" => None,
+                id => {
+                    // the device must exist when it is selected
+                    shared
+                        .backend
+                        .execute_cmd(Commands::DescribeDevice, &args[..1])?;
+                    Some(id.to_string())
+                }
+            };
+            ctx.session.lock().unwrap().device = device;
+            Ok(vec![])
+        }
+        Commands::SetPreference => ctx
+            .session
+            .lock()
+            .unwrap()
+            .preferences
+            .set(&args[0], &args[1])
+            .map(|_| vec![])
+            .map_err(|v| CommandError::new(Status::BadRequest, v)),
+        c => Err(CommandError::new(
+            Status::BadRequest,
+            format!("{} is not a session command", c.name()),
+        )),
+    }
+}
+
+/// Status of the device in JSON: description, power and energy in preferred units
+fn json_status(shared: &ServerState, session: &Session, args: &[String]) -> Vec<Packet> {
+    let backend = &shared.backend;
+    let info = backend.execute_cmd(Commands::DescribeDevice, args);
+    let power = backend.execute_cmd(Commands::GetConsumption, args);
+    let energy = backend.execute_cmd(Commands::GetEnergy, args);
+    let value = match (info.as_deref(), power.as_deref(), energy.as_deref()) {
+        (
+            Ok([Packet::Str(id), Packet::Str(name), Packet::Str(kind), Packet::Byte(state)]),
+            Ok([Packet::Float32(power)]),
+            Ok([Packet::Float32(energy)]),
+        ) => serde_json::json!({
+            "id": id,
+            "name": name,
+            "type": kind,
+            "power": if *state == PowerState::ON as u8 { "ON" } else { "OFF" },
+            "consumption": session.preferences.power(*power),
+            "energy": session.preferences.energy(*energy),
         }),
+        _ => serde_json::Value::Null,
+    };
+    vec![Packet::Str(value.to_string())]
+}
+
+/// Applies preferences of the session to the payload of the device command
+fn apply_preferences(
+    shared: &ServerState,
+    session: &Session,
+    cmd: Commands,
+    args: &[String],
+    payload: Vec<Packet>,
+) -> Vec<Packet> {
+    let preferences = &session.preferences;
+    match (cmd, payload.as_slice()) {
+        (Commands::GetConsumption, [Packet::Float32(v)]) => {
+            vec![Packet::Float32(preferences.power(*v))]
+        }
+        (Commands::GetEnergy, [Packet::Float32(v)]) => {
+            vec![Packet::Float32(preferences.energy(*v))]
+        }
+        (Commands::GetStatus, _) if preferences.encoding == Encoding::Json => {
+            json_status(shared, session, args)
+        }
+        _ => payload,
+    }
+}
+
+/// Appends record of the command to the audit log if it is enabled
+fn audit(
+    shared: &ServerState,
+    ctx: &ClientContext,
+    session: &Session,
+    role: Role,
+    cmd: Commands,
+    args: &[String],
+    status: Status,
+) {
+    let Some(log) = &shared.audit else {
+        return;
+    };
+    let (client, role) = match &session.identity {
+        Some(v) => (v.name.clone(), v.role),
+        None => (String::from("anonymous"), role),
+    };
+    let device = match cmd.is_device_command() || cmd == Commands::SelectDevice {
+        true => args[0].clone(),
+        false => String::new(),
+    };
+    log.append(&AuditRecord {
+        timestamp_ms: AuditRecord::now(),
+        client,
+        peer: ctx.peer.clone(),
+        role: role.name().to_string(),
+        device,
+        command: cmd.name().to_string(),
+        result: format!("{:?}", status),
+    });
+}
+
+/// Executes command on behalf of the connection
+fn execute_cmd(
+    shared: &ServerState,
+    ctx: &mut ClientContext,
+    cmd: Commands,
+    args: &[String],
+) -> Vec<Packet> {
+    let session = ctx.session.lock().unwrap().clone();
+    let role = match &session.identity {
+        Some(v) => v.role,
+        None => shared.settings.read().unwrap().anonymous_role,
+    };
+    let mut args = args.to_vec();
+    // empty id refers to the selected device, except the catalog of the server
+    if cmd.is_device_command() && cmd != Commands::GetCatalog && args[0].is_empty() {
+        match &session.device {
+            Some(id) => args[0] = id.clone(),
+            None => {
+                let msg = String::from("no device is selected");
+                audit(
+                    shared,
+                    ctx,
+                    &session,
+                    role,
+                    cmd,
+                    &args,
+                    Status::UnknownDevice,
+                );
+                return error_response(Status::UnknownDevice, msg);
+            }
+        }
+    }
+    let result = match cmd {
+        c if c.required_role() > role => Err(CommandError::new(
+            Status::Denied,
+            format!("{} requires {} role", c.name(), c.required_role().name()),
+        )),
+        c if c.is_connection_command() => execute_session_cmd(shared, ctx, c, &args),
+        // catalog of the server, device catalog is provided by the backend
+        Commands::GetCatalog if args[0].is_empty() => {
+            let mut commands = shared.backend.commands();
+            commands.extend(
+                Commands::ALL
+                    .iter()
+                    .filter(|c| c.is_connection_command() || **c == Commands::GetCatalog),
+            );
+            Ok(catalog_packets(&commands))
+        }
+        c => shared
+            .backend
+            .execute_cmd(c, &args)
+            .map(|payload| apply_preferences(shared, &session, c, &args, payload)),
+    };
+    let status = result.as_ref().map_or_else(|e| e.status, |_| Status::Ok);
+    // authentication changes the identity, it is recorded with the new one
+    let session = ctx.session.lock().unwrap().clone();
+    audit(shared, ctx, &session, role, cmd, &args, status);
+    match result {
+        Ok(payload) => {
+            let mut v = vec![Packet::Byte(Status::Ok as u8)];
+            v.extend(payload);
+            v
+        }
+        Err(e) => error_response(e.status, e.msg),
     }
 }
 
-fn handle_connection(
+/// Handles client requests, session of the client is detached on disconnect
+fn handle_connection(connection: TcpConnection, shared: Arc<ServerState>) -> Result<(), CmdError> {
+    let mut ctx = ClientContext {
+        session: Arc::default(),
+        token: None,
+        peer: connection
+            .peer_addr()
+            .map_or_else(|_| String::from("unknown"), |v| v.to_string()),
+    };
+    let result = serve_requests(connection, &shared, &mut ctx);
+    ctx.detach(&shared);
+    result
+}
+
+/// Serves client requests: command byte followed by command arguments
+fn serve_requests(
     mut connection: TcpConnection,
-    devices: Vec<ACSocket>,
-) -> Result<(), libprotocol::error::RecvError> {
+    shared: &ServerState,
+    ctx: &mut ClientContext,
+) -> Result<(), CmdError> {
     enum State {
-        IDLE,
-        READ_ID,
-        HANDLE_CMD,
-        SEND_RESULT,
+        Idle,
+        ReadArgs,
+        HandleCmd,
+        SendResult,
     }
-    let mut state = State::IDLE;
+    let mut state = State::Idle;
     let mut cmd: Option<Commands> = Option::None;
+    let mut args: Vec<String> = vec![];
+    let mut response: Vec<Packet> = vec![];
+    let mut started = Instant::now();
     loop {
         match state {
-            State::IDLE => {
+            State::Idle => {
                 let request = connection.recv_request()?;
-                state = State::READ_ID;
+                started = Instant::now();
+                cmd = Option::None;
+                args.clear();
                 match request {
-                    libprotocol::Packet::Byte(v) => {
-                        match v {
-                            v if v == Commands::GetConsumption as u8 => {
-                                cmd = Option::Some(Commands::GetConsumption);
-                            }
-                            v if v == Commands::GetStatus as u8 => {
-                                cmd = Option::Some(Commands::GetStatus);
-                            }
-                            v if v == Commands::ListDevices as u8 => {
-                                cmd = Option::Some(Commands::ListDevices);
-                                state = State::HANDLE_CMD;
-                            }
-                            v if v == Commands::PowerOn as u8 => {
-                                cmd = Option::Some(Commands::PowerOn);
-                            }
-                            v if v == Commands::PowerOff as u8 => {
-                                cmd = Option::Some(Commands::PowerOff);
-                            }
-                            _ => {
-                                println!("ERROR: Unsupported command {}", v);
-                                state = State::IDLE;
-                            }
-                        };
-                    }
+                    Packet::Byte(v) => match Commands::try_from(v) {
+                        Ok(c) => {
+                            cmd = Option::Some(c);
+                            state = State::ReadArgs;
+                        }
+                        Err(v) => {
+                            log_error!("Unsupported command {}", v);
+                            shared.metrics.decode_error();
+                            response = error_response(
+                                Status::UnknownCommand,
+                                format!("unsupported command {}", v),
+                            );
+                            state = State::SendResult;
+                        }
+                    },
                     _ => {
-                        println!("ERROR: Unsupported package {:?}", request);
+                        log_error!("Unsupported package {:?}", request);
+                        shared.metrics.decode_error();
+                        response = error_response(
+                            Status::BadRequest,
+                            String::from("command code expected"),
+                        );
+                        state = State::SendResult;
                     }
                 }
             }
-            State::READ_ID => {
-                state = State::HANDLE_CMD;
+            State::ReadArgs => match cmd {
+                Some(c) if args.len() < c.args_count() => match connection.recv_request()? {
+                    Packet::Str(v) => args.push(v),
+                    request => {
+                        log_error!("Unexpected argument {:?}", request);
+                        shared.metrics.decode_error();
+                        response =
+                            error_response(Status::BadRequest, String::from("string expected"));
+                        state = State::SendResult;
+                    }
+                },
+                _ => state = State::HandleCmd,
+            },
+            State::HandleCmd => {
+                if let Some(c) = cmd {
+                    response = execute_cmd(shared, ctx, c, &args);
+                    shared.metrics.command_handled(c.name(), started.elapsed());
+                }
+                state = State::SendResult;
             }
-            State::HANDLE_CMD => {
-                state = State::SEND_RESULT;
+            State::SendResult => {
+                connection.send_response_vec(&response)?;
+                state = State::Idle;
             }
-            State::SEND_RESULT => {
-                state = State::IDLE;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libprotocol::{client::TcpClient, error::SendError};
+
+    use super::*;
+    use crate::PowerState;
+
+    /// Waits until the condition holds, the server updates metrics after
+    /// the connection is closed
+    fn wait_for(f: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            if Instant::now() > deadline {
+                return false;
             }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    fn start_server(devices: &[ACSocket]) -> (SocketAddr, Arc<Metrics>) {
+        let mut builder = IotServerBuilder::new();
+        builder
+            .set_addr(String::from("127.0.0.1:0"))
+            .set_anonymous_role(Role::Operator);
+        devices.iter().for_each(|d| {
+            builder.add_device(d.clone());
+        });
+        let server = builder.build().unwrap();
+        let addr = server.local_addr().unwrap();
+        let metrics = server.metrics();
+        thread::spawn(move || server.run());
+        (addr, metrics)
+    }
+
+    #[test]
+    fn test_commands() {
+        let socket = ACSocket::new();
+        let id = socket.get_id().to_string();
+        let (addr, metrics) = start_server(&[socket]);
+        let mut client = TcpClient::connect(addr.to_string()).unwrap();
+
+        client
+            .send_request(&[Packet::Byte(Commands::ListDevices as u8)])
+            .unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+        assert_eq!(client.recv_response().unwrap(), Packet::Int32(1));
+        assert_eq!(client.recv_response().unwrap(), Packet::Str(id.clone()));
+
+        client
+            .send_request(&[
+                Packet::Byte(Commands::PowerOn as u8),
+                Packet::Str(id.clone()),
+            ])
+            .unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+
+        client
+            .send_request(&[Packet::Byte(Commands::GetStatus as u8), Packet::Str(id)])
+            .unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+        match client.recv_response().unwrap() {
+            Packet::Str(v) => assert!(v.contains("ON")),
+            v => panic!("unexpected status {:?}", v),
         }
+
+        client
+            .send_request(&[
+                Packet::Byte(Commands::PowerOff as u8),
+                Packet::Str(String::new()),
+            ])
+            .unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::UnknownDevice as u8)
+        );
+        assert!(matches!(client.recv_response().unwrap(), Packet::Str(_)));
+
+        client.send_request(&[Packet::Byte(123)]).unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::UnknownCommand as u8)
+        );
+        assert!(matches!(client.recv_response().unwrap(), Packet::Str(_)));
+
+        assert_eq!(metrics.get_connections_accepted(), 1);
+        assert_eq!(metrics.get_commands("list_devices"), 1);
+        assert_eq!(metrics.get_commands("power_on"), 1);
+        assert_eq!(metrics.get_commands("power_off"), 1);
+        assert_eq!(metrics.get_decode_errors(), 1);
+    }
+
+    #[test]
+    fn test_state_file() {
+        let path = std::env::temp_dir().join(format!("state-{}.json", xid::new()));
+        let socket = ACSocket::new();
+        let id = socket.get_id().to_string();
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .set_state_file(path.clone())
+            .add_device(socket)
+            .build()
+            .unwrap();
+        let args = [id.clone()];
+        server
+            .state
+            .backend
+            .execute_cmd(Commands::PowerOn, &args)
+            .unwrap();
+
+        // devices added to builder are ignored, saved ones are restored
+        let restarted = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .set_state_file(path.clone())
+            .add_device(ACSocket::new())
+            .build()
+            .unwrap();
+        let info = restarted
+            .state
+            .backend
+            .execute_cmd(Commands::DescribeDevice, &args)
+            .unwrap();
+        assert_eq!(info[0], Packet::Str(id));
+        assert_eq!(info[3], Packet::Byte(PowerState::ON as u8));
+        let list = restarted
+            .state
+            .backend
+            .execute_cmd(Commands::ListDevices, &[])
+            .unwrap();
+        assert_eq!(list[0], Packet::Int32(1));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let server = IotServerBuilder::new()
+            .set_addr(String::from("127.0.0.1:0"))
+            .set_max_connections(1)
+            .set_idle_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let metrics = server.metrics();
+        thread::spawn(move || server.run());
+
+        let mut first = TcpClient::connect(addr.to_string()).unwrap();
+        first
+            .send_request(&[Packet::Byte(Commands::ListDevices as u8)])
+            .unwrap();
+        assert_eq!(
+            first.recv_response().unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+        assert_eq!(first.recv_response().unwrap(), Packet::Int32(0));
+
+        // second client passes handshake, but it is dropped by the server
+        let mut second = TcpClient::connect(addr.to_string()).unwrap();
+        assert!(second
+            .send_request(&[Packet::Byte(Commands::ListDevices as u8)])
+            .and_then(|_| second
+                .recv_response()
+                .map_err(|_| SendError::UnexpectedPacket))
+            .is_err());
+        assert!(wait_for(|| metrics.get_connections_rejected() == 1));
+
+        // idle client is disconnected, so new client is accepted
+        thread::sleep(Duration::from_millis(400));
+        assert!(first.recv_response().is_err());
+        let mut third = TcpClient::connect(addr.to_string()).unwrap();
+        third
+            .send_request(&[Packet::Byte(Commands::ListDevices as u8)])
+            .unwrap();
+        assert_eq!(
+            third.recv_response().unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Arc::new(
+            IotServerBuilder::new()
+                .set_addr(String::from("invalid address is not bound"))
+                .set_listeners(vec![listener])
+                .build()
+                .unwrap(),
+        );
+        let runner = {
+            let server = server.clone();
+            thread::spawn(move || server.run())
+        };
+        let mut client = TcpClient::connect(addr.to_string()).unwrap();
+        client
+            .send_request(&[Packet::Byte(Commands::ListDevices as u8)])
+            .unwrap();
+        assert_eq!(
+            client.recv_response().unwrap(),
+            Packet::Byte(Status::Ok as u8)
+        );
+        assert_eq!(client.recv_response().unwrap(), Packet::Int32(0));
+        assert_eq!(server.active_connections(), 1);
+
+        server.shutdown();
+        runner.join().unwrap();
+        // idle client is disconnected
+        assert!(server.wait_idle(Some(Duration::from_secs(5))));
+        assert!(client.recv_response().is_err());
+    }
+
+    #[test]
+    fn test_bad_handshake() {
+        let (addr, metrics) = start_server(&[]);
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        libprotocol::write_packet(&mut stream, Packet::Byte(1)).unwrap();
+        // server drops connection after failed handshake
+        assert!(libprotocol::read_packet(&mut stream).is_err());
+        assert!(wait_for(|| metrics.get_handshake_failures() == 1));
+        assert_eq!(metrics.get_connections_rejected(), 1);
     }
 }
@@ -1,27 +1,37 @@
-use rand::Rng;
+use std::time::Instant;
 
+use energy::{EnergyMeter, LoadModel};
+
+pub mod audit;
+pub mod auth;
+pub mod backend;
+pub mod catalog;
+pub mod config;
+pub mod discovery;
+pub mod energy;
+pub mod error;
+pub mod hub;
 pub mod iotserver;
+pub mod metrics;
+pub mod session;
+pub mod storage;
+#[cfg(unix)]
+pub mod systemd;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum PowerState {
-    OFF,
-    ON,
-}
+pub use libprotocol::commands::{Commands, DeviceInfo, PowerState, Status, SOCKET_KIND};
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy)]
-pub enum Commands {
-    PowerOn = 1,
-    PowerOff = 2,
-    GetStatus = 3,
-    GetConsumption = 4,
-    ListDevices = 5,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct ACSocket {
     state: PowerState,
     id: xid::Id,
+    name: String,
+    meter: EnergyMeter,
+}
+
+impl Default for ACSocket {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ACSocket {
@@ -29,28 +39,101 @@ impl ACSocket {
         Self {
             state: PowerState::OFF,
             id: xid::new(),
+            name: String::default(),
+            meter: EnergyMeter::default(),
         }
     }
 
+    pub fn with_name(name: String) -> Self {
+        Self {
+            name,
+            ..Self::new()
+        }
+    }
+
+    /// Restores previously saved socket
+    pub fn restore(id: xid::Id, name: String, state: PowerState) -> Self {
+        let mut socket = Self {
+            id,
+            name,
+            ..Self::new()
+        };
+        socket.switch(state);
+        socket
+    }
+
     pub fn get_id(&self) -> xid::Id {
         self.id
     }
 
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn set_name(&mut self, name: String) -> &mut Self {
+        self.name = name;
+        self
+    }
+
+    pub fn get_info(&self) -> DeviceInfo {
+        DeviceInfo {
+            id: self.id.to_string(),
+            name: self.name.clone(),
+            kind: String::from(SOCKET_KIND),
+            power: self.state,
+        }
+    }
+
+    pub fn get_power_state(&self) -> PowerState {
+        self.state
+    }
+
     pub fn switch(&mut self, state: PowerState) -> &mut Self {
+        match state {
+            PowerState::ON => self.meter.switch_on(Instant::now()),
+            PowerState::OFF => self.meter.switch_off(Instant::now()),
+        }
         self.state = state;
         self
     }
 
+    pub fn get_load_model(&self) -> &LoadModel {
+        self.meter.get_model()
+    }
+
+    pub fn set_load_model(&mut self, model: LoadModel) -> &mut Self {
+        self.meter.set_model(model, Instant::now());
+        self
+    }
+
+    /// Current power in watts, zero when socket is off
     pub fn get_consumption(&self) -> f32 {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(0.1..100.0)
+        self.meter.power(Instant::now())
+    }
+
+    /// Energy in kWh consumed since the last reset of the meter
+    pub fn get_energy(&mut self) -> f64 {
+        self.meter.energy_kwh(Instant::now())
+    }
+
+    pub fn reset_energy(&mut self) -> &mut Self {
+        self.meter.reset(Instant::now());
+        self
+    }
+
+    /// Restores saved reading of the meter
+    pub fn set_energy(&mut self, kwh: f64) -> &mut Self {
+        self.meter.set_energy_kwh(kwh);
+        self
     }
 
-    pub fn get_state(&self) -> String {
+    pub fn get_state(&mut self) -> String {
+        let energy = self.get_energy();
         format!(
-            "power = {:?}, consumption = {}",
+            "power = {:?}, consumption = {}, energy = {}",
             self.state,
-            self.get_consumption()
+            self.get_consumption(),
+            energy
         )
     }
 }
@@ -0,0 +1,277 @@
+//! Runtime metrics of the IoT server, exposed in Prometheus text format
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use libprotocol::{log_error, log_info};
+
+/// Upper bounds (in seconds) of the command latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.1, 1.0,
+];
+
+/// Histogram with fixed buckets
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|b| value <= *b) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Counters and histograms of the running server
+#[derive(Debug, Default)]
+pub struct Metrics {
+    connections_accepted: AtomicU64,
+    connections_rejected: AtomicU64,
+    handshake_failures: AtomicU64,
+    decode_errors: AtomicU64,
+    commands: Mutex<BTreeMap<&'static str, u64>>,
+    latency: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connection_accepted(&self) {
+        self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_rejected(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Failed handshake also counts as rejected connection
+    pub fn handshake_failed(&self) {
+        self.handshake_failures.fetch_add(1, Ordering::Relaxed);
+        self.connection_rejected();
+    }
+
+    pub fn decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Registers handled command and its latency
+    pub fn command_handled(&self, command: &'static str, latency: Duration) {
+        *self.commands.lock().unwrap().entry(command).or_default() += 1;
+        self.latency
+            .lock()
+            .unwrap()
+            .entry(command)
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn get_connections_accepted(&self) -> u64 {
+        self.connections_accepted.load(Ordering::Relaxed)
+    }
+
+    pub fn get_connections_rejected(&self) -> u64 {
+        self.connections_rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn get_handshake_failures(&self) -> u64 {
+        self.handshake_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn get_decode_errors(&self) -> u64 {
+        self.decode_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn get_commands(&self, command: &str) -> u64 {
+        *self.commands.lock().unwrap().get(command).unwrap_or(&0)
+    }
+
+    /// Renders metrics in Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "iot_connections_accepted_total",
+                "Number of accepted client connections",
+                self.get_connections_accepted(),
+            ),
+            (
+                "iot_connections_rejected_total",
+                "Number of rejected client connections",
+                self.get_connections_rejected(),
+            ),
+            (
+                "iot_handshake_failures_total",
+                "Number of failed handshakes",
+                self.get_handshake_failures(),
+            ),
+            (
+                "iot_decode_errors_total",
+                "Number of malformed or unsupported requests",
+                self.get_decode_errors(),
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP iot_commands_total Number of handled commands by type"
+        );
+        let _ = writeln!(out, "# TYPE iot_commands_total counter");
+        for (cmd, value) in self.commands.lock().unwrap().iter() {
+            let _ = writeln!(out, "iot_commands_total{{command=\"{}\"}} {}", cmd, value);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP iot_command_duration_seconds Command handling latency"
+        );
+        let _ = writeln!(out, "# TYPE iot_command_duration_seconds histogram");
+        for (cmd, h) in self.latency.lock().unwrap().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(h.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "iot_command_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                    cmd, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "iot_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+                cmd, h.count
+            );
+            let _ = writeln!(
+                out,
+                "iot_command_duration_seconds_sum{{command=\"{}\"}} {}",
+                cmd, h.sum
+            );
+            let _ = writeln!(
+                out,
+                "iot_command_duration_seconds_count{{command=\"{}\"}} {}",
+                cmd, h.count
+            );
+        }
+        out
+    }
+}
+
+/// Starts HTTP listener which serves metrics on `GET /metrics`.
+/// Returns the bound address, listener runs in background thread.
+pub fn start_metrics_endpoint(addr: String, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    log_info!("Serving metrics on http://{}/metrics", local);
+    thread::spawn(move || {
+        listener.incoming().for_each(|stream| match stream {
+            Ok(stream) => {
+                if let Err(v) = serve_http(stream, &metrics) {
+                    log_error!("metrics request failed {:?}", v);
+                }
+            }
+            Err(v) => log_error!("metrics connection error {:?}", v),
+        })
+    });
+    Ok(local)
+}
+
+fn serve_http(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip headers, we don't need them
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::from("not found\n")),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.connection_accepted();
+        metrics.handshake_failed();
+        metrics.decode_error();
+        metrics.command_handled("power_on", Duration::from_micros(300));
+        metrics.command_handled("power_on", Duration::from_secs(2));
+
+        let text = metrics.render();
+        assert!(text.contains("iot_connections_accepted_total 1\n"));
+        assert!(text.contains("iot_connections_rejected_total 1\n"));
+        assert!(text.contains("iot_handshake_failures_total 1\n"));
+        assert!(text.contains("iot_decode_errors_total 1\n"));
+        assert!(text.contains("iot_commands_total{command=\"power_on\"} 2\n"));
+        assert!(text.contains(
+            "iot_command_duration_seconds_bucket{command=\"power_on\",le=\"0.00025\"} 0\n"
+        ));
+        assert!(text.contains(
+            "iot_command_duration_seconds_bucket{command=\"power_on\",le=\"0.0005\"} 1\n"
+        ));
+        assert!(text
+            .contains("iot_command_duration_seconds_bucket{command=\"power_on\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("iot_command_duration_seconds_count{command=\"power_on\"} 2\n"));
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.connection_accepted();
+        let addr = start_metrics_endpoint(String::from("127.0.0.1:0"), metrics).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("iot_connections_accepted_total 1"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Server-side sessions of the clients.
+//!
+//! Session keeps context of the connection: identity, selected device and
+//! preferences. Client which opened session gets the token, reconnecting client
+//! resumes the session by the token until it expires.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use crate::auth::Identity;
+
+/// Time during which detached session may be resumed
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(300);
+
+/// Unit of the power returned by `GetConsumption`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PowerUnit {
+    #[default]
+    W,
+    KW,
+}
+
+/// Unit of the energy returned by `GetEnergy`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnergyUnit {
+    #[default]
+    KWh,
+    Wh,
+}
+
+/// Encoding of the status returned by `GetStatus`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Preferences of the client applied to the responses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preferences {
+    pub power_unit: PowerUnit,
+    pub energy_unit: EnergyUnit,
+    pub encoding: Encoding,
+}
+
+impl Preferences {
+    /// Sets preference by name, fails on unknown name or value
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match (key, value.to_lowercase().as_str()) {
+            ("power_unit", "w") => self.power_unit = PowerUnit::W,
+            ("power_unit", "kw") => self.power_unit = PowerUnit::KW,
+            ("energy_unit", "kwh") => self.energy_unit = EnergyUnit::KWh,
+            ("energy_unit", "wh") => self.energy_unit = EnergyUnit::Wh,
+            ("encoding", "text") => self.encoding = Encoding::Text,
+            ("encoding", "json") => self.encoding = Encoding::Json,
+            ("power_unit" | "energy_unit" | "encoding", _) => {
+                return Err(format!("invalid {} '{}'", key, value))
+            }
+            _ => return Err(format!("unknown preference '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Converts power in watts to the preferred unit
+    pub fn power(&self, watts: f32) -> f32 {
+        match self.power_unit {
+            PowerUnit::W => watts,
+            PowerUnit::KW => watts / 1000.0,
+        }
+    }
+
+    /// Converts energy in kWh to the preferred unit
+    pub fn energy(&self, kwh: f32) -> f32 {
+        match self.energy_unit {
+            EnergyUnit::KWh => kwh,
+            EnergyUnit::Wh => kwh * 1000.0,
+        }
+    }
+}
+
+/// Context of the client
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    /// Authenticated client, anonymous if None
+    pub identity: Option<Identity>,
+    /// Device used by device commands with empty id
+    pub device: Option<String>,
+    pub preferences: Preferences,
+}
+
+struct Entry {
+    session: Arc<Mutex<Session>>,
+    /// Number of connections using the session
+    attached: usize,
+    detached_at: Instant,
+}
+
+/// Sessions of the server by token
+#[derive(Default)]
+pub struct SessionStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+/// Random token, it authorizes the holder to resume the session
+fn new_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..16)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn purge(entries: &mut HashMap<String, Entry>, ttl: Duration) {
+        entries.retain(|_, e| e.attached > 0 || e.detached_at.elapsed() < ttl);
+    }
+
+    /// Stores session attached to the calling connection, returns its token
+    pub fn open(&self, session: Arc<Mutex<Session>>, ttl: Duration) -> String {
+        let mut entries = self.entries.lock().unwrap();
+        Self::purge(&mut entries, ttl);
+        let token = new_token();
+        entries.insert(
+            token.clone(),
+            Entry {
+                session,
+                attached: 1,
+                detached_at: Instant::now(),
+            },
+        );
+        token
+    }
+
+    /// Attaches session to the calling connection, None if it is unknown or expired
+    pub fn resume(&self, token: &str, ttl: Duration) -> Option<Arc<Mutex<Session>>> {
+        let mut entries = self.entries.lock().unwrap();
+        Self::purge(&mut entries, ttl);
+        entries.get_mut(token).map(|e| {
+            e.attached += 1;
+            e.session.clone()
+        })
+    }
+
+    /// Detaches session from the closed connection, its TTL starts
+    pub fn detach(&self, token: &str) {
+        if let Some(e) = self.entries.lock().unwrap().get_mut(token) {
+            e.attached = e.attached.saturating_sub(1);
+            e.detached_at = Instant::now();
+        }
+    }
+
+    /// Number of stored sessions including expired ones which aren't purged yet
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferences() {
+        let mut p = Preferences::default();
+        assert_eq!(p.power(1500.0), 1500.0);
+        p.set("power_unit", "kW").unwrap();
+        p.set("energy_unit", "Wh").unwrap();
+        p.set("encoding", "json").unwrap();
+        assert_eq!(p.power(1500.0), 1.5);
+        assert_eq!(p.energy(1.5), 1500.0);
+        assert_eq!(p.encoding, Encoding::Json);
+        assert_eq!(
+            p.set("power_unit", "hp").unwrap_err(),
+            "invalid power_unit 'hp'"
+        );
+        assert_eq!(
+            p.set("color", "red").unwrap_err(),
+            "unknown preference 'color'"
+        );
+    }
+
+    #[test]
+    fn test_store() {
+        let store = SessionStore::new();
+        let ttl = Duration::from_millis(50);
+        let session = Arc::new(Mutex::new(Session {
+            device: Some(String::from("kettle")),
+            ..Session::default()
+        }));
+        let token = store.open(session, ttl);
+        assert_eq!(token.len(), 32);
+        let other = store.open(Arc::default(), ttl);
+        assert_ne!(other, token);
+        store.detach(&other);
+
+        // attached session doesn't expire, detached one is purged
+        std::thread::sleep(ttl);
+        let resumed = store.resume(&token, ttl).unwrap();
+        assert_eq!(resumed.lock().unwrap().device.as_deref(), Some("kettle"));
+        assert_eq!(store.len(), 1);
+        store.detach(&token);
+        store.detach(&token);
+        assert!(store.resume(&token, ttl).is_some());
+        store.detach(&token);
+        assert!(store.resume("unknown", ttl).is_none());
+
+        std::thread::sleep(ttl);
+        assert!(store.resume(&token, ttl).is_none());
+        assert!(store.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Persistent storage of the device inventory and power states
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{energy::LoadModel, ACSocket, PowerState, SOCKET_KIND};
+
+/// Storage error
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid state file: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("invalid device id {0}")]
+    InvalidId(String),
+    #[error("unsupported device type {0}")]
+    UnsupportedKind(String),
+    #[error("invalid load model of device {0}: {1}")]
+    InvalidLoad(String, String),
+}
+
+/// Saved state of the device
+#[derive(Debug, Serialize, Deserialize)]
+struct DeviceRecord {
+    id: String,
+    name: String,
+    kind: String,
+    power: bool,
+    #[serde(default)]
+    load: LoadModel,
+    /// Reading of the energy meter, kWh
+    #[serde(default)]
+    energy: f64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    devices: Vec<DeviceRecord>,
+}
+
+/// Stores devices in JSON file. File is replaced atomically on every save,
+/// so it is either old or new state after crash.
+#[derive(Debug, Clone)]
+pub struct DeviceStore {
+    path: PathBuf,
+}
+
+impl DeviceStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Loads devices with their ids, names, power states and energy meters
+    pub fn load(&self) -> Result<Vec<ACSocket>, StorageError> {
+        let state: StateFile = serde_json::from_slice(&fs::read(&self.path)?)?;
+        state
+            .devices
+            .into_iter()
+            .map(|r| {
+                if r.kind != SOCKET_KIND {
+                    return Err(StorageError::UnsupportedKind(r.kind));
+                }
+                let id = xid::Id::from_str(&r.id).map_err(|_| StorageError::InvalidId(r.id))?;
+                r.load
+                    .validate()
+                    .map_err(|v| StorageError::InvalidLoad(id.to_string(), v))?;
+                let power = match r.power {
+                    true => PowerState::ON,
+                    false => PowerState::OFF,
+                };
+                let mut socket = ACSocket::restore(id, r.name, power);
+                socket.set_load_model(r.load).set_energy(r.energy);
+                Ok(socket)
+            })
+            .collect()
+    }
+
+    /// Saves devices: writes temporary file next to the target and renames it
+    pub fn save(&self, devices: &mut [ACSocket]) -> Result<(), StorageError> {
+        let state = StateFile {
+            devices: devices
+                .iter_mut()
+                .map(|d| DeviceRecord {
+                    id: d.get_id().to_string(),
+                    name: d.get_name(),
+                    kind: String::from(SOCKET_KIND),
+                    power: d.get_power_state() == PowerState::ON,
+                    load: d.get_load_model().clone(),
+                    energy: d.get_energy(),
+                })
+                .collect(),
+        };
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            let mut file = fs::File::create(&tmp)?;
+            file.write_all(&serde_json::to_vec_pretty(&state)?)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("devices-{}.json", xid::new()));
+        let store = DeviceStore::new(path.clone());
+        assert!(!store.exists());
+
+        let mut s1 = ACSocket::with_name(String::from("kettle"));
+        s1.switch(PowerState::ON)
+            .set_load_model(LoadModel::Constant { watts: 2000.0 })
+            .set_energy(1.5);
+        let s2 = ACSocket::new();
+        store.save(&mut [s1.clone(), s2.clone()]).unwrap();
+        assert!(store.exists());
+        let mut loaded = store.load().unwrap();
+        assert_eq!(
+            loaded.iter().map(|d| d.get_info()).collect::<Vec<_>>(),
+            vec![s1.get_info(), s2.get_info()]
+        );
+        assert_eq!(loaded[0].get_load_model(), s1.get_load_model());
+        assert!(loaded[0].get_energy() >= 1.5);
+        assert_eq!(loaded[1].get_energy(), 0.0);
+
+        fs::write(&path, "{\"devices\": [{\"id\": \"bad\", \"name\": \"\", \"kind\": \"socket\", \"power\": false}]}").unwrap();
+        assert!(matches!(store.load(), Err(StorageError::InvalidId(_))));
+
+        for load in [
+            "{\"type\": \"scheduled\", \"steps\": []}",
+            "{\"type\": \"random\", \"seed\": 1, \"min_watts\": 1.0, \"max_watts\": 2.0, \"interval_secs\": 0}",
+        ] {
+            let state = format!(
+                "{{\"devices\": [{{\"id\": \"{}\", \"name\": \"\", \"kind\": \"socket\", \"power\": true, \"load\": {}}}]}}",
+                xid::new(),
+                load
+            );
+            fs::write(&path, state).unwrap();
+            assert!(matches!(store.load(), Err(StorageError::InvalidLoad(_, _))));
+        }
+        fs::remove_file(path).unwrap();
+    }
+}
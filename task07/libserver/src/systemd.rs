@@ -0,0 +1,138 @@
+//! Integration with systemd: socket activation and readiness notification.
+//! Both are no-op when the server is not started by systemd.
+
+use std::{
+    env, io,
+    net::TcpListener,
+    os::{
+        fd::{FromRawFd, RawFd},
+        unix::net::UnixDatagram,
+    },
+};
+
+use libprotocol::log_error;
+
+/// The first descriptor passed by systemd
+const LISTEN_FDS_START: i32 = 3;
+
+/// Number of passed descriptors if they are addressed to the process with the pid
+fn passed_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> io::Result<i32> {
+    match (listen_pid, listen_fds) {
+        (Some(listen_pid), Some(listen_fds)) if listen_pid.parse() == Ok(pid) => {
+            match listen_fds.parse() {
+                Ok(n) if n >= 0 => Ok(n),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid LISTEN_FDS {}", listen_fds),
+                )),
+            }
+        }
+        _ => Ok(0),
+    }
+}
+
+/// Takes TCP listeners of the descriptors, other descriptors are closed and skipped
+fn take_listeners(fds: impl Iterator<Item = RawFd>) -> Vec<TcpListener> {
+    fds.filter_map(|fd| {
+        // systemd passes the descriptors to this process, they aren't owned by anything else
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        // fails if the descriptor is not a TCP socket
+        match listener.local_addr() {
+            Ok(_) => Some(listener),
+            Err(v) => {
+                log_error!(
+                    "Skip passed descriptor {} which is not a TCP socket: {}",
+                    fd,
+                    v
+                );
+                None
+            }
+        }
+    })
+    .collect()
+}
+
+/// Takes listening sockets passed through `LISTEN_PID` and `LISTEN_FDS`.
+/// Variables are removed, so the sockets are not taken twice or inherited by children.
+/// Must be called at start, before other threads are spawned.
+pub fn listen_fds() -> io::Result<Vec<TcpListener>> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fds = env::var("LISTEN_FDS").ok();
+    let count = passed_fds(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        std::process::id(),
+    )?;
+    if listen_pid.is_some() {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+    Ok(take_listeners(LISTEN_FDS_START..LISTEN_FDS_START + count))
+}
+
+fn notify_socket(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.connect_addr(&addr)?;
+        }
+        _ => socket.connect(path)?,
+    }
+    Ok(socket)
+}
+
+/// Sends state to the service manager, e.g. `READY=1`.
+/// Returns false if the process is not started with `Type=notify`.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match env::var("NOTIFY_SOCKET") {
+        Ok(path) => {
+            notify_socket(&path)?.send(state.as_bytes())?;
+            Ok(true)
+        }
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passed_fds() {
+        assert_eq!(passed_fds(None, None, 10).unwrap(), 0);
+        assert_eq!(passed_fds(Some("10"), Some("2"), 10).unwrap(), 2);
+        // descriptors are passed to other process
+        assert_eq!(passed_fds(Some("11"), Some("2"), 10).unwrap(), 0);
+        assert!(passed_fds(Some("10"), Some("x"), 10).is_err());
+    }
+
+    #[test]
+    fn test_take_listeners() {
+        use std::os::fd::IntoRawFd;
+
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let (first, _) = UnixDatagram::pair().unwrap();
+        let listeners = take_listeners([first.into_raw_fd(), tcp.into_raw_fd()].into_iter());
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_notify_socket() {
+        let path = env::temp_dir().join(format!("notify-{}.sock", xid::new()));
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        notify_socket(path.to_str().unwrap())
+            .unwrap()
+            .send(b"READY=1")
+            .unwrap();
+        let mut buf = [0; 16];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_file(path).unwrap();
+    }
+}